@@ -0,0 +1,124 @@
+//! 批量位运算与逐位函数的性能对比
+//!
+//! 运行：cargo bench -p bitwise
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use bitwise::bitwise::{get_bit, set_bit};
+use bitwise::bulk::{and_into, any_set, find_first_set, or_into, popcount_slice, scalar};
+
+const WORDS: usize = 1 << 16;
+const ROUNDS: u32 = 20;
+
+fn pseudo_random_words(seed: u64, len: usize) -> Vec<u64> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        })
+        .collect()
+}
+
+/// 运行 ROUNDS 次，返回单次的平均耗时
+fn bench<F: FnMut()>(name: &str, mut f: F) -> Duration {
+    // 预热一次
+    f();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+    println!("{name:<32} {elapsed:>12?}");
+    elapsed
+}
+
+fn main() {
+    let a = pseudo_random_words(1, WORDS);
+    let b = pseudo_random_words(2, WORDS);
+    println!("{WORDS} words per operation, average of {ROUNDS} rounds\n");
+
+    let per_bit = bench("or: set_bit per bit", || {
+        let mut dst = a.clone();
+        for (d, s) in dst.iter_mut().zip(&b) {
+            for position in 1..=64 {
+                if get_bit(*s, position) == 1 {
+                    *d = set_bit(*d, position, true);
+                }
+            }
+        }
+        black_box(dst);
+    });
+    let scalar_or = bench("or: scalar::or_into", || {
+        let mut dst = a.clone();
+        scalar::or_into(&mut dst, black_box(&b));
+        black_box(dst);
+    });
+    let bulk_or = bench("or: or_into", || {
+        let mut dst = a.clone();
+        or_into(&mut dst, black_box(&b));
+        black_box(dst);
+    });
+    println!("  speedup {:.1}x (scalar {:.1}x)\n", ratio(per_bit, bulk_or), ratio(per_bit, scalar_or));
+
+    let per_bit = bench("and: set_bit per bit", || {
+        let mut dst = a.clone();
+        for (d, s) in dst.iter_mut().zip(&b) {
+            for position in 1..=64 {
+                if get_bit(*s, position) == 0 {
+                    *d = set_bit(*d, position, false);
+                }
+            }
+        }
+        black_box(dst);
+    });
+    let bulk_and = bench("and: and_into", || {
+        let mut dst = a.clone();
+        and_into(&mut dst, black_box(&b));
+        black_box(dst);
+    });
+    println!("  speedup {:.1}x\n", ratio(per_bit, bulk_and));
+
+    let per_bit = bench("popcount: get_bit per bit", || {
+        let mut count = 0u64;
+        for word in black_box(&a) {
+            for position in 1..=64 {
+                count += get_bit(*word, position) as u64;
+            }
+        }
+        black_box(count);
+    });
+    let scalar_popcount = bench("popcount: scalar::popcount_slice", || {
+        black_box(scalar::popcount_slice(black_box(&a)));
+    });
+    let bulk_popcount = bench("popcount: popcount_slice", || {
+        black_box(popcount_slice(black_box(&a)));
+    });
+    println!(
+        "  speedup {:.1}x (scalar {:.1}x)\n",
+        ratio(per_bit, bulk_popcount),
+        ratio(per_bit, scalar_popcount)
+    );
+
+    // 只有最后一个字有值，需要扫描整个切片
+    let mut sparse = vec![0u64; WORDS];
+    sparse[WORDS - 1] = 1 << 7;
+    let per_bit = bench("find: get_bit per bit", || {
+        let found = (0..WORDS * 64).find(|i| get_bit(sparse[i / 64], (i % 64 + 1) as i32) == 1);
+        black_box(found);
+    });
+    let bulk_find = bench("find: find_first_set", || {
+        black_box(find_first_set(black_box(&sparse)));
+    });
+    bench("find: any_set", || {
+        black_box(any_set(black_box(&sparse)));
+    });
+    println!("  speedup {:.1}x", ratio(per_bit, bulk_find));
+}
+
+fn ratio(slow: Duration, fast: Duration) -> f64 {
+    slow.as_secs_f64() / fast.as_secs_f64().max(f64::EPSILON)
+}
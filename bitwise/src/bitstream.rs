@@ -0,0 +1,311 @@
+//! 按位读写字节流
+//!
+//! [`BitWriter`] 把任意宽度（0 ~ 64 位）的无符号数、布尔值和 zig-zag 编码的有符号数紧凑地写入 `Vec<u8>`，
+//! [`BitReader`] 以同样的宽度和位序从 `&[u8]` 中读回。读写双方必须使用相同的 [`BitOrder`]。
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// 位序，决定一个值的各个位以什么顺序落到字节里
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    /// 先写值的最高位，并从每个字节的最高位开始填充，即常见的网络协议位序
+    MsbFirst,
+    /// 先写值的最低位，并从每个字节的最低位开始填充，例如 DEFLATE
+    LsbFirst,
+}
+
+/// 低 n 位全为 1 的掩码，n 不超过 8
+fn low_mask(n: usize) -> u8 {
+    ((1u16 << n) - 1) as u8
+}
+
+/// zig-zag 编码，把绝对值小的有符号数映射为小的无符号数：0, -1, 1, -2 => 0, 1, 2, 3
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn zigzag_decode(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// 按位写入器
+#[derive(Debug, Clone)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    order: BitOrder,
+    /// 已经写入的位数
+    bit_len: usize,
+}
+
+impl BitWriter {
+    pub fn new(order: BitOrder) -> Self {
+        Self::from_vec(Vec::new(), order)
+    }
+
+    /// 在已有字节之后继续写入，已有内容视为按字节对齐
+    pub fn from_vec(bytes: Vec<u8>, order: BitOrder) -> Self {
+        let bit_len = bytes.len() * 8;
+        BitWriter { bytes, order, bit_len }
+    }
+
+    /// 写入 value 的低 width 位
+    ///
+    /// width 超过 64，或者 value 无法用 width 位表示时会 panic，以免静默截断数据。
+    pub fn write_bits(&mut self, value: u64, width: u32) {
+        assert!(width <= 64, "bit width {width} is larger than 64");
+        assert!(width == 64 || value >> width == 0, "value {value} does not fit in {width} bits");
+
+        let mut remaining = width as usize;
+        let mut value = value;
+        while remaining > 0 {
+            let offset = self.bit_len % 8;
+            if offset == 0 {
+                self.bytes.push(0);
+            }
+            let free = 8 - offset;
+            let n = free.min(remaining);
+            let last = self.bytes.last_mut().unwrap();
+            match self.order {
+                BitOrder::MsbFirst => {
+                    // 取出剩余部分的最高 n 位，放到当前字节空闲部分的高位
+                    let chunk = (value >> (remaining - n)) as u8 & low_mask(n);
+                    *last |= chunk << (free - n);
+                }
+                BitOrder::LsbFirst => {
+                    // 取出剩余部分的最低 n 位，放到当前字节空闲部分的低位
+                    let chunk = value as u8 & low_mask(n);
+                    *last |= chunk << offset;
+                    value >>= n;
+                }
+            }
+            remaining -= n;
+            self.bit_len += n;
+        }
+    }
+
+    pub fn write_bool(&mut self, flag: bool) {
+        self.write_bits(flag as u64, 1)
+    }
+
+    /// 以 zig-zag 编码写入有符号数，编码后的值必须能用 width 位表示
+    pub fn write_signed(&mut self, value: i64, width: u32) {
+        self.write_bits(zigzag_encode(value), width)
+    }
+
+    /// 用 0 填充到下一个字节边界
+    pub fn align_to_byte(&mut self) {
+        self.bit_len = self.bytes.len() * 8;
+    }
+
+    /// 已经写入的位数
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    /// 已经写入的字节，最后一个字节未写满的部分为 0
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// 按位读取器
+#[derive(Debug, Clone)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    order: BitOrder,
+    /// 下一个要读取的位的位置
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8], order: BitOrder) -> Self {
+        BitReader { bytes, order, pos: 0 }
+    }
+
+    /// 读取 width 位无符号数，剩余数据不足时返回错误且不移动读取位置
+    pub fn read_bits(&mut self, width: u32) -> Result<u64, ReadError> {
+        assert!(width <= 64, "bit width {width} is larger than 64");
+        if width as usize > self.remaining_bits() {
+            return Err(ReadError::Underrun {
+                requested: width,
+                available: self.remaining_bits(),
+            });
+        }
+
+        let mut remaining = width as usize;
+        let mut value = 0u64;
+        let mut shift = 0;
+        while remaining > 0 {
+            let byte = self.bytes[self.pos / 8];
+            let offset = self.pos % 8;
+            let available = 8 - offset;
+            let n = available.min(remaining);
+            match self.order {
+                BitOrder::MsbFirst => {
+                    let chunk = (byte >> (available - n)) & low_mask(n);
+                    value = (value << n) | chunk as u64;
+                }
+                BitOrder::LsbFirst => {
+                    let chunk = (byte >> offset) & low_mask(n);
+                    value |= (chunk as u64) << shift;
+                    shift += n;
+                }
+            }
+            remaining -= n;
+            self.pos += n;
+        }
+        Ok(value)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, ReadError> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    /// 读取 zig-zag 编码的有符号数
+    pub fn read_signed(&mut self, width: u32) -> Result<i64, ReadError> {
+        Ok(zigzag_decode(self.read_bits(width)?))
+    }
+
+    /// 跳过当前字节剩余的位
+    pub fn align_to_byte(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+
+    /// 已经读取的位数
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// 剩余可读的位数
+    pub fn remaining_bits(&self) -> usize {
+        self.bytes.len() * 8 - self.pos
+    }
+}
+
+/// 读取失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadError {
+    /// 剩余数据不足，附带请求的位数和实际剩余的位数
+    Underrun { requested: u32, available: usize },
+}
+
+impl Display for ReadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Underrun { requested, available } => {
+                write!(f, "bit stream underrun: requested {requested} bits, {available} available")
+            }
+        }
+    }
+}
+
+impl Error for ReadError {}
+
+#[cfg(test)]
+mod test {
+    use crate::bitstream::{BitOrder, BitReader, BitWriter, ReadError};
+
+    #[test]
+    fn test_msb_first_layout() {
+        let mut writer = BitWriter::new(BitOrder::MsbFirst);
+        writer.write_bits(0b101, 3);
+        writer.write_bits(0b1_1110_0001, 9);
+        writer.write_bool(true);
+        assert_eq!(13, writer.bit_len());
+        // 101 11110 | 000 1 1 000
+        assert_eq!(vec![0b1011_1110, 0b0001_1000], writer.into_bytes());
+    }
+
+    #[test]
+    fn test_lsb_first_layout() {
+        let mut writer = BitWriter::new(BitOrder::LsbFirst);
+        writer.write_bits(0b101, 3);
+        writer.write_bits(0b1_1110_0001, 9);
+        writer.write_bool(true);
+        // 字节内从低位开始填充：第一个字节是 00001 101，第二个字节是 000 1 1111
+        assert_eq!(vec![0b0000_1101, 0b0001_1111], writer.into_bytes());
+    }
+
+    #[test]
+    fn test_round_trip_unaligned() {
+        let fields: Vec<(u64, u32)> = vec![
+            (1, 1),
+            (5, 3),
+            (0, 0),
+            (1109, 15),
+            (0x1f_ffff, 21),
+            (u64::MAX, 64),
+            (51343, 17),
+            (0x7_1234_5678, 35),
+            (2, 2),
+        ];
+        for order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            let mut writer = BitWriter::new(order);
+            for (value, width) in &fields {
+                writer.write_bits(*value, *width);
+            }
+            let total: u32 = fields.iter().map(|(_, width)| width).sum();
+            assert_eq!(total as usize, writer.bit_len());
+
+            let bytes = writer.into_bytes();
+            let mut reader = BitReader::new(&bytes, order);
+            for (value, width) in &fields {
+                assert_eq!(Ok(*value), reader.read_bits(*width), "{order:?} width {width}");
+            }
+            assert!(reader.remaining_bits() < 8);
+        }
+    }
+
+    #[test]
+    fn test_signed_and_bool() {
+        for order in [BitOrder::MsbFirst, BitOrder::LsbFirst] {
+            let mut writer = BitWriter::new(order);
+            writer.write_signed(-1, 1);
+            writer.write_bool(true);
+            writer.write_signed(-4096, 13);
+            writer.write_signed(4095, 13);
+            writer.write_signed(i64::MIN, 64);
+            writer.write_bool(false);
+
+            let bytes = writer.into_bytes();
+            let mut reader = BitReader::new(&bytes, order);
+            assert_eq!(Ok(-1), reader.read_signed(1));
+            assert_eq!(Ok(true), reader.read_bool());
+            assert_eq!(Ok(-4096), reader.read_signed(13));
+            assert_eq!(Ok(4095), reader.read_signed(13));
+            assert_eq!(Ok(i64::MIN), reader.read_signed(64));
+            assert_eq!(Ok(false), reader.read_bool());
+        }
+    }
+
+    #[test]
+    fn test_align_and_underrun() {
+        let mut writer = BitWriter::from_vec(vec![0xab], BitOrder::MsbFirst);
+        writer.write_bits(1, 1);
+        writer.align_to_byte();
+        writer.write_bits(0xcd, 8);
+        let bytes = writer.into_bytes();
+        assert_eq!(vec![0xab, 0x80, 0xcd], bytes);
+
+        let mut reader = BitReader::new(&bytes, BitOrder::MsbFirst);
+        assert_eq!(Ok(0xab), reader.read_bits(8));
+        assert_eq!(Ok(true), reader.read_bool());
+        reader.align_to_byte();
+        assert_eq!(16, reader.position());
+        assert_eq!(Err(ReadError::Underrun { requested: 9, available: 8 }), reader.read_bits(9));
+        // 失败的读取不会移动位置
+        assert_eq!(Ok(0xcd), reader.read_bits(8));
+        assert_eq!(Err(ReadError::Underrun { requested: 1, available: 0 }), reader.read_bool());
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn test_write_overflow() {
+        BitWriter::new(BitOrder::LsbFirst).write_bits(8, 3);
+    }
+}
@@ -0,0 +1,109 @@
+//! BitVec 定长位向量
+//!
+//! 当 64 位的 `u64` 不够用时，用多个 `u64` 字保存任意长度的位。
+//! 下标从 0 开始，第 i 位保存在 `words[i / 64]` 的第 `i % 64` 位，
+//! 因此下标 i 对应 [`crate::bitwise::set_bit`] 中的 position i + 1。
+
+/// 定长位向量，超出长度的高位始终为 0
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct BitVec {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitVec {
+    /// 创建 len 位全为 0 的位向量
+    pub fn new(len: usize) -> Self {
+        BitVec {
+            words: vec![0u64; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    /// 用已有的字创建位向量，words 的个数必须正好能容纳 len 位，超出 len 的位会被清零
+    pub fn from_words(mut words: Vec<u64>, len: usize) -> Self {
+        assert_eq!(len.div_ceil(64), words.len(), "{} words cannot hold exactly {len} bits", words.len());
+        if !len.is_multiple_of(64) {
+            if let Some(last) = words.last_mut() {
+                *last &= (1u64 << (len % 64)) - 1;
+            }
+        }
+        BitVec { words, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 读取第 index 位，越界时 panic
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < self.len, "bit index {index} out of range for length {}", self.len);
+        self.words[index / 64] >> (index % 64) & 1 == 1
+    }
+
+    /// 设置第 index 位，越界时 panic
+    pub fn set(&mut self, index: usize, flag: bool) {
+        assert!(index < self.len, "bit index {index} out of range for length {}", self.len);
+        let mask = 1u64 << (index % 64);
+        if flag {
+            self.words[index / 64] |= mask;
+        } else {
+            self.words[index / 64] &= !mask;
+        }
+    }
+
+    /// 把所有位清零
+    pub fn clear(&mut self) {
+        self.words.fill(0);
+    }
+
+    /// 值为 1 的位数
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn as_words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// 可变地访问底层的字，调用方需要保证超出长度的高位仍然为 0
+    pub fn as_words_mut(&mut self) -> &mut [u64] {
+        &mut self.words
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bitvec::BitVec;
+    use crate::bitwise::set_bit;
+
+    #[test]
+    fn test_bit_vec() {
+        let mut bits = BitVec::new(130);
+        assert_eq!(3, bits.as_words().len());
+
+        bits.set(0, true);
+        bits.set(64, true);
+        bits.set(129, true);
+        assert!(bits.get(64));
+        assert!(!bits.get(65));
+        assert_eq!(3, bits.count_ones());
+
+        bits.set(64, false);
+        assert_eq!(2, bits.count_ones());
+
+        // 下标 i 与 set_bit 的 position i + 1 一致
+        let attr = set_bit(set_bit(0, 1, true), 12, true);
+        let bits = BitVec::from_words(vec![attr], 64);
+        assert!(bits.get(0) && bits.get(11));
+        assert_eq!(2, bits.count_ones());
+
+        // 超出长度的位被清零
+        let bits = BitVec::from_words(vec![u64::MAX], 3);
+        assert_eq!(vec![0b111], bits.as_words());
+    }
+}
@@ -0,0 +1,329 @@
+//! 布隆过滤器
+//!
+//! [`BloomFilter`] 用一个 [`BitVec`] 记录元素是否可能出现过：判断为不存在时一定不存在，判断为存在时有一定概率误判。
+//! [`CountingBloomFilter`] 把每一位换成一个计数器，代价是更多的内存，好处是支持删除。
+//!
+//! 两者都使用双重哈希（double hashing）从两个基础哈希值推导出 k 个位置：`h(i) = h1 + i * h2 (mod m)`。
+
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::f64::consts::LN_2;
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+
+use crate::bitvec::BitVec;
+use crate::bulk::{or_into, popcount_slice};
+
+/// 根据预期元素个数和误判率计算位数 m 和哈希函数个数 k：
+/// `m = -n * ln(p) / ln(2)^2`，`k = m / n * ln(2)`
+fn optimal_params(expected_items: usize, false_positive_rate: f64) -> (usize, u32) {
+    assert!(
+        false_positive_rate > 0.0 && false_positive_rate < 1.0,
+        "false positive rate must be in (0, 1), got {false_positive_rate}"
+    );
+    let n = expected_items.max(1) as f64;
+    let m = (-n * false_positive_rate.ln() / (LN_2 * LN_2)).ceil().max(1.0);
+    let k = (m / n * LN_2).round().max(1.0);
+    (m as usize, k as u32)
+}
+
+/// 计算元素的两个基础哈希值，h2 保证为奇数，避免所有位置退化到同一个
+fn base_hashes<T: Hash + ?Sized>(item: &T) -> (u64, u64) {
+    let mut hasher = DefaultHasher::new();
+    item.hash(&mut hasher);
+    let h1 = hasher.finish();
+    // 在第一个哈希值的基础上继续混入一个常量，得到第二个相互独立的哈希值
+    hasher.write_u64(0x9e37_79b9_7f4a_7c15);
+    let h2 = hasher.finish() | 1;
+    (h1, h2)
+}
+
+/// 元素对应的 k 个下标
+fn indexes<T: Hash + ?Sized>(item: &T, num_hashes: u32, len: usize) -> impl Iterator<Item=usize> {
+    let (h1, h2) = base_hashes(item);
+    (0..num_hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len as u64) as usize)
+}
+
+/// 合并两个参数不同的过滤器时返回的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncompatibleFilters {
+    /// 两个过滤器各自的 (位数, 哈希函数个数)
+    pub left: (usize, u32),
+    pub right: (usize, u32),
+}
+
+impl Display for IncompatibleFilters {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "cannot merge bloom filters with {} bits / {} hashes and {} bits / {} hashes",
+            self.left.0, self.left.1, self.right.0, self.right.1
+        )
+    }
+}
+
+impl Error for IncompatibleFilters {}
+
+/// 布隆过滤器
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: BitVec,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// 按预期元素个数和目标误判率创建，误判率必须在 (0, 1) 之间
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let (num_bits, num_hashes) = optimal_params(expected_items, false_positive_rate);
+        Self::with_params(num_bits, num_hashes)
+    }
+
+    /// 直接指定位数和哈希函数个数
+    pub fn with_params(num_bits: usize, num_hashes: u32) -> Self {
+        assert!(num_bits > 0 && num_hashes > 0, "bloom filter needs at least one bit and one hash");
+        BloomFilter {
+            bits: BitVec::new(num_bits),
+            num_hashes,
+        }
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        for index in indexes(item, self.num_hashes, self.bits.len()) {
+            self.bits.set(index, true);
+        }
+    }
+
+    /// 返回 false 表示一定不存在，返回 true 表示可能存在
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        indexes(item, self.num_hashes, self.bits.len()).all(|index| self.bits.get(index))
+    }
+
+    /// 把另一个参数相同的过滤器合并进来，结果等价于两者插入过的元素的并集
+    pub fn union(&mut self, other: &BloomFilter) -> Result<(), IncompatibleFilters> {
+        if self.params() != other.params() {
+            return Err(IncompatibleFilters {
+                left: self.params(),
+                right: other.params(),
+            });
+        }
+        or_into(self.bits.as_words_mut(), other.bits.as_words());
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.bits.clear();
+    }
+
+    pub fn num_bits(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    /// 根据当前置位的比例估算误判率：`(置位数 / m) ^ k`
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        let fill = popcount_slice(self.bits.as_words()) as f64 / self.bits.len() as f64;
+        fill.powi(self.num_hashes as i32)
+    }
+
+    pub fn as_bit_vec(&self) -> &BitVec {
+        &self.bits
+    }
+
+    fn params(&self) -> (usize, u32) {
+        (self.bits.len(), self.num_hashes)
+    }
+}
+
+/// 计数布隆过滤器，每个位置是一个 8 位计数器
+///
+/// 计数器到达 255 后不再变化，也不会被删除操作减少，以免因为溢出产生假阴性。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountingBloomFilter {
+    counters: Vec<u8>,
+    num_hashes: u32,
+}
+
+impl CountingBloomFilter {
+    /// 按预期元素个数和目标误判率创建，误判率必须在 (0, 1) 之间
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let (num_counters, num_hashes) = optimal_params(expected_items, false_positive_rate);
+        Self::with_params(num_counters, num_hashes)
+    }
+
+    pub fn with_params(num_counters: usize, num_hashes: u32) -> Self {
+        assert!(num_counters > 0 && num_hashes > 0, "bloom filter needs at least one counter and one hash");
+        CountingBloomFilter {
+            counters: vec![0; num_counters],
+            num_hashes,
+        }
+    }
+
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        for index in indexes(item, self.num_hashes, self.counters.len()) {
+            self.counters[index] = self.counters[index].saturating_add(1);
+        }
+    }
+
+    pub fn contains<T: Hash + ?Sized>(&self, item: &T) -> bool {
+        indexes(item, self.num_hashes, self.counters.len()).all(|index| self.counters[index] > 0)
+    }
+
+    /// 删除一个元素，元素不存在时不做任何修改并返回 false
+    ///
+    /// 只能删除确实插入过的元素，删除一个误判为存在的元素会导致其他元素被误删。
+    pub fn remove<T: Hash + ?Sized>(&mut self, item: &T) -> bool {
+        if !self.contains(item) {
+            return false;
+        }
+        for index in indexes(item, self.num_hashes, self.counters.len()) {
+            if self.counters[index] != u8::MAX {
+                self.counters[index] -= 1;
+            }
+        }
+        true
+    }
+
+    /// 把另一个参数相同的过滤器合并进来，对应的计数器相加
+    pub fn merge(&mut self, other: &CountingBloomFilter) -> Result<(), IncompatibleFilters> {
+        if self.params() != other.params() {
+            return Err(IncompatibleFilters {
+                left: self.params(),
+                right: other.params(),
+            });
+        }
+        for (counter, other) in self.counters.iter_mut().zip(&other.counters) {
+            *counter = counter.saturating_add(*other);
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.counters.fill(0);
+    }
+
+    pub fn num_counters(&self) -> usize {
+        self.counters.len()
+    }
+
+    pub fn num_hashes(&self) -> u32 {
+        self.num_hashes
+    }
+
+    /// 转换为普通的布隆过滤器，计数器不为 0 的位置置 1
+    pub fn to_bloom_filter(&self) -> BloomFilter {
+        let mut filter = BloomFilter::with_params(self.counters.len(), self.num_hashes);
+        for (index, counter) in self.counters.iter().enumerate() {
+            if *counter > 0 {
+                filter.bits.set(index, true);
+            }
+        }
+        filter
+    }
+
+    fn params(&self) -> (usize, u32) {
+        (self.counters.len(), self.num_hashes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bloom::{optimal_params, BloomFilter, CountingBloomFilter, IncompatibleFilters};
+
+    const ITEMS: u64 = 10_000;
+    const PROBES: u64 = 100_000;
+
+    #[test]
+    fn test_optimal_params() {
+        // 1% 误判率大约需要每个元素 9.6 位和 7 个哈希函数
+        let (bits, hashes) = optimal_params(1000, 0.01);
+        assert_eq!(9586, bits);
+        assert_eq!(7, hashes);
+    }
+
+    #[test]
+    fn test_bloom_filter_false_positive_rate() {
+        for rate in [0.1, 0.01, 0.001] {
+            let mut filter = BloomFilter::new(ITEMS as usize, rate);
+            for id in 0..ITEMS {
+                filter.insert(&id);
+            }
+            // 不会有假阴性
+            assert!((0..ITEMS).all(|id| filter.contains(&id)));
+
+            let false_positives = (ITEMS..ITEMS + PROBES).filter(|id| filter.contains(id)).count();
+            let measured = false_positives as f64 / PROBES as f64;
+            assert!(measured < rate * 1.5, "target {rate}, measured {measured}");
+
+            let estimated = filter.estimated_false_positive_rate();
+            assert!(estimated < rate * 1.5, "target {rate}, estimated {estimated}");
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter_union() {
+        let mut a = BloomFilter::new(1000, 0.01);
+        let mut b = BloomFilter::new(1000, 0.01);
+        a.insert("event-a");
+        b.insert("event-b");
+        assert!(!a.contains("event-b"));
+
+        a.union(&b).unwrap();
+        assert!(a.contains("event-a"));
+        assert!(a.contains("event-b"));
+
+        let c = BloomFilter::with_params(64, 3);
+        assert_eq!(
+            Err(IncompatibleFilters { left: (9586, 7), right: (64, 3) }),
+            a.union(&c)
+        );
+
+        a.clear();
+        assert!(!a.contains("event-a"));
+    }
+
+    #[test]
+    fn test_counting_bloom_filter_remove() {
+        let mut filter = CountingBloomFilter::new(ITEMS as usize, 0.01);
+        for id in 0..ITEMS {
+            filter.insert(&id);
+        }
+        assert!(!filter.remove(&(ITEMS * 10)));
+
+        // 删除一半后，剩下的一半仍然存在，被删除的大部分不再存在
+        for id in (0..ITEMS).step_by(2) {
+            assert!(filter.remove(&id));
+        }
+        assert!((1..ITEMS).step_by(2).all(|id| filter.contains(&id)));
+        let remaining = (0..ITEMS).step_by(2).filter(|id| filter.contains(id)).count();
+        assert!((remaining as f64) < ITEMS as f64 / 2.0 * 0.015, "{remaining} removed items still present");
+
+        let false_positives = (ITEMS..ITEMS + PROBES).filter(|id| filter.contains(id)).count();
+        assert!((false_positives as f64 / PROBES as f64) < 0.015);
+    }
+
+    #[test]
+    fn test_counting_bloom_filter_merge() {
+        let mut a = CountingBloomFilter::new(100, 0.01);
+        let mut b = CountingBloomFilter::new(100, 0.01);
+        a.insert(&1);
+        b.insert(&1);
+        b.insert(&2);
+
+        a.merge(&b).unwrap();
+        assert!(a.contains(&2));
+        // 1 被插入了两次，删除一次后仍然存在
+        assert!(a.remove(&1));
+        assert!(a.contains(&1));
+        assert!(a.remove(&1));
+        assert!(!a.contains(&1));
+
+        let plain = a.to_bloom_filter();
+        assert!(plain.contains(&2));
+        assert!(!plain.contains(&1));
+
+        assert!(a.merge(&CountingBloomFilter::with_params(10, 1)).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! 面向 `u64` 字切片的批量位运算
+//!
+//! 相比对每一位调用 [`crate::bitwise::set_bit`]，这里一次处理一整个字，并按 8 个字一组展开循环，
+//! 让编译器能够自动向量化。在 x86_64 上还会在运行时检测 CPU 特性，支持 AVX2 / POPCNT 时走 `std::arch` 的实现。
+//!
+//! 位下标的约定与 [`crate::bitvec::BitVec`] 一致：第 i 位保存在 `words[i / 64]` 的第 `i % 64` 位。
+
+/// 每组处理的字数，8 个 u64 正好是两个 256 位寄存器
+const LANES: usize = 8;
+
+/// `dst[i] &= src[i]`，两个切片的长度必须相同
+pub fn and_into(dst: &mut [u64], src: &[u64]) {
+    assert_eq!(dst.len(), src.len(), "and_into requires slices of equal length");
+    #[cfg(target_arch = "x86_64")]
+    if x86::has_avx2() {
+        // SAFETY: 已经在运行时确认 CPU 支持 AVX2
+        unsafe { x86::and_into_avx2(dst, src) };
+        return;
+    }
+    scalar::and_into(dst, src)
+}
+
+/// `dst[i] |= src[i]`，两个切片的长度必须相同
+pub fn or_into(dst: &mut [u64], src: &[u64]) {
+    assert_eq!(dst.len(), src.len(), "or_into requires slices of equal length");
+    #[cfg(target_arch = "x86_64")]
+    if x86::has_avx2() {
+        // SAFETY: 已经在运行时确认 CPU 支持 AVX2
+        unsafe { x86::or_into_avx2(dst, src) };
+        return;
+    }
+    scalar::or_into(dst, src)
+}
+
+/// 所有字中值为 1 的位数
+pub fn popcount_slice(words: &[u64]) -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    if x86::has_popcnt() {
+        // SAFETY: 已经在运行时确认 CPU 支持 POPCNT
+        return unsafe { x86::popcount_slice_popcnt(words) };
+    }
+    scalar::popcount_slice(words)
+}
+
+/// 是否存在值为 1 的位
+pub fn any_set(words: &[u64]) -> bool {
+    #[cfg(target_arch = "x86_64")]
+    if x86::has_avx2() {
+        // SAFETY: 已经在运行时确认 CPU 支持 AVX2
+        return unsafe { x86::any_set_avx2(words) };
+    }
+    scalar::any_set(words)
+}
+
+/// 第一个值为 1 的位的下标
+pub fn find_first_set(words: &[u64]) -> Option<usize> {
+    // 先按块快速跳过全 0 的区域，再在命中的块里逐字查找
+    let mut base = 0;
+    for chunk in words.chunks(LANES * 16) {
+        if any_set(chunk) {
+            return scalar::find_first_set(chunk).map(|bit| base + bit);
+        }
+        base += chunk.len() * 64;
+    }
+    None
+}
+
+/// 可移植的实现，循环写成便于自动向量化的形式
+pub mod scalar {
+    use super::LANES;
+
+    pub fn and_into(dst: &mut [u64], src: &[u64]) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d &= *s;
+        }
+    }
+
+    pub fn or_into(dst: &mut [u64], src: &[u64]) {
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d |= *s;
+        }
+    }
+
+    pub fn popcount_slice(words: &[u64]) -> u64 {
+        // 多个独立的累加器可以避免每次加法都依赖上一次的结果
+        let mut counts = [0u64; LANES];
+        let mut chunks = words.chunks_exact(LANES);
+        for chunk in &mut chunks {
+            for (count, word) in counts.iter_mut().zip(chunk) {
+                *count += word.count_ones() as u64;
+            }
+        }
+        let rest: u64 = chunks.remainder().iter().map(|word| word.count_ones() as u64).sum();
+        counts.iter().sum::<u64>() + rest
+    }
+
+    pub fn any_set(words: &[u64]) -> bool {
+        let mut chunks = words.chunks_exact(LANES);
+        for chunk in &mut chunks {
+            // 先把一组字 OR 到一起再判断，分支只有一个
+            if chunk.iter().fold(0, |acc, word| acc | word) != 0 {
+                return true;
+            }
+        }
+        chunks.remainder().iter().any(|word| *word != 0)
+    }
+
+    pub fn find_first_set(words: &[u64]) -> Option<usize> {
+        words
+            .iter()
+            .position(|word| *word != 0)
+            .map(|idx| idx * 64 + words[idx].trailing_zeros() as usize)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::{
+        __m256i, _mm256_and_si256, _mm256_loadu_si256, _mm256_or_si256, _mm256_storeu_si256, _mm256_testz_si256,
+        _popcnt64,
+    };
+    use std::sync::OnceLock;
+
+    use super::scalar;
+
+    /// 每个 256 位寄存器可以放 4 个 u64
+    const WORDS_PER_REG: usize = 4;
+
+    pub fn has_avx2() -> bool {
+        static AVX2: OnceLock<bool> = OnceLock::new();
+        *AVX2.get_or_init(|| is_x86_feature_detected!("avx2"))
+    }
+
+    pub fn has_popcnt() -> bool {
+        static POPCNT: OnceLock<bool> = OnceLock::new();
+        *POPCNT.get_or_init(|| is_x86_feature_detected!("popcnt"))
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn and_into_avx2(dst: &mut [u64], src: &[u64]) {
+        let mut d = dst.chunks_exact_mut(WORDS_PER_REG);
+        let mut s = src.chunks_exact(WORDS_PER_REG);
+        for (d, s) in (&mut d).zip(&mut s) {
+            let a = _mm256_loadu_si256(d.as_ptr() as *const __m256i);
+            let b = _mm256_loadu_si256(s.as_ptr() as *const __m256i);
+            _mm256_storeu_si256(d.as_mut_ptr() as *mut __m256i, _mm256_and_si256(a, b));
+        }
+        scalar::and_into(d.into_remainder(), s.remainder());
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn or_into_avx2(dst: &mut [u64], src: &[u64]) {
+        let mut d = dst.chunks_exact_mut(WORDS_PER_REG);
+        let mut s = src.chunks_exact(WORDS_PER_REG);
+        for (d, s) in (&mut d).zip(&mut s) {
+            let a = _mm256_loadu_si256(d.as_ptr() as *const __m256i);
+            let b = _mm256_loadu_si256(s.as_ptr() as *const __m256i);
+            _mm256_storeu_si256(d.as_mut_ptr() as *mut __m256i, _mm256_or_si256(a, b));
+        }
+        scalar::or_into(d.into_remainder(), s.remainder());
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn any_set_avx2(words: &[u64]) -> bool {
+        let mut chunks = words.chunks_exact(WORDS_PER_REG);
+        for chunk in &mut chunks {
+            let v = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+            // testz 在 v & v 全为 0 时返回 1
+            if _mm256_testz_si256(v, v) == 0 {
+                return true;
+            }
+        }
+        scalar::any_set(chunks.remainder())
+    }
+
+    #[target_feature(enable = "popcnt")]
+    pub unsafe fn popcount_slice_popcnt(words: &[u64]) -> u64 {
+        let mut counts = [0u64; super::LANES];
+        let mut chunks = words.chunks_exact(super::LANES);
+        for chunk in &mut chunks {
+            for (count, word) in counts.iter_mut().zip(chunk) {
+                *count += _popcnt64(*word as i64) as u64;
+            }
+        }
+        let rest: u64 = chunks.remainder().iter().map(|word| _popcnt64(*word as i64) as u64).sum();
+        counts.iter().sum::<u64>() + rest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bitwise::get_bit;
+    use crate::bulk::{and_into, any_set, find_first_set, or_into, popcount_slice, scalar};
+
+    fn pseudo_random_words(seed: u64, len: usize) -> Vec<u64> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_and_or_into() {
+        // 长度覆盖整组和余数的情况
+        for len in [0, 1, 3, 4, 8, 13, 64, 67] {
+            let a = pseudo_random_words(1, len);
+            let b = pseudo_random_words(2, len);
+            let and_want: Vec<u64> = a.iter().zip(&b).map(|(x, y)| x & y).collect();
+            let or_want: Vec<u64> = a.iter().zip(&b).map(|(x, y)| x | y).collect();
+
+            let mut dst = a.clone();
+            and_into(&mut dst, &b);
+            assert_eq!(and_want, dst);
+            let mut dst = a.clone();
+            scalar::and_into(&mut dst, &b);
+            assert_eq!(and_want, dst);
+
+            let mut dst = a.clone();
+            or_into(&mut dst, &b);
+            assert_eq!(or_want, dst);
+            let mut dst = a.clone();
+            scalar::or_into(&mut dst, &b);
+            assert_eq!(or_want, dst);
+        }
+    }
+
+    #[test]
+    fn test_popcount_slice() {
+        for len in [0, 1, 7, 8, 9, 100] {
+            let words = pseudo_random_words(3, len);
+            // 用逐位的 get_bit 作为参照
+            let want: u64 = words
+                .iter()
+                .map(|word| (1..=64).map(|position| get_bit(*word, position) as u64).sum::<u64>())
+                .sum();
+            assert_eq!(want, popcount_slice(&words));
+            assert_eq!(want, scalar::popcount_slice(&words));
+        }
+    }
+
+    #[test]
+    fn test_any_set_and_find_first_set() {
+        let mut words = vec![0u64; 37];
+        assert!(!any_set(&words));
+        assert!(!scalar::any_set(&words));
+        assert_eq!(None, find_first_set(&words));
+        assert_eq!(None, find_first_set(&[]));
+
+        for bit in [0, 63, 64, 511, 512, 35 * 64 + 5, 36 * 64 + 63] {
+            words.fill(0);
+            words[bit / 64] = 1u64 << (bit % 64);
+            // 在更靠后的位置也放一个 1，确保找到的是第一个
+            words[36] |= 1u64 << 63;
+            assert!(any_set(&words));
+            assert!(scalar::any_set(&words));
+            assert_eq!(Some(bit), find_first_set(&words));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "equal length")]
+    fn test_length_mismatch() {
+        or_into(&mut [0u64; 2], &[0u64; 3]);
+    }
+}
@@ -0,0 +1,376 @@
+//! 位掩码的字符串编码，用于在配置文件和 URL 中展示、接收掩码
+//!
+//! 支持三种编码：
+//!
+//! - 二进制字符串：第 i 个字符是第 i + 1 位，与 [`crate::bitwise::get_bits`] 的顺序一致，例如 1109 的前 15 位是 `"101010100010000"`
+//! - 十六进制：`u64` 掩码按数值书写（`"455"`），位向量按小端字节序逐字节书写，每个字节两位
+//! - base64url：按小端字节序编码，不带 `=` 填充；`u64` 掩码会省略末尾（高位）的零字节
+//!
+//! 所有解析函数都是严格的：拒绝空串、非法字符、超长输入以及不为零的填充位；`u64` 掩码的十六进制和 base64url
+//! 编码还拒绝本应省略的高位零。二进制字符串的长度对应 `get_bits` 的 length，末尾的 `0` 不算多余。
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use crate::bitvec::BitVec;
+
+const BASE64URL: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// 解析失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// 输入为空
+    Empty,
+    /// 在字符下标 index 处遇到非法字符
+    InvalidChar { index: usize, ch: char },
+    /// 输入超过了掩码能容纳的最大长度
+    TooLong { max: usize, found: usize },
+    /// 位向量的编码长度与期望的长度不符
+    Length { expected: usize, found: usize },
+    /// 填充位或超出长度的位不为 0，说明输入不是规范编码
+    NonZeroPadding,
+    /// `u64` 掩码的编码带有本应省略的高位零（十六进制的前导 0，base64url 末尾的零字节），说明输入不是规范编码
+    RedundantZeros,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty bit mask"),
+            ParseError::InvalidChar { index, ch } => write!(f, "invalid character {ch:?} at index {index}"),
+            ParseError::TooLong { max, found } => write!(f, "bit mask too long: {found} characters, at most {max} allowed"),
+            ParseError::Length { expected, found } => write!(f, "bit mask has {found} characters, expected {expected}"),
+            ParseError::NonZeroPadding => write!(f, "bit mask has non-zero padding bits"),
+            ParseError::RedundantZeros => write!(f, "bit mask has redundant high-order zeros"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+// --------------------------------------------------------------------------------
+// u64 掩码
+
+/// 把 attr 的前 length 位编码为二进制字符串，顺序与 `get_bits(attr, length)` 一致
+pub fn to_binary(attr: u64, length: usize) -> String {
+    (0..length)
+        .map(|i| if i < 64 && attr >> i & 1 == 1 { '1' } else { '0' })
+        .collect()
+}
+
+/// 解析二进制字符串，结果与对相同的位调用 `get_total_bits` 一致，最多 64 个字符
+pub fn from_binary(text: &str) -> Result<u64, ParseError> {
+    check_length(text, 64)?;
+    let mut attr = 0u64;
+    for (index, ch) in text.chars().enumerate() {
+        match ch {
+            '0' => {}
+            '1' => attr |= 1u64 << index,
+            _ => return Err(ParseError::InvalidChar { index, ch }),
+        }
+    }
+    Ok(attr)
+}
+
+/// 以小写十六进制数值表示掩码，不带 `0x` 前缀和前导零
+pub fn to_hex(attr: u64) -> String {
+    format!("{attr:x}")
+}
+
+/// 解析十六进制数值，大小写均可，最多 16 位，不接受 `0x` 前缀、符号和前导零（0 本身写作 `"0"`）
+pub fn from_hex(text: &str) -> Result<u64, ParseError> {
+    check_length(text, 16)?;
+    let mut attr = 0u64;
+    for (index, ch) in text.chars().enumerate() {
+        let digit = ch.to_digit(16).ok_or(ParseError::InvalidChar { index, ch })?;
+        attr = attr << 4 | digit as u64;
+    }
+    if text.len() > 1 && text.starts_with('0') {
+        return Err(ParseError::RedundantZeros);
+    }
+    Ok(attr)
+}
+
+/// 以 base64url 编码掩码的小端字节，省略高位的零字节（至少保留一个字节）
+pub fn to_base64url(attr: u64) -> String {
+    let bytes = attr.to_le_bytes();
+    let used = (8 - attr.leading_zeros() as usize / 8).max(1);
+    encode_base64url(&bytes[..used])
+}
+
+/// 解析 [`to_base64url`] 的输出，最多 8 个字节（11 个字符）
+///
+/// 只接受规范编码：除了 0 编码成的单个零字节，最后一个字节不能是 0。
+pub fn from_base64url(text: &str) -> Result<u64, ParseError> {
+    check_length(text, 11)?;
+    let bytes = decode_base64url(text)?;
+    // 11 个字符最多 66 位，check_length 已经保证了不超过 8 个字节
+    debug_assert!(bytes.len() <= 8);
+    if bytes.len() > 1 && bytes.last() == Some(&0) {
+        return Err(ParseError::RedundantZeros);
+    }
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(&bytes);
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn check_length(text: &str, max: usize) -> Result<(), ParseError> {
+    if text.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    let found = text.chars().count();
+    if found > max {
+        return Err(ParseError::TooLong { max, found });
+    }
+    Ok(())
+}
+
+// --------------------------------------------------------------------------------
+// 位向量
+
+impl BitVec {
+    /// 编码为二进制字符串，第 i 个字符是第 i 位
+    pub fn to_binary(&self) -> String {
+        (0..self.len()).map(|i| if self.get(i) { '1' } else { '0' }).collect()
+    }
+
+    /// 解析二进制字符串，字符个数必须正好是 len
+    pub fn from_binary(text: &str, len: usize) -> Result<BitVec, ParseError> {
+        check_exact_length(text, len)?;
+        let mut bits = BitVec::new(len);
+        for (index, ch) in text.chars().enumerate() {
+            match ch {
+                '0' => {}
+                '1' => bits.set(index, true),
+                _ => return Err(ParseError::InvalidChar { index, ch }),
+            }
+        }
+        Ok(bits)
+    }
+
+    /// 按小端字节序逐字节编码为十六进制，每个字节高半字节在前
+    pub fn to_hex(&self) -> String {
+        self.to_le_bytes().iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// 解析 [`BitVec::to_hex`] 的输出，字符个数必须正好是 len 位对应的字节数的两倍
+    pub fn from_hex(text: &str, len: usize) -> Result<BitVec, ParseError> {
+        check_exact_length(text, len.div_ceil(8) * 2)?;
+        let mut bytes = Vec::with_capacity(text.len() / 2);
+        let mut high = 0u8;
+        for (index, ch) in text.chars().enumerate() {
+            let digit = ch.to_digit(16).ok_or(ParseError::InvalidChar { index, ch })? as u8;
+            if index % 2 == 0 {
+                high = digit;
+            } else {
+                bytes.push(high << 4 | digit);
+            }
+        }
+        BitVec::from_le_bytes(&bytes, len)
+    }
+
+    /// 以 base64url 编码小端字节，不带填充
+    pub fn to_base64url(&self) -> String {
+        encode_base64url(&self.to_le_bytes())
+    }
+
+    /// 解析 [`BitVec::to_base64url`] 的输出，长度必须与 len 位对应的编码长度一致
+    pub fn from_base64url(text: &str, len: usize) -> Result<BitVec, ParseError> {
+        let byte_len = len.div_ceil(8);
+        check_exact_length(text, (byte_len * 4).div_ceil(3))?;
+        let bytes = decode_base64url(text)?;
+        BitVec::from_le_bytes(&bytes, len)
+    }
+
+    fn to_le_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.as_words().iter().flat_map(|word| word.to_le_bytes()).collect();
+        bytes.truncate(self.len().div_ceil(8));
+        bytes
+    }
+
+    fn from_le_bytes(bytes: &[u8], len: usize) -> Result<BitVec, ParseError> {
+        let mut words = vec![0u64; len.div_ceil(64)];
+        for (i, byte) in bytes.iter().enumerate() {
+            words[i / 8] |= (*byte as u64) << (i % 8 * 8);
+        }
+        let bits = BitVec::from_words(words.clone(), len);
+        // from_words 会清掉超出长度的位，清掉了说明输入里有多余的 1
+        if bits.as_words() != words {
+            return Err(ParseError::NonZeroPadding);
+        }
+        Ok(bits)
+    }
+}
+
+fn check_exact_length(text: &str, expected: usize) -> Result<(), ParseError> {
+    let found = text.chars().count();
+    if found != expected {
+        return Err(ParseError::Length { expected, found });
+    }
+    Ok(())
+}
+
+// --------------------------------------------------------------------------------
+// base64url
+
+fn encode_base64url(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity((bytes.len() * 4).div_ceil(3));
+    for chunk in bytes.chunks(3) {
+        let mut buf = [0u8; 3];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let group = (buf[0] as u32) << 16 | (buf[1] as u32) << 8 | buf[2] as u32;
+        // n 个字节需要 n + 1 个字符
+        for i in 0..=chunk.len() {
+            text.push(BASE64URL[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+    }
+    text
+}
+
+fn decode_base64url(text: &str) -> Result<Vec<u8>, ParseError> {
+    let mut values = Vec::with_capacity(text.len());
+    for (index, ch) in text.chars().enumerate() {
+        let value = match ch {
+            'A'..='Z' => ch as u8 - b'A',
+            'a'..='z' => ch as u8 - b'a' + 26,
+            '0'..='9' => ch as u8 - b'0' + 52,
+            '-' => 62,
+            '_' => 63,
+            _ => return Err(ParseError::InvalidChar { index, ch }),
+        };
+        values.push(value as u32);
+    }
+    // 最后一组只有 1 个字符时连一个字节都凑不齐
+    if values.len() % 4 == 1 {
+        return Err(ParseError::Length {
+            expected: values.len() + 1,
+            found: values.len(),
+        });
+    }
+
+    let mut bytes = Vec::with_capacity(values.len() * 3 / 4);
+    for chunk in values.chunks(4) {
+        let mut group = 0u32;
+        for (i, value) in chunk.iter().enumerate() {
+            group |= value << (18 - 6 * i);
+        }
+        let byte_count = chunk.len() - 1;
+        // 没有被字节用到的低位必须为 0，否则同一个值会有多种编码
+        if group & ((1u32 << (8 * (3 - byte_count))) - 1) != 0 {
+            return Err(ParseError::NonZeroPadding);
+        }
+        for i in 0..byte_count {
+            bytes.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bitvec::BitVec;
+    use crate::bitwise::{get_bits, get_total_bits};
+    use crate::encoding::{from_base64url, from_binary, from_hex, to_base64url, to_binary, to_hex, ParseError};
+
+    fn bits_to_string(bits: &[i8]) -> String {
+        bits.iter().map(|b| if *b == 1 { '1' } else { '0' }).collect()
+    }
+
+    #[test]
+    fn test_binary_matches_get_bits() {
+        // 与 test_get_bits / test_get_total_bits 相同的测试数据
+        let attr = 1109u64;
+        assert_eq!(bits_to_string(&get_bits(attr, 15)), to_binary(attr, 15));
+        assert_eq!("101010100010000", to_binary(attr, 15));
+        assert_eq!(Ok(attr), from_binary("101010100010000"));
+
+        let flags: Vec<i8> = vec![0, 1, 0, 1];
+        assert_eq!(Ok(get_total_bits(&flags)), from_binary(&bits_to_string(&flags)));
+        assert_eq!(Ok(10), from_binary("0101"));
+
+        for attr in [0u64, 1, 139, 51343, u64::MAX] {
+            assert_eq!(Ok(attr), from_binary(&to_binary(attr, 64)));
+            assert_eq!(bits_to_string(&get_bits(attr, 64)), to_binary(attr, 64));
+        }
+    }
+
+    #[test]
+    fn test_hex_and_base64url_round_trip() {
+        assert_eq!("455", to_hex(1109));
+        assert_eq!(Ok(1109), from_hex("455"));
+        assert_eq!(Ok(51343), from_hex("C88F"));
+        assert_eq!("VQQ", to_base64url(1109));
+        assert_eq!("AA", to_base64url(0));
+
+        for attr in [0u64, 1, 10, 139, 1109, 51343, 1 << 63, u64::MAX] {
+            assert_eq!(Ok(attr), from_hex(&to_hex(attr)));
+            assert_eq!(Ok(attr), from_base64url(&to_base64url(attr)));
+        }
+        assert_eq!(11, to_base64url(u64::MAX).len());
+    }
+
+    #[test]
+    fn test_strict_parsing() {
+        assert_eq!(Err(ParseError::Empty), from_binary(""));
+        assert_eq!(Err(ParseError::InvalidChar { index: 2, ch: '2' }), from_binary("012"));
+        assert_eq!(Err(ParseError::TooLong { max: 64, found: 65 }), from_binary(&"0".repeat(65)));
+
+        assert_eq!(Err(ParseError::InvalidChar { index: 1, ch: 'x' }), from_hex("0x1f"));
+        assert_eq!(Err(ParseError::InvalidChar { index: 0, ch: '-' }), from_hex("-1"));
+        assert_eq!(Err(ParseError::TooLong { max: 16, found: 17 }), from_hex("10000000000000000"));
+        // 前导零必须省略，否则同一个值会有多种编码
+        assert_eq!(Err(ParseError::RedundantZeros), from_hex("0455"));
+        assert_eq!(Err(ParseError::RedundantZeros), from_hex("0000000000000455"));
+        assert_eq!(Err(ParseError::RedundantZeros), from_hex("00"));
+        assert_eq!(Ok(0), from_hex("0"));
+
+        assert_eq!(Err(ParseError::InvalidChar { index: 2, ch: '=' }), from_base64url("AA=="));
+        assert_eq!(Err(ParseError::InvalidChar { index: 0, ch: '+' }), from_base64url("+A"));
+        assert_eq!(Err(ParseError::TooLong { max: 11, found: 12 }), from_base64url("AAAAAAAAAAAA"));
+        assert_eq!(Err(ParseError::Length { expected: 2, found: 1 }), from_base64url("A"));
+        // "AB" 的低 4 位不为 0，不是规范编码
+        assert_eq!(Err(ParseError::NonZeroPadding), from_base64url("AB"));
+        // 高位的零字节必须省略，否则同一个值会有多种编码
+        assert_eq!(Err(ParseError::RedundantZeros), from_base64url("AQAA"));
+        assert_eq!(Err(ParseError::RedundantZeros), from_base64url("AAAA"));
+        assert_eq!(Err(ParseError::RedundantZeros), from_base64url("AAAAAAAAAAA"));
+        assert_eq!(Ok(1), from_base64url("AQ"));
+    }
+
+    #[test]
+    fn test_bit_vec_round_trip() {
+        let mut bits = BitVec::new(150);
+        for index in [0, 2, 4, 6, 10, 63, 64, 100, 149] {
+            bits.set(index, true);
+        }
+
+        let binary = bits.to_binary();
+        assert_eq!(150, binary.len());
+        assert_eq!(&binary[..15], to_binary(1109, 15));
+        assert_eq!(Ok(bits.clone()), BitVec::from_binary(&binary, 150));
+
+        let hex = bits.to_hex();
+        assert_eq!(38, hex.len());
+        assert!(hex.starts_with("5504"));
+        assert_eq!(Ok(bits.clone()), BitVec::from_hex(&hex, 150));
+        assert_eq!(Ok(bits.clone()), BitVec::from_hex(&hex.to_uppercase(), 150));
+
+        let base64 = bits.to_base64url();
+        assert_eq!(Ok(bits.clone()), BitVec::from_base64url(&base64, 150));
+
+        // 单个字的位向量和 u64 掩码的 base64url 编码在没有高位零字节时一致
+        let attr = BitVec::from_words(vec![u64::MAX], 64);
+        assert_eq!(to_base64url(u64::MAX), attr.to_base64url());
+    }
+
+    #[test]
+    fn test_bit_vec_strict_parsing() {
+        assert_eq!(Err(ParseError::Length { expected: 4, found: 5 }), BitVec::from_binary("10101", 4));
+        assert_eq!(Err(ParseError::InvalidChar { index: 1, ch: ' ' }), BitVec::from_binary("1 01", 4));
+        assert_eq!(Err(ParseError::Length { expected: 2, found: 4 }), BitVec::from_hex("0f00", 4));
+        // 长度为 4 时第 4 位以上必须为 0
+        assert_eq!(Err(ParseError::NonZeroPadding), BitVec::from_hex("1f", 4));
+        assert_eq!(Ok(BitVec::from_words(vec![0xf], 4)), BitVec::from_hex("0f", 4));
+        assert_eq!(Err(ParseError::NonZeroPadding), BitVec::from_base64url("_w", 4));
+    }
+}
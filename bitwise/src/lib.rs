@@ -1,3 +1,10 @@
+pub mod bitstream;
+pub mod bitvec;
+pub mod bloom;
+pub mod bulk;
+pub mod encoding;
+pub mod roaring;
+
 pub mod bitwise {
     pub fn set_bit(attr: u64, position: i32, flag: bool) -> u64 {
         if position <= 0 {
@@ -0,0 +1,920 @@
+//! RoaringBitmap 压缩位图
+//!
+//! 把 32 位的 ID 按高 16 位分桶，每个桶（container）只保存低 16 位，并根据桶内数据的疏密选择编码：
+//!
+//! - 数组（array）：有序的 `u16` 数组，适合稀疏的桶，最多 4096 个元素（8KB）
+//! - 位图（bitmap）：1024 个 `u64` 组成的定长位图（8KB），适合稠密的桶
+//! - 行程（run）：`[start, end]` 闭区间列表，适合连续的 ID 段，通过 [`RoaringBitmap::run_optimize`] 生成
+//!
+//! 相比对全部 ID 使用稠密的 `u64` 位图，稀疏数据只需要为实际存在的桶付出内存。
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+
+/// 数组容器的最大元素个数，超过后转换为位图容器
+const ARRAY_MAX: usize = 4096;
+
+/// 位图容器固定由 1024 个 u64 组成，覆盖 65536 个低位值
+const BITMAP_WORDS: usize = 1024;
+
+/// 序列化格式的魔数，即 ASCII 的 "RBM1"
+const SERIAL_MAGIC: u32 = u32::from_le_bytes(*b"RBM1");
+
+const KIND_ARRAY: u8 = 0;
+const KIND_BITMAP: u8 = 1;
+const KIND_RUN: u8 = 2;
+
+/// 一段连续的值，`start` 和 `end` 都包含在内
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Run {
+    start: u16,
+    end: u16,
+}
+
+impl Run {
+    fn len(&self) -> usize {
+        (self.end - self.start) as usize + 1
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Container {
+    Array(Vec<u16>),
+    /// 位图以及它的基数，基数随插入删除维护，避免每次都重新统计
+    Bitmap(Box<[u64; BITMAP_WORDS]>, usize),
+    Run(Vec<Run>),
+}
+
+impl Container {
+    fn cardinality(&self) -> usize {
+        match self {
+            Container::Array(values) => values.len(),
+            Container::Bitmap(_, cardinality) => *cardinality,
+            Container::Run(runs) => runs.iter().map(Run::len).sum(),
+        }
+    }
+
+    fn contains(&self, value: u16) -> bool {
+        match self {
+            Container::Array(values) => values.binary_search(&value).is_ok(),
+            Container::Bitmap(words, _) => words[value as usize / 64] & (1u64 << (value % 64)) != 0,
+            Container::Run(runs) => {
+                // 找到最后一个 start <= value 的区间，再看它是否覆盖 value
+                let idx = runs.partition_point(|run| run.start <= value);
+                idx > 0 && runs[idx - 1].end >= value
+            }
+        }
+    }
+
+    fn insert(&mut self, value: u16) -> bool {
+        let inserted = match self {
+            Container::Array(values) => match values.binary_search(&value) {
+                Ok(_) => false,
+                Err(idx) => {
+                    values.insert(idx, value);
+                    true
+                }
+            },
+            Container::Bitmap(words, cardinality) => {
+                let word = &mut words[value as usize / 64];
+                let mask = 1u64 << (value % 64);
+                if *word & mask != 0 {
+                    false
+                } else {
+                    *word |= mask;
+                    *cardinality += 1;
+                    true
+                }
+            }
+            Container::Run(runs) => insert_into_runs(runs, value),
+        };
+        if inserted {
+            self.repair();
+        }
+        inserted
+    }
+
+    fn remove(&mut self, value: u16) -> bool {
+        let removed = match self {
+            Container::Array(values) => match values.binary_search(&value) {
+                Ok(idx) => {
+                    values.remove(idx);
+                    true
+                }
+                Err(_) => false,
+            },
+            Container::Bitmap(words, cardinality) => {
+                let word = &mut words[value as usize / 64];
+                let mask = 1u64 << (value % 64);
+                if *word & mask == 0 {
+                    false
+                } else {
+                    *word &= !mask;
+                    *cardinality -= 1;
+                    true
+                }
+            }
+            Container::Run(runs) => remove_from_runs(runs, value),
+        };
+        if removed {
+            self.repair();
+        }
+        removed
+    }
+
+    /// 插入或删除后检查编码是否还合适：
+    /// 数组超过上限转位图，位图低于上限转数组，行程容器比其他编码更占空间时放弃行程编码
+    fn repair(&mut self) {
+        if self.is_canonical() {
+            return;
+        }
+        *self = match self {
+            Container::Bitmap(..) => Container::Array(self.iter().collect()),
+            _ => Container::from_words(self.to_words()),
+        };
+    }
+
+    /// 编码是否已经是 [`repair`](Self::repair) 之后的形式
+    fn is_canonical(&self) -> bool {
+        match self {
+            Container::Array(values) => values.len() <= ARRAY_MAX,
+            Container::Bitmap(_, cardinality) => *cardinality > ARRAY_MAX,
+            Container::Run(runs) => {
+                let cardinality: usize = runs.iter().map(Run::len).sum();
+                runs.len() * 4 <= (cardinality * 2).min(BITMAP_WORDS * 8)
+            }
+        }
+    }
+
+    /// 展开成定长位图，用于和其他编码做集合运算
+    fn to_words(&self) -> Box<[u64; BITMAP_WORDS]> {
+        match self {
+            Container::Bitmap(words, _) => words.clone(),
+            _ => {
+                let mut words = Box::new([0u64; BITMAP_WORDS]);
+                for value in self.iter() {
+                    words[value as usize / 64] |= 1u64 << (value % 64);
+                }
+                words
+            }
+        }
+    }
+
+    /// 根据基数从位图构造最合适的数组或位图容器
+    fn from_words(words: Box<[u64; BITMAP_WORDS]>) -> Container {
+        let cardinality = words.iter().map(|word| word.count_ones() as usize).sum();
+        let container = Container::Bitmap(words, cardinality);
+        if cardinality <= ARRAY_MAX {
+            Container::Array(container.iter().collect())
+        } else {
+            container
+        }
+    }
+
+    /// 如果行程编码更省空间，则转换为行程容器，返回是否为行程容器
+    fn run_optimize(&mut self) -> bool {
+        let mut runs: Vec<Run> = Vec::new();
+        for value in self.iter() {
+            match runs.last_mut() {
+                Some(run) if run.end as u32 + 1 == value as u32 => run.end = value,
+                _ => runs.push(Run { start: value, end: value }),
+            }
+        }
+        let current_size = match self {
+            Container::Array(values) => values.len() * 2,
+            Container::Bitmap(..) => BITMAP_WORDS * 8,
+            Container::Run(runs) => runs.len() * 4,
+        };
+        if runs.len() * 4 < current_size {
+            *self = Container::Run(runs);
+        }
+        matches!(self, Container::Run(_))
+    }
+
+    fn union(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Array(a), Container::Array(b)) if a.len() + b.len() <= ARRAY_MAX => {
+                let mut merged = Vec::with_capacity(a.len() + b.len());
+                let (mut i, mut j) = (0, 0);
+                while i < a.len() && j < b.len() {
+                    if a[i] < b[j] {
+                        merged.push(a[i]);
+                        i += 1;
+                    } else if a[i] > b[j] {
+                        merged.push(b[j]);
+                        j += 1;
+                    } else {
+                        merged.push(a[i]);
+                        i += 1;
+                        j += 1;
+                    }
+                }
+                merged.extend_from_slice(&a[i..]);
+                merged.extend_from_slice(&b[j..]);
+                Container::Array(merged)
+            }
+            _ => {
+                let mut words = self.to_words();
+                for (word, other) in words.iter_mut().zip(other.to_words().iter()) {
+                    *word |= other;
+                }
+                Container::from_words(words)
+            }
+        }
+    }
+
+    fn intersection(&self, other: &Container) -> Container {
+        match (self, other) {
+            (Container::Array(a), _) => {
+                Container::Array(a.iter().copied().filter(|v| other.contains(*v)).collect())
+            }
+            (_, Container::Array(b)) => {
+                Container::Array(b.iter().copied().filter(|v| self.contains(*v)).collect())
+            }
+            _ => {
+                let mut words = self.to_words();
+                for (word, other) in words.iter_mut().zip(other.to_words().iter()) {
+                    *word &= other;
+                }
+                Container::from_words(words)
+            }
+        }
+    }
+
+    fn difference(&self, other: &Container) -> Container {
+        match self {
+            Container::Array(a) => {
+                Container::Array(a.iter().copied().filter(|v| !other.contains(*v)).collect())
+            }
+            _ => {
+                let mut words = self.to_words();
+                for (word, other) in words.iter_mut().zip(other.to_words().iter()) {
+                    *word &= !other;
+                }
+                Container::from_words(words)
+            }
+        }
+    }
+
+    fn iter(&self) -> ContainerIter<'_> {
+        match self {
+            Container::Array(values) => ContainerIter::Array(values.iter()),
+            Container::Bitmap(words, _) => ContainerIter::Bitmap {
+                words,
+                idx: 0,
+                current: words[0],
+            },
+            Container::Run(runs) => ContainerIter::Run {
+                runs: runs.iter(),
+                next: 0,
+                end: None,
+            },
+        }
+    }
+}
+
+/// 向行程列表插入一个值，必要时与前后区间合并
+fn insert_into_runs(runs: &mut Vec<Run>, value: u16) -> bool {
+    let idx = runs.partition_point(|run| run.start <= value);
+    if idx > 0 && runs[idx - 1].end >= value {
+        return false;
+    }
+    let joins_prev = idx > 0 && runs[idx - 1].end as u32 + 1 == value as u32;
+    let joins_next = idx < runs.len() && value as u32 + 1 == runs[idx].start as u32;
+    match (joins_prev, joins_next) {
+        (true, true) => {
+            runs[idx - 1].end = runs[idx].end;
+            runs.remove(idx);
+        }
+        (true, false) => runs[idx - 1].end = value,
+        (false, true) => runs[idx].start = value,
+        (false, false) => runs.insert(idx, Run { start: value, end: value }),
+    }
+    true
+}
+
+/// 从行程列表删除一个值，删除区间中间的值会把区间一分为二
+fn remove_from_runs(runs: &mut Vec<Run>, value: u16) -> bool {
+    let idx = runs.partition_point(|run| run.start <= value);
+    if idx == 0 || runs[idx - 1].end < value {
+        return false;
+    }
+    let run = runs[idx - 1];
+    if run.start == run.end {
+        runs.remove(idx - 1);
+    } else if run.start == value {
+        runs[idx - 1].start = value + 1;
+    } else if run.end == value {
+        runs[idx - 1].end = value - 1;
+    } else {
+        runs[idx - 1].end = value - 1;
+        runs.insert(idx, Run { start: value + 1, end: run.end });
+    }
+    true
+}
+
+enum ContainerIter<'a> {
+    Array(std::slice::Iter<'a, u16>),
+    Bitmap {
+        words: &'a [u64; BITMAP_WORDS],
+        idx: usize,
+        current: u64,
+    },
+    Run {
+        runs: std::slice::Iter<'a, Run>,
+        next: u32,
+        /// 当前区间的结尾，None 表示需要取下一个区间
+        end: Option<u32>,
+    },
+}
+
+impl Iterator for ContainerIter<'_> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<u16> {
+        match self {
+            ContainerIter::Array(iter) => iter.next().copied(),
+            ContainerIter::Bitmap { words, idx, current } => {
+                while *current == 0 {
+                    *idx += 1;
+                    if *idx >= BITMAP_WORDS {
+                        return None;
+                    }
+                    *current = words[*idx];
+                }
+                let bit = current.trailing_zeros() as usize;
+                // 清掉最低位的 1
+                *current &= *current - 1;
+                Some((*idx * 64 + bit) as u16)
+            }
+            ContainerIter::Run { runs, next, end } => {
+                if end.is_none_or(|end| *next > end) {
+                    let run = runs.next()?;
+                    *next = run.start as u32;
+                    *end = Some(run.end as u32);
+                }
+                let value = *next as u16;
+                *next += 1;
+                Some(value)
+            }
+        }
+    }
+}
+
+/// RoaringBitmap 压缩位图，保存一组 `u32`
+///
+/// 容器按高 16 位有序排列，空容器会被立即移除。
+#[derive(Clone, Default)]
+pub struct RoaringBitmap {
+    containers: Vec<(u16, Container)>,
+}
+
+fn split(value: u32) -> (u16, u16) {
+    ((value >> 16) as u16, value as u16)
+}
+
+fn join(high: u16, low: u16) -> u32 {
+    (high as u32) << 16 | low as u32
+}
+
+impl RoaringBitmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 插入一个值，返回插入前该值是否不存在
+    pub fn insert(&mut self, value: u32) -> bool {
+        let (high, low) = split(value);
+        match self.containers.binary_search_by_key(&high, |(key, _)| *key) {
+            Ok(idx) => self.containers[idx].1.insert(low),
+            Err(idx) => {
+                self.containers.insert(idx, (high, Container::Array(vec![low])));
+                true
+            }
+        }
+    }
+
+    /// 删除一个值，返回该值原先是否存在
+    pub fn remove(&mut self, value: u32) -> bool {
+        let (high, low) = split(value);
+        let Ok(idx) = self.containers.binary_search_by_key(&high, |(key, _)| *key) else {
+            return false;
+        };
+        let removed = self.containers[idx].1.remove(low);
+        if self.containers[idx].1.cardinality() == 0 {
+            self.containers.remove(idx);
+        }
+        removed
+    }
+
+    pub fn contains(&self, value: u32) -> bool {
+        let (high, low) = split(value);
+        match self.containers.binary_search_by_key(&high, |(key, _)| *key) {
+            Ok(idx) => self.containers[idx].1.contains(low),
+            Err(_) => false,
+        }
+    }
+
+    /// 集合中元素的个数
+    pub fn cardinality(&self) -> u64 {
+        self.containers.iter().map(|(_, c)| c.cardinality() as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.containers.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.containers.clear();
+    }
+
+    /// 把适合的容器转换为行程编码，返回转换后行程容器的个数
+    ///
+    /// 集合运算的结果只会使用数组或位图编码，需要的话可以再调用一次本方法。
+    pub fn run_optimize(&mut self) -> usize {
+        self.containers
+            .iter_mut()
+            .map(|(_, c)| c.run_optimize())
+            .filter(|is_run| *is_run)
+            .count()
+    }
+
+    /// 并集
+    pub fn union(&self, other: &RoaringBitmap) -> RoaringBitmap {
+        let mut containers = Vec::with_capacity(self.containers.len().max(other.containers.len()));
+        let (mut i, mut j) = (0, 0);
+        while i < self.containers.len() && j < other.containers.len() {
+            let (ka, a) = &self.containers[i];
+            let (kb, b) = &other.containers[j];
+            if ka < kb {
+                containers.push((*ka, a.clone()));
+                i += 1;
+            } else if ka > kb {
+                containers.push((*kb, b.clone()));
+                j += 1;
+            } else {
+                containers.push((*ka, a.union(b)));
+                i += 1;
+                j += 1;
+            }
+        }
+        containers.extend_from_slice(&self.containers[i..]);
+        containers.extend_from_slice(&other.containers[j..]);
+        RoaringBitmap { containers }
+    }
+
+    /// 交集
+    pub fn intersection(&self, other: &RoaringBitmap) -> RoaringBitmap {
+        let mut containers = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.containers.len() && j < other.containers.len() {
+            let (ka, a) = &self.containers[i];
+            let (kb, b) = &other.containers[j];
+            if ka < kb {
+                i += 1;
+            } else if ka > kb {
+                j += 1;
+            } else {
+                let c = a.intersection(b);
+                if c.cardinality() > 0 {
+                    containers.push((*ka, c));
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+        RoaringBitmap { containers }
+    }
+
+    /// 差集，即在 self 中但不在 other 中的值
+    pub fn difference(&self, other: &RoaringBitmap) -> RoaringBitmap {
+        let mut containers = Vec::with_capacity(self.containers.len());
+        for (key, container) in &self.containers {
+            match other.containers.binary_search_by_key(key, |(k, _)| *k) {
+                Ok(idx) => {
+                    let c = container.difference(&other.containers[idx].1);
+                    if c.cardinality() > 0 {
+                        containers.push((*key, c));
+                    }
+                }
+                Err(_) => containers.push((*key, container.clone())),
+            }
+        }
+        RoaringBitmap { containers }
+    }
+
+    /// 按从小到大的顺序遍历所有值
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            containers: self.containers.iter(),
+            current: None,
+        }
+    }
+
+    /// 序列化为与平台无关的字节序列，所有整数均为小端序：
+    ///
+    /// ```text
+    /// u32  魔数 "RBM1"
+    /// u32  容器个数
+    /// 每个容器：
+    ///   u16  高 16 位
+    ///   u8   编码：0 数组，1 位图，2 行程
+    ///   u32  元素个数：数组为值的个数，位图固定为 1024（字数），行程为区间个数
+    ///   数据：数组为 u16 列表，位图为 u64 列表，行程为 (u16 start, u16 end) 列表
+    /// ```
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&SERIAL_MAGIC.to_le_bytes());
+        bytes.extend_from_slice(&(self.containers.len() as u32).to_le_bytes());
+        for (key, container) in &self.containers {
+            bytes.extend_from_slice(&key.to_le_bytes());
+            match container {
+                Container::Array(values) => {
+                    bytes.push(KIND_ARRAY);
+                    bytes.extend_from_slice(&(values.len() as u32).to_le_bytes());
+                    for value in values {
+                        bytes.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+                Container::Bitmap(words, _) => {
+                    bytes.push(KIND_BITMAP);
+                    bytes.extend_from_slice(&(BITMAP_WORDS as u32).to_le_bytes());
+                    for word in words.iter() {
+                        bytes.extend_from_slice(&word.to_le_bytes());
+                    }
+                }
+                Container::Run(runs) => {
+                    bytes.push(KIND_RUN);
+                    bytes.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+                    for run in runs {
+                        bytes.extend_from_slice(&run.start.to_le_bytes());
+                        bytes.extend_from_slice(&run.end.to_le_bytes());
+                    }
+                }
+            }
+        }
+        bytes
+    }
+
+    /// 从 [`RoaringBitmap::serialize`] 生成的字节序列还原，会校验数据的有序性和完整性
+    pub fn deserialize(bytes: &[u8]) -> Result<RoaringBitmap, DecodeError> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+        if reader.u32()? != SERIAL_MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+        let count = reader.u32()? as usize;
+        // 每个容器至少占 7 个字节，提前拦截伪造的超大容器个数
+        if count > reader.remaining() / 7 {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let mut containers: Vec<(u16, Container)> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let key = reader.u16()?;
+            if containers.last().is_some_and(|(last, _)| *last >= key) {
+                return Err(DecodeError::InvalidContainer(key));
+            }
+            let kind = reader.u8()?;
+            let len = reader.u32()? as usize;
+            let container = match kind {
+                KIND_ARRAY => {
+                    if len == 0 || len > ARRAY_MAX {
+                        return Err(DecodeError::InvalidContainer(key));
+                    }
+                    let mut values = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        values.push(reader.u16()?);
+                    }
+                    if values.windows(2).any(|w| w[0] >= w[1]) {
+                        return Err(DecodeError::InvalidContainer(key));
+                    }
+                    Container::Array(values)
+                }
+                KIND_BITMAP => {
+                    if len != BITMAP_WORDS {
+                        return Err(DecodeError::InvalidContainer(key));
+                    }
+                    let mut words = Box::new([0u64; BITMAP_WORDS]);
+                    for word in words.iter_mut() {
+                        *word = reader.u64()?;
+                    }
+                    let cardinality = words.iter().map(|w| w.count_ones() as usize).sum();
+                    if cardinality == 0 {
+                        return Err(DecodeError::InvalidContainer(key));
+                    }
+                    Container::Bitmap(words, cardinality)
+                }
+                KIND_RUN => {
+                    if len == 0 || len > reader.remaining() / 4 {
+                        return Err(DecodeError::InvalidContainer(key));
+                    }
+                    let mut runs: Vec<Run> = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        let run = Run {
+                            start: reader.u16()?,
+                            end: reader.u16()?,
+                        };
+                        // 区间必须合法、有序且互不相邻
+                        let overlaps = runs.last().is_some_and(|last| last.end as u32 + 1 >= run.start as u32);
+                        if run.start > run.end || overlaps {
+                            return Err(DecodeError::InvalidContainer(key));
+                        }
+                        runs.push(run);
+                    }
+                    Container::Run(runs)
+                }
+                _ => return Err(DecodeError::InvalidContainer(key)),
+            };
+            // serialize 只会写出 repair 之后的编码，例如不会有基数不超过 ARRAY_MAX 的位图容器
+            if !container.is_canonical() {
+                return Err(DecodeError::InvalidContainer(key));
+            }
+            containers.push((key, container));
+        }
+        if reader.remaining() > 0 {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(RoaringBitmap { containers })
+    }
+}
+
+impl PartialEq for RoaringBitmap {
+    /// 按集合内容比较，与容器的编码无关
+    fn eq(&self, other: &Self) -> bool {
+        self.cardinality() == other.cardinality() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for RoaringBitmap {}
+
+impl Debug for RoaringBitmap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl FromIterator<u32> for RoaringBitmap {
+    fn from_iter<I: IntoIterator<Item=u32>>(iter: I) -> Self {
+        let mut bitmap = RoaringBitmap::new();
+        bitmap.extend(iter);
+        bitmap
+    }
+}
+
+impl Extend<u32> for RoaringBitmap {
+    fn extend<I: IntoIterator<Item=u32>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a RoaringBitmap {
+    type Item = u32;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// RoaringBitmap 的迭代器，按从小到大的顺序返回值
+pub struct Iter<'a> {
+    containers: std::slice::Iter<'a, (u16, Container)>,
+    current: Option<(u16, ContainerIter<'a>)>,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        loop {
+            if let Some((high, iter)) = &mut self.current {
+                if let Some(low) = iter.next() {
+                    return Some(join(*high, low));
+                }
+            }
+            let (high, container) = self.containers.next()?;
+            self.current = Some((*high, container.iter()));
+        }
+    }
+}
+
+/// 反序列化失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// 数据不是以 "RBM1" 开头
+    BadMagic,
+    /// 数据提前结束
+    UnexpectedEof,
+    /// 容器内容不合法，附带容器的高 16 位
+    InvalidContainer(u16),
+    /// 全部容器读完后仍有多余的字节
+    TrailingBytes,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "not a serialized roaring bitmap"),
+            DecodeError::UnexpectedEof => write!(f, "unexpected end of roaring bitmap data"),
+            DecodeError::InvalidContainer(key) => write!(f, "invalid roaring bitmap container {key}"),
+            DecodeError::TrailingBytes => write!(f, "trailing bytes after roaring bitmap data"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl ByteReader<'_> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let chunk = self.bytes.get(self.pos..self.pos + N).ok_or(DecodeError::UnexpectedEof)?;
+        self.pos += N;
+        Ok(chunk.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use crate::roaring::{Container, DecodeError, RoaringBitmap, BITMAP_WORDS, KIND_BITMAP, KIND_RUN, SERIAL_MAGIC};
+
+    /// 简单的线性同余生成器，保证测试数据可复现
+    fn pseudo_random(seed: &mut u64) -> u32 {
+        *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*seed >> 33) as u32
+    }
+
+    #[test]
+    fn test_insert_contains_remove() {
+        let mut bitmap = RoaringBitmap::new();
+        assert!(bitmap.is_empty());
+
+        assert!(bitmap.insert(1));
+        assert!(bitmap.insert(70000));
+        assert!(bitmap.insert(u32::MAX));
+        assert!(!bitmap.insert(1));
+        assert_eq!(3, bitmap.cardinality());
+
+        assert!(bitmap.contains(70000));
+        assert!(!bitmap.contains(2));
+
+        assert!(bitmap.remove(70000));
+        assert!(!bitmap.remove(70000));
+        assert!(!bitmap.contains(70000));
+        assert_eq!(vec![1, u32::MAX], bitmap.iter().collect::<Vec<u32>>());
+
+        // 空容器会被移除
+        assert_eq!(2, bitmap.containers.len());
+    }
+
+    #[test]
+    fn test_container_switch() {
+        let mut bitmap = RoaringBitmap::new();
+        for value in 0..=4096u32 {
+            bitmap.insert(value * 2);
+        }
+        // 4097 个值，超过数组上限，转为位图
+        assert!(matches!(bitmap.containers[0].1, Container::Bitmap(_, 4097)));
+
+        bitmap.remove(0);
+        assert!(matches!(bitmap.containers[0].1, Container::Array(_)));
+        assert_eq!(4096, bitmap.cardinality());
+    }
+
+    #[test]
+    fn test_run_optimize() {
+        let mut bitmap: RoaringBitmap = (100..60000u32).chain(200000..200010).collect();
+        let expected: Vec<u32> = bitmap.iter().collect();
+
+        assert_eq!(2, bitmap.run_optimize());
+        assert!(matches!(bitmap.containers[0].1, Container::Run(ref runs) if runs.len() == 1));
+        assert_eq!(expected, bitmap.iter().collect::<Vec<u32>>());
+
+        // 在行程容器中间删除会拆分区间，插入相邻的值会重新合并
+        assert!(bitmap.remove(30000));
+        assert!(!bitmap.contains(30000));
+        assert!(matches!(bitmap.containers[0].1, Container::Run(ref runs) if runs.len() == 2));
+        assert!(bitmap.insert(30000));
+        assert!(matches!(bitmap.containers[0].1, Container::Run(ref runs) if runs.len() == 1));
+        assert!(bitmap.insert(99));
+        assert!(bitmap.contains(99));
+        assert_eq!(59900 + 10 + 1, bitmap.cardinality());
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let mut seed = 42u64;
+        let a_values: BTreeSet<u32> = (0..20000).map(|_| pseudo_random(&mut seed) % 300000).collect();
+        let b_values: BTreeSet<u32> = (0..5000).map(|_| pseudo_random(&mut seed) % 300000)
+            .chain(0..70000)
+            .collect();
+
+        let a: RoaringBitmap = a_values.iter().copied().collect();
+        let mut b: RoaringBitmap = b_values.iter().copied().collect();
+        b.run_optimize();
+
+        let union: Vec<u32> = a_values.union(&b_values).copied().collect();
+        let intersection: Vec<u32> = a_values.intersection(&b_values).copied().collect();
+        let difference: Vec<u32> = a_values.difference(&b_values).copied().collect();
+        let reverse_difference: Vec<u32> = b_values.difference(&a_values).copied().collect();
+
+        assert_eq!(union, a.union(&b).iter().collect::<Vec<u32>>());
+        assert_eq!(intersection, a.intersection(&b).iter().collect::<Vec<u32>>());
+        assert_eq!(difference, a.difference(&b).iter().collect::<Vec<u32>>());
+        assert_eq!(reverse_difference, b.difference(&a).iter().collect::<Vec<u32>>());
+        assert_eq!(union.len() as u64, a.union(&b).cardinality());
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let mut bitmap: RoaringBitmap = (0..10u32)
+            .chain((65536..65536 + 5000).step_by(2))
+            .chain(1 << 20..(1 << 20) + 3000)
+            .collect();
+        bitmap.run_optimize();
+
+        let bytes = bitmap.serialize();
+        let decoded = RoaringBitmap::deserialize(&bytes).unwrap();
+        assert_eq!(bitmap, decoded);
+        assert_eq!(bytes, decoded.serialize());
+
+        assert_eq!(RoaringBitmap::new(), RoaringBitmap::deserialize(&RoaringBitmap::new().serialize()).unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_invalid() {
+        let bytes: RoaringBitmap = [1u32, 2, 3].into_iter().collect();
+        let bytes = bytes.serialize();
+
+        assert_eq!(Err(DecodeError::BadMagic), RoaringBitmap::deserialize(b"nope0000"));
+        assert_eq!(Err(DecodeError::UnexpectedEof), RoaringBitmap::deserialize(&bytes[..bytes.len() - 1]));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(Err(DecodeError::TrailingBytes), RoaringBitmap::deserialize(&trailing));
+
+        // 把数组中的 2 改成 1，破坏有序性
+        let mut unsorted = bytes.clone();
+        let len = unsorted.len();
+        unsorted[len - 4] = 1;
+        assert_eq!(Err(DecodeError::InvalidContainer(0)), RoaringBitmap::deserialize(&unsorted));
+    }
+
+    /// 只有一个容器的序列化数据
+    fn single_container(kind: u8, len: u32, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = SERIAL_MAGIC.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn test_deserialize_non_canonical() {
+        // 只有 3 个元素的位图容器，serialize 会把它写成数组
+        let mut words = vec![0u8; BITMAP_WORDS * 8];
+        words[0] = 0b111;
+        let bitmap = single_container(KIND_BITMAP, BITMAP_WORDS as u32, &words);
+        assert_eq!(Err(DecodeError::InvalidContainer(0)), RoaringBitmap::deserialize(&bitmap));
+
+        // 3 个单元素的行程比同样内容的数组还大
+        let runs: Vec<u8> = [0u16, 0, 2, 2, 4, 4].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let sparse_runs = single_container(KIND_RUN, 3, &runs);
+        assert_eq!(Err(DecodeError::InvalidContainer(0)), RoaringBitmap::deserialize(&sparse_runs));
+
+        // 连续的区间用行程编码是规范的
+        let runs: Vec<u8> = [0u16, 99].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let dense_runs = single_container(KIND_RUN, 1, &runs);
+        let decoded = RoaringBitmap::deserialize(&dense_runs).unwrap();
+        assert_eq!(100, decoded.cardinality());
+        assert_eq!(dense_runs, decoded.serialize());
+    }
+}
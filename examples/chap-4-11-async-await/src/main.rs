@@ -6,6 +6,7 @@ use std::rc::Rc;
 use futures::{FutureExt, join, pin_mut, select, Stream, StreamExt, try_join, TryFutureExt, TryStreamExt};
 use futures::executor::block_on;
 use futures::stream::FusedStream;
+use timer_future::LocalExecutor;
 
 async fn do_something() {
     // .await 等待另一个 async 方法执行完成
@@ -263,6 +264,19 @@ async fn foo_with_no_send() {
     foo().await;
 }
 
+// 如果 NoSend 必须跨过 .await 存活，可以交给本地执行器运行，它不要求 Future 实现 Send
+fn no_send_on_local_executor() -> u8 {
+    let executor = LocalExecutor::new();
+    let handle = executor.spawn_local(async {
+        let x = NoSend::default();
+        let n = foo().await;
+        drop(x);
+        n
+    });
+    executor.run();
+    block_on(handle).unwrap()
+}
+
 // --------------------------------------------------------------------------------
 
 fn main() {
@@ -274,13 +288,20 @@ fn main() {
 
     block_on(blocks());
     block_on(move_block());
+
+    println!("no send: {}", no_send_on_local_executor());
 }
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
+
     use futures::{future, select};
     use futures::executor::block_on;
 
+    use timer_future::clock::MockClock;
+    use timer_future::TimerFuture;
+
     use crate::{foo, race_tasks};
 
     #[test]
@@ -317,6 +338,16 @@ mod test {
         assert_eq!(total, 10);
     }
 
+    #[test]
+    fn timer_with_mock_clock() {
+        // 使用 MockClock 驱动定时器，两秒的定时器不需要真的等两秒
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let timer = TimerFuture::new(Duration::new(2, 0));
+        clock.advance(Duration::new(2, 0));
+        block_on(timer);
+    }
+
     #[test]
     fn tell_async_ret_type() {
         async fn foo() -> Result<u8, String> {
@@ -0,0 +1,62 @@
+//! 单线程执行器与多线程工作窃取执行器的性能对比
+//!
+//! 运行：cargo bench -p timer-future
+
+use std::hint::black_box;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use timer_future::{block_on, new_executor_and_spawner, yield_now, ThreadPool};
+
+const TASKS: usize = 2_000;
+const STAGES: usize = 8;
+const WORK_PER_STAGE: u64 = 20_000;
+const ROUNDS: u32 = 5;
+
+/// 模拟 CPU 密集的流水线：每个阶段做一段计算后让出执行权
+async fn pipeline(seed: u64) -> u64 {
+    let mut state = seed | 1;
+    for _ in 0..STAGES {
+        for _ in 0..WORK_PER_STAGE {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+        }
+        yield_now().await;
+    }
+    black_box(state)
+}
+
+/// 运行 ROUNDS 次，返回单次的平均耗时
+fn bench<F: FnMut()>(name: &str, mut f: F) -> Duration {
+    // 预热一次
+    f();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+    println!("{name:<32} {elapsed:>12?}");
+    elapsed
+}
+
+fn main() {
+    let workers = thread::available_parallelism().map_or(4, |n| n.get());
+    println!("{TASKS} tasks x {STAGES} stages, average of {ROUNDS} rounds\n");
+
+    let single = bench("Executor::run", || {
+        let (executor, spawner) = new_executor_and_spawner();
+        let handles: Vec<_> = (0..TASKS as u64).map(|i| spawner.spawn(pipeline(i))).collect();
+        executor.run();
+        black_box(block_on(join_all(handles)));
+    });
+
+    let pool = ThreadPool::new(workers);
+    let multi = bench(&format!("ThreadPool ({workers} workers)"), || {
+        let handles: Vec<_> = (0..TASKS as u64).map(|i| pool.spawn(pipeline(i))).collect();
+        black_box(block_on(join_all(handles)));
+    });
+
+    println!("\nspeedup: {:.1}x", single.as_secs_f64() / multi.as_secs_f64());
+}
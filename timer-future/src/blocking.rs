@@ -0,0 +1,323 @@
+//! 阻塞任务的线程池
+//!
+//! 在异步任务里直接调用 `std::fs` 或者做大量计算会卡住执行器线程，其他任务都得不到 poll。
+//! [`spawn_blocking`] 把这样的闭包交给单独的线程池运行，返回的 [`JoinHandle`] 在闭包结束时唤醒等待它的任务。
+//!
+//! 线程池是弹性的：没有空闲线程时才创建新线程，直到达到上限，之后的闭包排队等待；
+//! 空闲超过一段时间的线程会自己退出，所以平时不占用线程。
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use crate::task::{join_channel, JoinError, JoinHandle, TaskId};
+
+/// 全局线程池的最大线程数
+const DEFAULT_MAX_THREADS: usize = 512;
+/// 全局线程池中线程的最长空闲时间
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// 全局的阻塞线程池，第一次使用时创建
+static GLOBAL: OnceLock<BlockingPool> = OnceLock::new();
+
+/// 设置全局阻塞线程池的最大线程数和线程的最长空闲时间
+///
+/// 需要在第一次调用 [`spawn_blocking`] 之前调用；全局线程池已经创建时不做任何修改，返回 false。
+///
+/// ```
+/// use std::time::Duration;
+///
+/// assert!(timer_future::init_blocking_pool(16, Duration::from_secs(60)));
+/// // 只能设置一次
+/// assert!(!timer_future::init_blocking_pool(32, Duration::from_secs(60)));
+/// ```
+///
+/// # Panics
+///
+/// max_threads 为 0 时 panic。
+pub fn init_blocking_pool(max_threads: usize, idle_timeout: Duration) -> bool {
+    let mut created = false;
+    GLOBAL.get_or_init(|| {
+        created = true;
+        BlockingPool::new(max_threads, idle_timeout)
+    });
+    created
+}
+
+/// 在全局的阻塞线程池中运行 f，返回等待结果的 JoinHandle
+///
+/// 全局线程池默认最多 512 个线程，线程空闲 10 秒后退出，可以用 [`init_blocking_pool`] 修改。
+/// f panic 时 JoinHandle 得到 panic 的 payload。
+///
+/// ```
+/// let (executor, spawner) = timer_future::new_executor_and_spawner();
+/// let handle = spawner.spawn(async {
+///     timer_future::spawn_blocking(|| std::env::temp_dir().exists()).await.unwrap()
+/// });
+/// executor.run();
+/// assert!(timer_future::block_on(handle).unwrap());
+/// ```
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    GLOBAL.get_or_init(|| BlockingPool::new(DEFAULT_MAX_THREADS, DEFAULT_IDLE_TIMEOUT)).spawn(f)
+}
+
+/// 运行阻塞闭包的弹性线程池
+///
+/// drop 时还在排队的闭包被丢弃，对应的 JoinHandle 得到“已取消”；正在运行的闭包不受影响，
+/// 线程在闭包结束后退出。
+pub struct BlockingPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    condvar: Condvar,
+    max_threads: usize,
+    idle_timeout: Duration,
+}
+
+struct State {
+    queue: VecDeque<Job>,
+    /// 存活的线程数
+    threads: usize,
+    /// 正在等待任务，并且还没有被分配任务的线程数
+    idle: usize,
+    /// 已经通知、但还没有醒来领取任务的线程数
+    notified: usize,
+    shutdown: bool,
+}
+
+impl BlockingPool {
+    /// 最多 max_threads 个线程，线程空闲超过 idle_timeout 后退出
+    ///
+    /// # Panics
+    ///
+    /// max_threads 为 0 时 panic。
+    pub fn new(max_threads: usize, idle_timeout: Duration) -> Self {
+        assert!(max_threads > 0, "blocking pool needs at least one thread");
+        BlockingPool {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State { queue: VecDeque::new(), threads: 0, idle: 0, notified: 0, shutdown: false }),
+                condvar: Condvar::new(),
+                max_threads,
+                idle_timeout,
+            }),
+        }
+    }
+
+    /// 在线程池中运行 f，返回等待结果的 JoinHandle
+    pub fn spawn<F, R>(&self, f: F) -> JoinHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (mut sender, handle) = join_channel(TaskId::next());
+        let job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(JoinError::panic);
+            // 写入结果并唤醒等待的任务
+            sender.complete(result);
+        });
+
+        let mut state = self.inner.state.lock().unwrap();
+        state.queue.push_back(job);
+        if state.idle > 0 {
+            // 把任务分配给一个空闲线程
+            state.idle -= 1;
+            state.notified += 1;
+            self.inner.condvar.notify_one();
+        } else if state.threads < self.inner.max_threads {
+            state.threads += 1;
+            drop(state);
+            let inner = self.inner.clone();
+            let spawned =
+                thread::Builder::new().name("blocking-worker".to_string()).spawn(move || inner.run_worker());
+            if let Err(err) = spawned {
+                let mut state = self.inner.state.lock().unwrap();
+                state.threads -= 1;
+                // 没有存活的线程时，排队的闭包不会再被运行，丢弃它们让 JoinHandle 得到“已取消”
+                let orphaned = if state.threads == 0 { std::mem::take(&mut state.queue) } else { VecDeque::new() };
+                drop(state);
+                drop(orphaned);
+                panic!("failed to spawn blocking worker thread: {err}");
+            }
+        }
+        // 否则线程数已经达到上限，任务排队等待正在运行的线程
+        handle
+    }
+
+    /// 存活的线程数
+    pub fn thread_count(&self) -> usize {
+        self.inner.state.lock().unwrap().threads
+    }
+}
+
+impl Inner {
+    fn run_worker(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            if state.shutdown {
+                break;
+            }
+
+            state.idle += 1;
+            let mut timed_out = false;
+            loop {
+                let (next, result) = self.condvar.wait_timeout(state, self.idle_timeout).unwrap();
+                state = next;
+                if state.notified > 0 {
+                    // 被分配了任务，spawn 已经把 idle 减掉了
+                    state.notified -= 1;
+                    break;
+                }
+                if state.shutdown || result.timed_out() {
+                    state.idle -= 1;
+                    timed_out = result.timed_out();
+                    break;
+                }
+                // 虚假唤醒，继续等待
+            }
+            if timed_out && state.queue.is_empty() {
+                break;
+            }
+        }
+        state.threads -= 1;
+    }
+}
+
+impl Drop for BlockingPool {
+    fn drop(&mut self) {
+        let queued = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.shutdown = true;
+            std::mem::take(&mut state.queue)
+        };
+        self.inner.condvar.notify_all();
+        // 在锁外丢弃排队的闭包，JoinHandle 会得到“已取消”
+        drop(queued);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Barrier};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::future::join_all;
+
+    use crate::blocking::BlockingPool;
+    use crate::{block_on, new_executor_and_spawner, spawn_blocking, TimerFuture};
+
+    #[test]
+    fn test_blocking_does_not_stall_executor() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let started = Instant::now();
+        let blocking = spawner.spawn(async {
+            spawn_blocking(|| {
+                thread::sleep(Duration::from_millis(50));
+                42
+            })
+            .await
+            .unwrap()
+        });
+        // 阻塞闭包运行期间，执行器上的定时器任务照常完成
+        let timer = spawner.spawn(async move {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            started.elapsed()
+        });
+        executor.run();
+        assert_eq!(42, block_on(blocking).unwrap());
+        assert!(block_on(timer).unwrap() < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_threads_limited_and_reused() {
+        let pool = BlockingPool::new(2, Duration::from_secs(10));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let running = running.clone();
+                let max_running = max_running.clone();
+                pool.spawn(move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                })
+            })
+            .collect();
+        let results: Vec<_> = block_on(join_all(handles)).into_iter().map(Result::unwrap).collect();
+        assert_eq!((0..8).collect::<Vec<_>>(), results);
+        assert_eq!(2, max_running.load(Ordering::SeqCst));
+        assert_eq!(2, pool.thread_count());
+
+        // 空闲线程会被复用，不会创建新线程
+        block_on(pool.spawn(|| ())).unwrap();
+        assert_eq!(2, pool.thread_count());
+    }
+
+    #[test]
+    fn test_idle_threads_exit() {
+        let pool = BlockingPool::new(4, Duration::from_millis(20));
+        let barrier = Arc::new(Barrier::new(4));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let barrier = barrier.clone();
+                pool.spawn(move || {
+                    barrier.wait();
+                })
+            })
+            .collect();
+        block_on(join_all(handles));
+        assert_eq!(4, pool.thread_count());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.thread_count() > 0 {
+            assert!(Instant::now() < deadline, "idle threads did not exit");
+            thread::sleep(Duration::from_millis(10));
+        }
+        // 线程全部退出后还能继续使用
+        assert_eq!(3, block_on(pool.spawn(|| 1 + 2)).unwrap());
+    }
+
+    #[test]
+    fn test_panic_and_cancellation() {
+        let pool = BlockingPool::new(1, Duration::from_secs(10));
+        let panicked = pool.spawn(|| panic!("blocking panic"));
+        assert!(block_on(panicked).unwrap_err().is_panic());
+
+        // 唯一的线程被占住，后面的闭包只能排队，线程池被 drop 时它们被取消
+        let barrier = Arc::new(Barrier::new(2));
+        let (started_tx, started_rx) = mpsc::channel();
+        let busy = {
+            let barrier = barrier.clone();
+            pool.spawn(move || {
+                started_tx.send(()).unwrap();
+                barrier.wait();
+            })
+        };
+        started_rx.recv().unwrap();
+        let queued = pool.spawn(|| ());
+        drop(pool);
+        assert!(block_on(queued).unwrap_err().is_cancelled());
+        barrier.wait();
+        block_on(busy).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+//! 时钟
+//!
+//! 定时器驱动通过 [`Clock`] 获取当前时间。默认驱动使用 [`SystemClock`]；
+//! 测试中可以使用 [`MockClock`]，时间只有在调用 [`MockClock::advance`] 时才会前进，
+//! 到期的定时器会在 `advance` 返回前被唤醒，整个过程不需要真正地睡眠。
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::driver::{EnterGuard, Handle};
+
+/// 时钟，返回单调递增的当前时间
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+/// 系统的单调时钟
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// 可以手动拨动的时钟，附带一个使用该时钟的定时器驱动
+///
+/// ```
+/// use std::time::Duration;
+/// use timer_future::clock::MockClock;
+/// use timer_future::TimerFuture;
+///
+/// let clock = MockClock::new();
+/// let _guard = clock.enter();
+/// // 在 guard 存活期间创建的定时器都由 clock 驱动
+/// let timer = TimerFuture::new(Duration::from_secs(2));
+/// clock.advance(Duration::from_secs(2));
+/// futures::executor::block_on(timer);
+/// ```
+#[derive(Clone)]
+pub struct MockClock {
+    time: Arc<MockTime>,
+    handle: Handle,
+}
+
+/// MockClock 的时间，单独拆出来避免 MockClock 和驱动互相持有
+struct MockTime {
+    now: Mutex<Instant>,
+}
+
+impl Clock for MockTime {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
+
+impl MockClock {
+    /// 创建一个从当前系统时间开始的模拟时钟
+    pub fn new() -> Self {
+        let time = Arc::new(MockTime {
+            now: Mutex::new(Instant::now()),
+        });
+        let handle = Handle::with_clock(time.clone());
+        MockClock { time, handle }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.time.now()
+    }
+
+    /// 使用该时钟的定时器驱动
+    pub fn handle(&self) -> Handle {
+        self.handle.clone()
+    }
+
+    /// 等价于 `self.handle().enter()`，让当前线程新建的定时器使用该时钟
+    pub fn enter(&self) -> EnterGuard {
+        self.handle.enter()
+    }
+
+    /// 让时间前进 duration，并唤醒所有因此到期的定时器
+    pub fn advance(&self, duration: Duration) {
+        *self.time.now.lock().unwrap() += duration;
+        self.handle.fire_expired();
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures::executor::LocalPool;
+    use futures::task::LocalSpawnExt;
+
+    use crate::clock::MockClock;
+    use crate::TimerFuture;
+
+    #[test]
+    fn test_advance_fires_due_timers() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let mut pool = LocalPool::new();
+        let fired = Rc::new(RefCell::new(Vec::new()));
+
+        for secs in [3, 1, 2] {
+            let fired = fired.clone();
+            pool.spawner()
+                .spawn_local(async move {
+                    TimerFuture::new(Duration::from_secs(secs)).await;
+                    fired.borrow_mut().push(secs);
+                })
+                .unwrap();
+        }
+        pool.run_until_stalled();
+        assert!(fired.borrow().is_empty());
+
+        clock.advance(Duration::from_millis(999));
+        pool.run_until_stalled();
+        assert!(fired.borrow().is_empty());
+
+        // 正好到期的定时器会被唤醒
+        clock.advance(Duration::from_millis(1));
+        pool.run_until_stalled();
+        assert_eq!(vec![1], *fired.borrow());
+
+        // 一次前进跨过多个截止时间，所有到期的定时器都会被唤醒
+        clock.advance(Duration::from_secs(5));
+        pool.run_until_stalled();
+        assert_eq!(vec![1, 2, 3], *fired.borrow());
+    }
+
+    #[test]
+    fn test_enter_guard_restores_previous() {
+        let outer = MockClock::new();
+        let inner = MockClock::new();
+        let _outer_guard = outer.enter();
+        {
+            let _inner_guard = inner.enter();
+            inner.advance(Duration::from_secs(10));
+            let timer = TimerFuture::new(Duration::from_secs(1));
+            assert_eq!(inner.now() + Duration::from_secs(1), timer.deadline());
+        }
+        outer.advance(Duration::from_secs(1));
+        let timer = TimerFuture::new(Duration::from_secs(1));
+        assert_eq!(outer.now() + Duration::from_secs(1), timer.deadline());
+    }
+}
@@ -0,0 +1,429 @@
+//! 定时器驱动
+//!
+//! 所有 [`crate::TimerFuture`] 共用一个后台线程：定时器创建时把截止时间登记到驱动的最小堆里，
+//! 后台线程只需要睡到最近的截止时间，唤醒所有到期的定时器，然后继续睡到下一个截止时间。
+//! 这样无论有多少个定时器，都只占用一个系统线程。
+//!
+//! 在 Linux 上后台线程用 timerfd 和 `epoll_wait` 等待：timerfd 总是被设置为最近的截止时间，
+//! 登记更早的截止时间时直接重新设置它，到期后的误差只剩下线程被唤醒的延迟。timerfd 不可用时，
+//! 以及在其他平台上，退回到在条件变量上带超时地等待。
+//!
+//! 驱动通过 [`Clock`] 获取当前时间。使用 [`MockClock`](crate::clock::MockClock) 的驱动没有后台线程，
+//! 时间只在测试调用 `advance` 时前进，到期的定时器也在那时被唤醒。
+
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::marker::PhantomData;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
+use std::task::{Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::TimerFuture;
+
+#[cfg(target_os = "linux")]
+mod timerfd;
+
+thread_local! {
+    /// 当前线程通过 Handle::enter 指定的驱动
+    static CURRENT: RefCell<Option<Handle>> = const { RefCell::new(None) };
+}
+
+/// 定时器驱动的句柄，克隆开销很小，所有克隆共享同一个驱动
+#[derive(Clone)]
+pub struct Handle {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    /// 驱动使用的时钟
+    clock: Arc<dyn Clock>,
+    state: Mutex<State>,
+    /// 后台线程等待下一个截止时间的方式
+    park: Park,
+}
+
+/// 后台线程等待下一个截止时间的方式
+enum Park {
+    /// 在条件变量上带超时地等待，登记了更早的截止时间时通知它提前醒来
+    Condvar(Condvar),
+    /// 在 epoll 上等待一个总是被设置为最近截止时间的 timerfd
+    #[cfg(target_os = "linux")]
+    Timerfd(timerfd::TimerFd),
+}
+
+/// 堆中的记录不超过这个数时不重建，避免定时器很少时反复重建
+const COMPACT_MIN_HEAP: usize = 64;
+
+/// 驱动的共享状态
+struct State {
+    /// 所有未注销的定时器
+    entries: HashMap<u64, Entry>,
+    /// 按截止时间排列的最小堆，堆顶就是后台线程下一次需要醒来的时间
+    heap: BinaryHeap<Reverse<(Instant, u64)>>,
+    next_id: u64,
+}
+
+/// 单个定时器的状态
+struct Entry {
+    /// 当前的截止时间，重置后堆中旧的记录会因为与它不一致而被跳过
+    deadline: Instant,
+    /// 是否已经到期
+    fired: bool,
+    /// 到期时用来通知对应的任务
+    waker: Option<Waker>,
+}
+
+impl Handle {
+    /// 进程内共享的默认驱动，第一次使用时启动后台线程
+    pub fn global() -> Handle {
+        static GLOBAL: OnceLock<Handle> = OnceLock::new();
+        GLOBAL.get_or_init(Handle::spawn).clone()
+    }
+
+    /// 当前线程使用的驱动：优先使用 [`Handle::enter`] 指定的驱动，否则使用默认驱动
+    pub fn current() -> Handle {
+        CURRENT.with(|current| current.borrow().clone()).unwrap_or_else(Handle::global)
+    }
+
+    /// 在返回的 guard 存活期间，让当前线程新建的定时器都使用这个驱动
+    pub fn enter(&self) -> EnterGuard {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        EnterGuard { previous, _not_send: PhantomData }
+    }
+
+    /// 驱动时钟的当前时间
+    pub fn now(&self) -> Instant {
+        self.inner.clock.now()
+    }
+
+    /// 创建一个在 duration 后到期的定时器
+    pub fn sleep(&self, duration: Duration) -> TimerFuture {
+        self.sleep_until(self.now() + duration)
+    }
+
+    /// 创建一个在 deadline 到期的定时器
+    pub fn sleep_until(&self, deadline: Instant) -> TimerFuture {
+        let id = self.register(deadline);
+        TimerFuture { handle: self.clone(), id }
+    }
+
+    /// 创建一个使用系统时钟的驱动，并启动它的后台线程；Linux 上优先使用 timerfd
+    fn spawn() -> Handle {
+        #[cfg(target_os = "linux")]
+        if let Ok(timer) = timerfd::TimerFd::new() {
+            return Handle::spawn_with(Park::Timerfd(timer));
+        }
+        Handle::spawn_with(Park::Condvar(Condvar::new()))
+    }
+
+    fn spawn_with(park: Park) -> Handle {
+        let handle = Handle::new(Arc::new(SystemClock), park);
+        let inner = handle.inner.clone();
+        thread::Builder::new()
+            .name("timer-driver".to_string())
+            .spawn(move || inner.run())
+            .expect("无法启动定时器线程");
+        handle
+    }
+
+    /// 创建一个没有后台线程的驱动，需要由调用方通过 [`Handle::fire_expired`] 推动
+    pub(crate) fn with_clock(clock: Arc<dyn Clock>) -> Handle {
+        Handle::new(clock, Park::Condvar(Condvar::new()))
+    }
+
+    fn new(clock: Arc<dyn Clock>, park: Park) -> Handle {
+        Handle {
+            inner: Arc::new(Inner {
+                clock,
+                state: Mutex::new(State {
+                    entries: HashMap::new(),
+                    heap: BinaryHeap::new(),
+                    next_id: 0,
+                }),
+                park,
+            }),
+        }
+    }
+
+    /// 唤醒所有按驱动时钟已经到期的定时器
+    pub(crate) fn fire_expired(&self) {
+        let wakers = self.inner.lock().fire_expired(self.now());
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// 登记一个截止时间，返回定时器的 id
+    pub(crate) fn register(&self, deadline: Instant) -> u64 {
+        let mut state = self.inner.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.entries.insert(id, Entry { deadline, fired: false, waker: None });
+        self.inner.schedule(&mut state, id, deadline);
+        id
+    }
+
+    /// 修改定时器的截止时间，已经到期的定时器会重新变为未到期
+    pub(crate) fn reset(&self, id: u64, deadline: Instant) {
+        let mut state = self.inner.lock();
+        let entry = state.entries.get_mut(&id).expect("定时器已注销");
+        entry.deadline = deadline;
+        entry.fired = false;
+        // 旧的堆记录不删除，弹出时发现截止时间对不上就会跳过；过期记录太多时整体重建
+        self.inner.schedule(&mut state, id, deadline);
+        state.compact();
+    }
+
+    /// 定时器当前的截止时间
+    pub(crate) fn deadline(&self, id: u64) -> Instant {
+        self.inner.lock().entries.get(&id).expect("定时器已注销").deadline
+    }
+
+    /// 检查定时器是否到期，未到期则保存 waker，等到期时唤醒
+    pub(crate) fn poll_elapsed(&self, id: u64, waker: &Waker) -> Poll<()> {
+        let mut state = self.inner.lock();
+        let entry = state.entries.get_mut(&id).expect("定时器已注销");
+        // 截止时间已过但后台线程还没来得及处理（例如刚被重置到过去的时间），直接视为到期
+        if entry.fired || entry.deadline <= self.inner.clock.now() {
+            entry.fired = true;
+            return Poll::Ready(());
+        }
+        // 任务可能在执行器的不同任务间移动，因此每次 poll 都要检查 waker 是否还是同一个
+        match &entry.waker {
+            Some(old) if old.will_wake(waker) => {}
+            _ => entry.waker = Some(waker.clone()),
+        }
+        Poll::Pending
+    }
+
+    /// 注销定时器，注销后驱动不会再唤醒它的 waker
+    pub(crate) fn deregister(&self, id: u64) {
+        // 堆中对应的记录不在这里删除，后台线程弹出时发现 id 不存在会直接跳过
+        let mut state = self.inner.lock();
+        state.entries.remove(&id);
+        state.compact();
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // 持有锁的一方即使 panic 也不会让状态处于不一致的中间态，可以继续使用
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 把截止时间放入堆中
+    fn schedule(&self, state: &mut State, id: u64, deadline: Instant) {
+        // 只有新的截止时间比堆顶更早时，后台线程才需要提前醒来重新计算睡眠时间
+        let earliest = state.heap.peek().is_none_or(|Reverse((first, _))| deadline < *first);
+        state.heap.push(Reverse((deadline, id)));
+        if earliest {
+            match &self.park {
+                Park::Condvar(condvar) => condvar.notify_one(),
+                // 持有状态锁时设置，与后台线程的设置不会交错，timerfd 总是对应堆顶的截止时间
+                #[cfg(target_os = "linux")]
+                Park::Timerfd(timer) => {
+                    timer.arm(Some(deadline.saturating_duration_since(self.clock.now()))).expect("设置 timerfd 失败")
+                }
+            }
+        }
+    }
+
+    /// 后台线程的主循环
+    fn run(&self) {
+        match &self.park {
+            Park::Condvar(condvar) => self.run_condvar(condvar),
+            #[cfg(target_os = "linux")]
+            Park::Timerfd(timer) => self.run_timerfd(timer),
+        }
+    }
+
+    fn run_condvar(&self, condvar: &Condvar) {
+        let mut state = self.lock();
+        loop {
+            let now = self.clock.now();
+            let wakers = state.fire_expired(now);
+            if !wakers.is_empty() {
+                // 在锁外唤醒，waker 里可能会再次访问驱动
+                drop(state);
+                wakers.into_iter().for_each(Waker::wake);
+                state = self.lock();
+                continue;
+            }
+
+            state = match state.heap.peek() {
+                Some(Reverse((deadline, _))) => {
+                    let timeout = deadline.saturating_duration_since(now);
+                    condvar.wait_timeout(state, timeout).unwrap_or_else(|e| e.into_inner()).0
+                }
+                None => condvar.wait(state).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn run_timerfd(&self, timer: &timerfd::TimerFd) {
+        loop {
+            let wakers = {
+                let mut state = self.lock();
+                let now = self.clock.now();
+                let wakers = state.fire_expired(now);
+                // 为新的堆顶重新设置 timerfd，堆空时停止它
+                let next = state.heap.peek().map(|Reverse((deadline, _))| deadline.saturating_duration_since(now));
+                timer.arm(next).expect("设置 timerfd 失败");
+                wakers
+            };
+            // 在锁外唤醒，waker 里可能会再次访问驱动
+            wakers.into_iter().for_each(Waker::wake);
+            timer.wait().expect("等待 timerfd 失败");
+        }
+    }
+}
+
+impl State {
+    /// 把所有截止时间不晚于 now 的定时器标记为到期，返回需要唤醒的 waker
+    /// 堆中失效的记录（已注销或者被重置过的定时器）超过有效记录时，只用有效的定时器重建堆
+    ///
+    /// 大量短命的定时器（例如很快完成的 `timeout`）或者频繁的重置会留下很多失效记录，
+    /// 不清理的话它们要一直留到原来的截止时间才会被弹出。重建的开销分摊到每次失效上是常数。
+    fn compact(&mut self) {
+        if self.heap.len() <= COMPACT_MIN_HEAP || self.heap.len() <= 2 * self.entries.len() {
+            return;
+        }
+        // 已经到期的定时器在堆里没有记录
+        self.heap = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| !entry.fired)
+            .map(|(id, entry)| Reverse((entry.deadline, *id)))
+            .collect();
+    }
+
+    fn fire_expired(&mut self, now: Instant) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while let Some(Reverse((deadline, id))) = self.heap.peek().copied() {
+            if deadline > now {
+                break;
+            }
+            self.heap.pop();
+            // 已注销或者被重置过的定时器直接跳过
+            if let Some(entry) = self.entries.get_mut(&id).filter(|entry| entry.deadline == deadline) {
+                entry.fired = true;
+                wakers.extend(entry.waker.take());
+            }
+        }
+        wakers
+    }
+}
+
+/// [`Handle::enter`] 返回的 guard，drop 时恢复之前的驱动
+#[must_use]
+pub struct EnterGuard {
+    previous: Option<Handle>,
+    /// guard 只能在创建它的线程上 drop
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for EnterGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Condvar;
+    use std::time::{Duration, Instant};
+
+    use futures::FutureExt;
+
+    use crate::block_on;
+    use crate::clock::MockClock;
+    use crate::driver::{Handle, Park, COMPACT_MIN_HEAP};
+
+    /// 依次等待若干个定时器，返回每个定时器实际到期时间比截止时间晚了多少，从小到大排列
+    fn measure_slop(handle: &Handle) -> Vec<Duration> {
+        let mut slop: Vec<Duration> = (1..=40)
+            .map(|i| {
+                let deadline = Instant::now() + Duration::from_micros(250 * i);
+                block_on(handle.sleep_until(deadline));
+                Instant::now().duration_since(deadline)
+            })
+            .collect();
+        slop.sort();
+        slop
+    }
+
+    fn report(name: &str, slop: &[Duration]) {
+        println!(
+            "{name} timer slop: min={:?} p50={:?} p90={:?} max={:?}",
+            slop[0],
+            slop[slop.len() / 2],
+            slop[slop.len() * 9 / 10],
+            slop[slop.len() - 1]
+        );
+    }
+
+    #[test]
+    fn test_condvar_driver_slop() {
+        let handle = Handle::spawn_with(Park::Condvar(Condvar::new()));
+        let slop = measure_slop(&handle);
+        report("condvar", &slop);
+        // 共享的测试机上调度延迟不可控，只检查中位数在合理范围内
+        assert!(slop[slop.len() / 2] < Duration::from_millis(20));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_timerfd_driver_slop() {
+        let handle = Handle::spawn_with(Park::Timerfd(super::timerfd::TimerFd::new().unwrap()));
+        let slop = measure_slop(&handle);
+        report("timerfd", &slop);
+        assert!(slop[slop.len() / 2] < Duration::from_millis(20));
+    }
+
+    /// 登记更早的截止时间时，timerfd 被重新设置，后台线程不会一直等到原来较晚的截止时间
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_timerfd_rearmed_for_earlier_deadline() {
+        let handle = Handle::spawn_with(Park::Timerfd(super::timerfd::TimerFd::new().unwrap()));
+        let _late = handle.sleep(Duration::from_secs(60));
+        let started = Instant::now();
+        block_on(handle.sleep(Duration::from_millis(5)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // 堆顶的定时器被注销后，后台线程在它原来的截止时间醒来，跳过它，再为下一个截止时间重新设置
+        let first = handle.sleep(Duration::from_millis(5));
+        drop(first);
+        let started = Instant::now();
+        block_on(handle.sleep(Duration::from_millis(20)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_stale_heap_entries_compacted() {
+        let clock = MockClock::new();
+        let handle = clock.handle();
+        let heap_len = || handle.inner.lock().heap.len();
+
+        // 反复重置同一个定时器，每次都会留下一条失效记录
+        let mut timer = handle.sleep(Duration::from_secs(60));
+        for i in 1..=1000 {
+            timer.reset(clock.now() + Duration::from_secs(60 + i));
+        }
+        assert!(heap_len() <= COMPACT_MIN_HEAP + 1, "{}", heap_len());
+
+        // 大量很快被丢弃的定时器
+        for _ in 0..1000 {
+            drop(handle.sleep(Duration::from_secs(60)));
+        }
+        assert!(heap_len() <= COMPACT_MIN_HEAP + 1, "{}", heap_len());
+
+        // 重建之后定时器仍然在最后一次重置的截止时间到期
+        clock.advance(Duration::from_secs(1059));
+        assert_eq!(None, (&mut timer).now_or_never());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Some(()), timer.now_or_never());
+        assert_eq!(0, heap_len());
+    }
+}
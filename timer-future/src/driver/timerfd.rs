@@ -0,0 +1,70 @@
+//! 基于 timerfd 的等待方式
+//!
+//! timerfd 始终被设置为最近的截止时间。登记了更早的截止时间的线程直接重新设置它，
+//! 后台线程在 `epoll_wait` 上等待它到期，不需要再额外通知。timerfd 的精度是纳秒，
+//! 到期后内核立即唤醒等待的线程，不受条件变量超时换算和调度粒度的影响。
+
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Duration;
+
+use crate::reactor::cvt;
+
+pub(super) struct TimerFd {
+    epoll: OwnedFd,
+    timer: OwnedFd,
+}
+
+impl TimerFd {
+    pub(super) fn new() -> io::Result<TimerFd> {
+        let timer =
+            cvt(unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC) })?;
+        // SAFETY: timerfd_create 成功返回的描述符归我们所有
+        let timer = unsafe { OwnedFd::from_raw_fd(timer) };
+        let epoll = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        // SAFETY: epoll_create1 成功返回的描述符归我们所有
+        let epoll = unsafe { OwnedFd::from_raw_fd(epoll) };
+        let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: 0 };
+        cvt(unsafe { libc::epoll_ctl(epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, timer.as_raw_fd(), &mut event) })?;
+        Ok(TimerFd { epoll, timer })
+    }
+
+    /// 让定时器在 timeout 之后到期，覆盖之前的设置；None 表示停止定时器
+    pub(super) fn arm(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let value = match timeout {
+            // it_value 全为 0 表示停止定时器，已经到期的截止时间用 1 纳秒代替
+            Some(timeout) => {
+                let timeout = timeout.max(Duration::from_nanos(1));
+                libc::timespec {
+                    tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+                    tv_nsec: timeout.subsec_nanos() as libc::c_long,
+                }
+            }
+            None => libc::timespec { tv_sec: 0, tv_nsec: 0 },
+        };
+        let spec = libc::itimerspec { it_interval: libc::timespec { tv_sec: 0, tv_nsec: 0 }, it_value: value };
+        cvt(unsafe { libc::timerfd_settime(self.timer.as_raw_fd(), 0, &spec, std::ptr::null_mut()) })?;
+        Ok(())
+    }
+
+    /// 等待定时器到期，并清除到期计数
+    pub(super) fn wait(&self) -> io::Result<()> {
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        loop {
+            match cvt(unsafe { libc::epoll_wait(self.epoll.as_raw_fd(), &mut event, 1, -1) }) {
+                Ok(_) => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        // 读出到期次数，让 timerfd 回到未就绪状态；定时器在此之前被重新设置时计数已经清零，会返回 WouldBlock
+        let mut expirations = 0u64;
+        let result = unsafe {
+            libc::read(self.timer.as_raw_fd(), &mut expirations as *mut u64 as *mut libc::c_void, size_of::<u64>())
+        };
+        match cvt(result as i32) {
+            Err(err) if err.kind() != io::ErrorKind::WouldBlock => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,762 @@
+//! 单线程执行器
+//!
+//! [`Executor`] 从任务通道中接收被唤醒的任务并 poll 它们，[`Spawner`] 负责把新的 Future 包装成任务放进通道。
+//! 两者由 [`new_executor_and_spawner`] 成对创建。
+//!
+//! 任务 panic 不会影响执行器和其他任务：panic 被捕获后交给任务的 JoinHandle，
+//! 执行器再调用 [`Executor::set_panic_hook`] 设置的回调，或者按 [`Executor::set_abort_on_panic`] 终止进程。
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, Thread};
+use std::time::Instant;
+
+use futures::future::poll_fn;
+use futures::task::{waker, ArcWake};
+
+use crate::metrics::Instrument;
+use crate::task::{joinable, JoinHandle, Schedule, Task, TaskId, TaskInfo, TaskState};
+
+/// 任务 panic 时调用的回调，参数是任务的信息和 panic 信息
+type PanicHook = Box<dyn Fn(&TaskInfo, &str) + Send + Sync>;
+
+/// 执行器，负责从通道接受任务并执行
+pub struct Executor {
+    /// 运行队列的接收端，任务附带进入队列的时间；None 只用来唤醒阻塞在 `run` 中的执行器
+    ready_queue: Receiver<Option<(Arc<Task>, Instant)>>,
+    shared: Arc<Shared>,
+    panic_hook: Option<PanicHook>,
+    abort_on_panic: bool,
+}
+
+/// 执行器和 Spawner 共享的状态
+struct Shared {
+    /// 尚未完成的任务数
+    live_tasks: AtomicUsize,
+    /// `try_spawn` 和 `spawn_wait` 允许的最大未完成任务数
+    max_tasks: usize,
+    /// 执行器是否已经被 drop
+    closed: AtomicBool,
+    /// 在 `spawn_wait` 中等待空位的任务
+    waiters: Mutex<Vec<Waker>>,
+    /// 所有未完成的任务，用于 `dump`；不持有任务本身，任务的 Future 被丢弃时移除
+    tasks: Mutex<HashMap<TaskId, Weak<Task>>>,
+    /// 运行队列中的任务数
+    queued: AtomicUsize,
+    /// 由 `Executor::set_instrument` 设置
+    instrument: OnceLock<Arc<dyn Instrument>>,
+}
+
+impl Shared {
+    /// 为一个新任务占一个位置
+    fn try_reserve(&self) -> Result<(), SpawnError> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(SpawnError::Shutdown);
+        }
+        self.live_tasks
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |live| (live < self.max_tasks).then_some(live + 1))
+            .map(|_| ())
+            .map_err(|_| SpawnError::AtCapacity)
+    }
+
+    fn poll_reserve(&self, cx: &mut Context<'_>) -> Poll<Result<(), SpawnError>> {
+        match self.try_reserve() {
+            Err(SpawnError::AtCapacity) => {}
+            result => return Poll::Ready(result),
+        }
+        self.waiters.lock().unwrap().push(cx.waker().clone());
+        // 登记之后再试一次，避免在两次检查之间有任务完成而错过唤醒
+        match self.try_reserve() {
+            Err(SpawnError::AtCapacity) => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+
+    /// 释放一个位置，并唤醒所有等待的 Spawner，让它们重新竞争；返回是否已经没有未完成的任务
+    fn release(&self) -> bool {
+        let last = self.live_tasks.fetch_sub(1, Ordering::AcqRel) == 1;
+        self.wake_waiters();
+        last
+    }
+
+    /// 所有存活任务的状态，按 id 排序
+    fn dump(&self) -> Vec<TaskInfo> {
+        let tasks: Vec<Arc<Task>> = self.tasks.lock().unwrap().values().filter_map(Weak::upgrade).collect();
+        // 在锁外读取并丢弃升级得到的引用：它可能是任务的最后一个引用，丢弃时会回来删除 tasks 中的条目
+        let mut infos: Vec<TaskInfo> =
+            tasks.iter().map(|task| task.info()).filter(|info| info.state != TaskState::Complete).collect();
+        infos.sort_by_key(|info| info.id);
+        infos
+    }
+
+    fn wake_waiters(&self) {
+        let waiters = std::mem::take(&mut *self.waiters.lock().unwrap());
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+}
+
+impl Executor {
+    /// 运行所有任务，直到已生成的任务全部完成
+    ///
+    /// 不需要先 drop 掉 Spawner：只要还有未完成的任务，执行器就会继续等待它们被唤醒，
+    /// 包括运行期间由任务自己生成的新任务。
+    pub fn run(&self) {
+        while self.shared.live_tasks.load(Ordering::Acquire) > 0 {
+            // 所有 Spawner 和任务都被 drop 之后，通道会关闭
+            match self.ready_queue.recv() {
+                Ok(Some((task, scheduled_at))) => self.run_task(task, scheduled_at),
+                // 最后一个任务在别处被丢弃，回到循环开头重新检查
+                Ok(None) => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// 运行所有已经就绪的任务，直到运行队列为空后返回，不等待还没有被唤醒的任务
+    ///
+    /// 适合配合 [`MockClock`](crate::clock::MockClock) 一步一步地推动任务：每次拨动时钟后调用一次。
+    pub fn run_until_stalled(&self) {
+        while let Ok(message) = self.ready_queue.try_recv() {
+            if let Some((task, scheduled_at)) = message {
+                self.run_task(task, scheduled_at);
+            }
+        }
+    }
+
+    fn run_task(&self, task: Arc<Task>, scheduled_at: Instant) {
+        self.shared.queued.fetch_sub(1, Ordering::AcqRel);
+        let instrument = self.shared.instrument.get();
+        let started = Instant::now();
+        if let Some(instrument) = instrument {
+            instrument.on_poll_start(task.id(), started.saturating_duration_since(scheduled_at));
+        }
+        // 每个任务在队列中最多出现一次，poll 时不需要加锁
+        let poll = task.run();
+        if let Some(instrument) = instrument {
+            instrument.on_poll_end(task.id(), started.elapsed());
+        }
+        // 位置在 Future 被丢弃时由 ReleaseOnDrop 释放，这里不需要处理
+        if let Poll::Ready(result) = poll {
+            if let Some(instrument) = instrument {
+                instrument.on_complete(task.id());
+            }
+            if let Err(message) = result {
+                self.on_panic(&task, &message);
+            }
+        }
+    }
+
+    /// 设置接收执行器事件的 [`Instrument`]，例如内置的 [`MetricsCollector`](crate::metrics::MetricsCollector)
+    ///
+    /// 只能设置一次，应该在生成任务之前设置，否则之前生成的任务的事件会缺失一部分。
+    ///
+    /// # Panics
+    ///
+    /// 已经设置过时 panic。
+    pub fn set_instrument(&self, instrument: Arc<dyn Instrument>) {
+        if self.shared.instrument.set(instrument).is_err() {
+            panic!("executor instrument is already set");
+        }
+    }
+
+    /// 设置任务 panic 时调用的回调，参数是任务的信息（状态为 `Panicked`）和 panic 信息
+    ///
+    /// 回调在执行器线程上、panic 的任务结束之后调用。panic 信息本身已经由标准库的 panic hook 打印过，
+    /// 这里适合做记录指标、告警之类的事情。
+    pub fn set_panic_hook(&mut self, hook: impl Fn(&TaskInfo, &str) + Send + Sync + 'static) {
+        self.panic_hook = Some(Box::new(hook));
+    }
+
+    /// 设置任务 panic 时是否终止整个进程，默认为 false
+    ///
+    /// 对于任务 panic 就说明状态已经不可信的程序，这比让其余任务继续运行更安全。终止前会先调用 panic 回调。
+    pub fn set_abort_on_panic(&mut self, abort: bool) {
+        self.abort_on_panic = abort;
+    }
+
+    fn on_panic(&self, task: &Task, message: &str) {
+        let info = task.info();
+        if let Some(hook) = &self.panic_hook {
+            hook(&info, message);
+        }
+        if self.abort_on_panic {
+            eprintln!("{info} panicked, aborting: {message}");
+            std::process::abort();
+        }
+    }
+}
+
+impl Executor {
+    /// 列出所有存活的任务，包括它们的状态、poll 次数、poll 耗时和最近一次被唤醒的时间
+    pub fn dump(&self) -> Vec<TaskInfo> {
+        self.shared.dump()
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.wake_waiters();
+    }
+}
+
+/// 负责创建新的 Future 然后发送到任务通道
+#[derive(Clone)]
+pub struct Spawner {
+    task_sender: Arc<ReadyQueue>,
+    shared: Arc<Shared>,
+}
+
+impl Spawner {
+    /// spawn 用于生成 Future 并放入任务通道，返回的 JoinHandle 可以用来等待 Future 的输出
+    ///
+    /// spawn 总是成功，不受最大任务数的限制；执行器已经被 drop 时，任务会直接被取消。
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.build_task().spawn(future)
+    }
+
+    /// 创建一个可以设置任务名字的 TaskBuilder
+    pub fn build_task(&self) -> TaskBuilder<'_> {
+        TaskBuilder { spawner: self, name: None }
+    }
+
+    /// 未完成的任务数已经达到上限，或者执行器已经被 drop 时返回错误，否则生成任务
+    pub fn try_spawn<F>(&self, future: F) -> Result<JoinHandle<F::Output>, SpawnError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.build_task().try_spawn(future)
+    }
+
+    /// 等到未完成的任务数低于上限后再生成任务，执行器已经被 drop 时返回错误
+    pub async fn spawn_wait<F>(&self, future: F) -> Result<JoinHandle<F::Output>, SpawnError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        poll_fn(|cx| self.shared.poll_reserve(cx)).await?;
+        Ok(self.spawn_reserved(future, None))
+    }
+
+    /// 未完成的任务数
+    pub fn live_tasks(&self) -> usize {
+        self.shared.live_tasks.load(Ordering::Acquire)
+    }
+
+    /// 列出所有存活的任务，可以在执行器运行时从其他线程调用
+    pub fn dump(&self) -> Vec<TaskInfo> {
+        self.shared.dump()
+    }
+
+    /// 已经占好位置，生成任务
+    fn spawn_reserved<F>(&self, future: F, name: Option<String>) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id = TaskId::next();
+        let release = ReleaseOnDrop { queue: self.task_sender.clone(), id };
+        let (future, handle) = joinable(
+            async move {
+                let _release = release;
+                future.await
+            },
+            id,
+        );
+        // 先登记再调度，保证 dump 能看到刚生成的任务
+        if let Some(instrument) = self.shared.instrument.get() {
+            instrument.on_spawn(id, name.as_deref());
+        }
+        let task = {
+            let mut tasks = self.shared.tasks.lock().unwrap();
+            // 把 Spawner 自己的发布者交给 Task，任务被唤醒时通过它回到队列
+            let task = Task::spawn(future, self.task_sender.clone(), id, name);
+            tasks.insert(id, Arc::downgrade(&task));
+            task
+        };
+        // 执行器已经被 drop 时这是任务的最后一个引用，必须在释放 tasks 的锁之后丢弃
+        drop(task);
+        handle
+    }
+}
+
+/// 生成任务前设置任务的属性，由 [`Spawner::build_task`] 创建
+///
+/// ```
+/// let (executor, spawner) = timer_future::new_executor_and_spawner();
+/// let handle = spawner.build_task().name("worker").spawn(async { 1 });
+/// assert_eq!(Some("worker"), executor.dump()[0].name.as_deref());
+/// executor.run();
+/// assert_eq!(1, timer_future::block_on(handle).unwrap());
+/// ```
+#[must_use]
+pub struct TaskBuilder<'a> {
+    spawner: &'a Spawner,
+    name: Option<String>,
+}
+
+impl TaskBuilder<'_> {
+    /// 任务的名字，会出现在 dump 的结果里
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// 同 [`Spawner::spawn`]
+    pub fn spawn<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawner.shared.live_tasks.fetch_add(1, Ordering::AcqRel);
+        self.spawner.spawn_reserved(future, self.name)
+    }
+
+    /// 同 [`Spawner::try_spawn`]
+    pub fn try_spawn<F>(self, future: F) -> Result<JoinHandle<F::Output>, SpawnError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawner.shared.try_reserve()?;
+        Ok(self.spawner.spawn_reserved(future, self.name))
+    }
+}
+
+/// 运行队列的发送端，任务被唤醒时通过它回到队列
+struct ReadyQueue {
+    sender: Sender<Option<(Arc<Task>, Instant)>>,
+    shared: Arc<Shared>,
+}
+
+/// 放在任务的 Future 里，Future 被丢弃时释放任务占用的位置
+///
+/// 任务完成、panic，或者因为 waker 全部被丢弃、执行器已经关闭而被取消，最终都会丢弃 Future，
+/// 所以位置总会被释放，不会让 `try_spawn` 和 `spawn_wait` 永远等不到空位。
+struct ReleaseOnDrop {
+    queue: Arc<ReadyQueue>,
+    id: TaskId,
+}
+
+impl Drop for ReleaseOnDrop {
+    fn drop(&mut self) {
+        let shared = &self.queue.shared;
+        shared.tasks.lock().unwrap().remove(&self.id);
+        if shared.release() {
+            // 任务可能在执行器线程之外被丢弃，这时 run 正阻塞在 recv 上，需要唤醒它重新检查任务数
+            let _ = self.queue.sender.send(None);
+        }
+    }
+}
+
+impl Schedule for ReadyQueue {
+    fn schedule(&self, task: Arc<Task>) {
+        let queued = self.shared.queued.fetch_add(1, Ordering::AcqRel) + 1;
+        if let Some(instrument) = self.shared.instrument.get() {
+            instrument.on_schedule(task.id(), queued);
+        }
+        // 通过发送任务到任务管道来实现 wake，这样 wake 后，任务就可以被执行器 poll 了；
+        // 通道是无界的，发送只会在执行器已经被 drop 时失败，这时直接丢弃任务，等价于取消
+        if self.sender.send(Some((task, Instant::now()))).is_err() {
+            self.shared.queued.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+/// 生成任务失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// 未完成的任务数已经达到上限
+    AtCapacity,
+    /// 执行器已经被 drop
+    Shutdown,
+}
+
+impl Display for SpawnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpawnError::AtCapacity => write!(f, "executor is at capacity"),
+            SpawnError::Shutdown => write!(f, "executor has shut down"),
+        }
+    }
+}
+
+impl Error for SpawnError {}
+
+/// 创建一组连通的执行器和生成器，`try_spawn` 和 `spawn_wait` 不限制任务数
+pub fn new_executor_and_spawner() -> (Executor, Spawner) {
+    new_executor_and_spawner_with_limit(usize::MAX)
+}
+
+/// 创建一组连通的执行器和生成器，`try_spawn` 和 `spawn_wait` 最多允许 max_tasks 个未完成的任务
+///
+/// 运行队列是无界的，唤醒任务永远不会因为队列已满而失败；任务数的上限只作用在生成新任务上。
+pub fn new_executor_and_spawner_with_limit(max_tasks: usize) -> (Executor, Spawner) {
+    let (task_sender, ready_queue) = channel();
+    let shared = Arc::new(Shared {
+        live_tasks: AtomicUsize::new(0),
+        max_tasks,
+        closed: AtomicBool::new(false),
+        waiters: Mutex::new(Vec::new()),
+        tasks: Mutex::new(HashMap::new()),
+        queued: AtomicUsize::new(0),
+        instrument: OnceLock::new(),
+    });
+    (
+        Executor { ready_queue, shared: shared.clone(), panic_hook: None, abort_on_panic: false },
+        Spawner { task_sender: Arc::new(ReadyQueue { sender: task_sender, shared: shared.clone() }), shared },
+    )
+}
+
+/// 在当前线程上运行 future 直到完成，等待期间线程会被挂起（park）
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = waker(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        // 被唤醒之前一直挂起，偶尔的虚假唤醒只会多 poll 一次
+        thread::park();
+    }
+}
+
+/// 唤醒时 unpark 对应线程的 waker
+struct ThreadWaker(Thread);
+
+impl ArcWake for ThreadWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.unpark();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::executor::{block_on, new_executor_and_spawner, new_executor_and_spawner_with_limit, SpawnError};
+    use crate::TaskState;
+    use crate::{yield_now, TimerFuture};
+
+    #[test]
+    fn test_join_handle_output() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let first = spawner.spawn(async { 1 + 1 });
+        let inner_spawner = spawner.clone();
+        // 任务内部可以继续生成任务，并等待它的结果
+        let second = spawner.spawn(async move {
+            let nested = inner_spawner.spawn(async {
+                TimerFuture::new(Duration::from_millis(10)).await;
+                "nested"
+            });
+            nested.await.unwrap().len()
+        });
+
+        // spawner 还活着，run 也会在任务全部完成后返回
+        executor.run();
+        assert!(first.is_finished());
+        assert_eq!(2, block_on(first).unwrap());
+        assert_eq!(6, block_on(second).unwrap());
+    }
+
+    #[test]
+    fn test_run_without_tasks_returns() {
+        let (executor, _spawner) = new_executor_and_spawner();
+        executor.run();
+    }
+
+    #[test]
+    fn test_spawn_from_other_thread() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let spawner = spawner.clone();
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    spawner.spawn(async move {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                    })
+                })
+            })
+            .collect();
+        let join_handles: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        executor.run();
+        assert_eq!(4, counter.load(Ordering::SeqCst));
+        assert!(join_handles.iter().all(|h| h.is_finished()));
+    }
+
+    #[test]
+    fn test_cancelled_when_executor_dropped() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let handle = spawner.spawn(async { 1 });
+        drop(executor);
+        drop(spawner);
+        let err = block_on(handle).unwrap_err();
+        assert!(err.is_cancelled());
+        assert_eq!("task was cancelled", err.to_string());
+    }
+
+    #[test]
+    fn test_block_on_timer() {
+        let value = block_on(async {
+            TimerFuture::new(Duration::from_millis(10)).await;
+            42
+        });
+        assert_eq!(42, value);
+    }
+
+    #[test]
+    fn test_many_more_tasks_than_old_queue_size() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let counter = Arc::new(AtomicUsize::new(0));
+        // 所有任务在 run 之前就已经在队列里，并且每个任务都会被唤醒两次
+        let handles: Vec<_> = (0..100_000)
+            .map(|_| {
+                let counter = counter.clone();
+                spawner.spawn(async move {
+                    for _ in 0..2 {
+                        yield_now().await;
+                    }
+                    counter.fetch_add(1, Ordering::Relaxed);
+                })
+            })
+            .collect();
+        executor.run();
+        assert_eq!(100_000, counter.load(Ordering::Relaxed));
+        assert!(handles.iter().all(|h| h.is_finished()));
+    }
+
+    #[test]
+    fn test_wake_after_executor_dropped_does_not_panic() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let waker = Arc::new(std::sync::Mutex::new(None));
+        let stored = waker.clone();
+        let handle = spawner.spawn(futures::future::poll_fn(move |cx| {
+            *stored.lock().unwrap() = Some(cx.waker().clone());
+            std::task::Poll::<()>::Pending
+        }));
+        // 手动 poll 一次，让任务把 waker 交出来
+        assert!(executor.ready_queue.recv().unwrap().unwrap().0.run().is_pending());
+        drop(executor);
+
+        waker.lock().unwrap().take().unwrap().wake();
+        assert!(block_on(handle).unwrap_err().is_cancelled());
+        assert_eq!(Err(SpawnError::Shutdown), spawner.try_spawn(async {}).map(|_| ()));
+    }
+
+    #[test]
+    fn test_try_spawn_at_capacity() {
+        let (executor, spawner) = new_executor_and_spawner_with_limit(2);
+        let first = spawner.try_spawn(async { 1 }).unwrap();
+        let _second = spawner.try_spawn(async { 2 }).unwrap();
+        let err = spawner.try_spawn(async { 3 }).unwrap_err();
+        assert_eq!(SpawnError::AtCapacity, err);
+        assert_eq!("executor is at capacity", err.to_string());
+        // spawn 不受上限限制
+        let _third = spawner.spawn(async { 3 });
+        assert_eq!(3, spawner.live_tasks());
+
+        executor.run();
+        assert_eq!(0, spawner.live_tasks());
+        assert_eq!(1, block_on(first).unwrap());
+        assert!(spawner.try_spawn(async {}).is_ok());
+    }
+
+    #[test]
+    fn test_cancelled_task_releases_slot() {
+        let (executor, spawner) = new_executor_and_spawner_with_limit(1);
+        // 没有人持有 waker，poll 之后任务被丢弃，占用的位置也要还回来
+        let handle = spawner.try_spawn(futures::future::pending::<()>()).unwrap();
+        executor.run_until_stalled();
+        assert!(block_on(handle).unwrap_err().is_cancelled());
+        assert_eq!(0, spawner.live_tasks());
+        assert!(executor.dump().is_empty());
+        assert!(spawner.try_spawn(async {}).is_ok());
+
+        // 执行器被 drop 之后生成的任务直接被取消，同样不占位置
+        executor.run();
+        drop(executor);
+        let handle = spawner.spawn(async {});
+        assert!(block_on(handle).unwrap_err().is_cancelled());
+        assert_eq!(0, spawner.live_tasks());
+    }
+
+    #[test]
+    fn test_run_returns_when_last_task_dropped_elsewhere() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let (waker_tx, waker_rx) = std::sync::mpsc::channel();
+        let handle = spawner.spawn(futures::future::poll_fn(move |cx| {
+            waker_tx.send(cx.waker().clone()).unwrap();
+            std::task::Poll::<()>::Pending
+        }));
+        // 另一个线程丢弃任务唯一的 waker，任务在执行器线程之外被取消
+        let dropper = thread::spawn(move || {
+            let waker = waker_rx.recv().unwrap();
+            thread::sleep(Duration::from_millis(20));
+            drop(waker);
+        });
+        // spawner 还活着，run 只能靠任务数归零返回
+        executor.run();
+        dropper.join().unwrap();
+        assert!(block_on(handle).unwrap_err().is_cancelled());
+        assert_eq!(0, spawner.live_tasks());
+    }
+
+    #[test]
+    fn test_spawn_wait_applies_backpressure() {
+        const LIMIT: usize = 4;
+        let (executor, spawner) = new_executor_and_spawner_with_limit(LIMIT);
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let completed = Arc::new(AtomicUsize::new(0));
+
+        // 生产者在另一个线程上以阻塞方式生成 100 个任务，执行器满了就等待
+        let producer = {
+            let spawner = spawner.clone();
+            let running = running.clone();
+            let max_running = max_running.clone();
+            let completed = completed.clone();
+            thread::spawn(move || {
+                block_on(async {
+                    for _ in 0..100 {
+                        let running = running.clone();
+                        let max_running = max_running.clone();
+                        let completed = completed.clone();
+                        spawner
+                            .spawn_wait(async move {
+                                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                                max_running.fetch_max(now, Ordering::SeqCst);
+                                TimerFuture::new(Duration::from_millis(1)).await;
+                                running.fetch_sub(1, Ordering::SeqCst);
+                                completed.fetch_add(1, Ordering::SeqCst);
+                            })
+                            .await
+                            .unwrap();
+                        assert!(spawner.live_tasks() <= LIMIT);
+                    }
+                })
+            })
+        };
+
+        while completed.load(Ordering::SeqCst) < 100 {
+            executor.run();
+            thread::yield_now();
+        }
+        producer.join().unwrap();
+        assert!(max_running.load(Ordering::SeqCst) <= LIMIT);
+    }
+
+    #[test]
+    fn test_dump_lists_live_tasks() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let (release, released) = crate::sync::oneshot::channel::<()>();
+        let stuck = spawner.build_task().name("stuck").spawn(async move {
+            released.await.ok();
+            crate::current_task_id()
+        });
+        let quick = spawner.spawn(async { crate::current_task_id() });
+
+        let dump = executor.dump();
+        assert_eq!(vec![stuck.id(), quick.id()], dump.iter().map(|info| info.id).collect::<Vec<_>>());
+        assert!(dump.iter().all(|info| info.state == TaskState::Scheduled && info.polls == 0));
+
+        // 只运行到 stuck 挂起为止：先让 quick 完成，再在另一个线程上释放 stuck
+        let dumped = thread::scope(|scope| {
+            let dumper = scope.spawn(|| {
+                while spawner.dump().len() != 1 || spawner.dump()[0].polls == 0 {
+                    thread::yield_now();
+                }
+                let dump = spawner.dump();
+                release.send(()).unwrap();
+                dump
+            });
+            executor.run();
+            dumper.join().unwrap()
+        });
+        assert_eq!(1, dumped.len());
+        let info = &dumped[0];
+        assert_eq!(Some("stuck"), info.name.as_deref());
+        assert_eq!(TaskState::Idle, info.state);
+        assert_eq!(1, info.polls);
+        assert!(info.last_woken_at.is_none());
+        assert!(info.to_string().starts_with(&format!("task {} \"stuck\" idle polls=1", stuck.id())));
+
+        assert!(executor.dump().is_empty());
+        assert_eq!(Some(stuck.id()), block_on(stuck).unwrap());
+        assert_eq!(Some(quick.id()), block_on(quick).unwrap());
+        assert_eq!(None, crate::current_task_id());
+    }
+
+    #[test]
+    fn test_panicking_task_does_not_affect_others() {
+        let (mut executor, spawner) = new_executor_and_spawner();
+        let panics = Arc::new(Mutex::new(Vec::new()));
+        let recorded = panics.clone();
+        executor.set_panic_hook(move |info, message| {
+            recorded.lock().unwrap().push((info.name.clone(), info.state, message.to_string()));
+        });
+
+        let before = spawner.spawn(async { 1 });
+        let panicking = spawner.build_task().name("bad").spawn(async {
+            TimerFuture::new(Duration::from_millis(5)).await;
+            panic!("boom");
+        });
+        let after = spawner.spawn(async {
+            TimerFuture::new(Duration::from_millis(20)).await;
+            2
+        });
+        executor.run();
+
+        assert_eq!(1, block_on(before).unwrap());
+        assert_eq!(2, block_on(after).unwrap());
+        let err = block_on(panicking).unwrap_err();
+        assert!(err.is_panic() && !err.is_cancelled());
+        assert_eq!("task panicked: boom", err.to_string());
+        assert_eq!(Some(&"boom"), err.into_panic().downcast_ref::<&str>());
+        assert_eq!(vec![(Some("bad".to_string()), TaskState::Panicked, "boom".to_string())], *panics.lock().unwrap());
+        assert_eq!(0, spawner.live_tasks());
+    }
+
+    /// 在子进程中运行自己，检查 abort_on_panic 会终止进程
+    #[test]
+    fn test_abort_on_panic() {
+        if std::env::var_os("TIMER_FUTURE_ABORT_CHILD").is_some() {
+            let (mut executor, spawner) = new_executor_and_spawner();
+            executor.set_abort_on_panic(true);
+            spawner.spawn(async { panic!("fatal") });
+            executor.run();
+            // 不应该到达这里
+            std::process::exit(0);
+        }
+
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["executor::test::test_abort_on_panic", "--exact", "--nocapture"])
+            .env("TIMER_FUTURE_ABORT_CHILD", "1")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("panicked, aborting: fatal"), "{stderr}");
+    }
+}
@@ -0,0 +1,211 @@
+//! Interval 周期定时器
+//!
+//! 以固定周期产生 tick 的 [`Stream`]，每次返回本次 tick 计划的时间点。
+//! 内部只持有一个 [`TimerFuture`]，每次 tick 后重置它的截止时间，不会重复创建定时器。
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::Stream;
+
+use crate::driver::Handle;
+use crate::TimerFuture;
+
+/// 错过 tick 时的处理策略
+///
+/// 当任务没有及时 poll（例如被其他计算阻塞），到下一次 poll 时可能已经错过了不止一个 tick。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MissedTickBehavior {
+    /// 立即连续补发所有错过的 tick，之后恢复原来的节奏
+    #[default]
+    Burst,
+    /// 只补发一次，之后以补发的时间点为起点重新计算周期
+    Delay,
+    /// 只补发一次，之后跳到原节奏中下一个还没到的时间点
+    Skip,
+}
+
+impl MissedTickBehavior {
+    /// 根据本次 tick 计划的时间点 scheduled 和当前时间 now 计算下一次 tick 的时间点
+    fn next_tick(&self, scheduled: Instant, now: Instant, period: Duration) -> Instant {
+        let next = scheduled + period;
+        // 下一个 tick 还没到，说明没有错过
+        if next > now {
+            return next;
+        }
+        match self {
+            MissedTickBehavior::Burst => next,
+            MissedTickBehavior::Delay => now + period,
+            MissedTickBehavior::Skip => {
+                // 按纳秒计算，周期很短、停顿很长时错过的 tick 数可能超过 u32
+                let missed = (now - scheduled).as_nanos() / period.as_nanos();
+                scheduled + Duration::from_nanos((period.as_nanos() * (missed + 1)) as u64)
+            }
+        }
+    }
+}
+
+/// 周期定时器
+pub struct Interval {
+    timer: TimerFuture,
+    period: Duration,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+/// 创建一个周期为 period 的 Interval，第一次 tick 立即完成
+pub fn interval(period: Duration) -> Interval {
+    interval_at(Handle::current().now(), period)
+}
+
+/// 创建一个周期为 period 的 Interval，第一次 tick 在 start 完成
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval {
+        timer: TimerFuture::sleep_until(start),
+        period,
+        missed_tick_behavior: MissedTickBehavior::default(),
+    }
+}
+
+impl Interval {
+    /// 等待下一次 tick，返回它计划的时间点
+    pub async fn tick(&mut self) -> Instant {
+        futures::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.timer).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let scheduled = self.timer.deadline();
+        let now = self.timer.handle().now();
+        let next = self.missed_tick_behavior.next_tick(scheduled, now, self.period);
+        self.timer.reset(next);
+        Poll::Ready(scheduled)
+    }
+
+    /// 从现在开始重新计时，下一次 tick 在一个周期之后
+    pub fn reset(&mut self) {
+        let now = self.timer.handle().now();
+        self.timer.reset(now + self.period);
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        // Interval 永远不会结束
+        self.get_mut().poll_tick(cx).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use futures::executor::block_on;
+    use futures::{FutureExt, StreamExt};
+
+    use crate::clock::MockClock;
+    use crate::interval::{interval, interval_at, Interval, MissedTickBehavior};
+
+    const PERIOD: Duration = Duration::from_secs(10);
+
+    /// 取出所有立即就绪的 tick
+    fn ready_ticks(ticker: &mut Interval) -> Vec<Instant> {
+        let mut ready = Vec::new();
+        while let Some(tick) = ticker.tick().now_or_never() {
+            ready.push(tick);
+        }
+        ready
+    }
+
+    #[test]
+    fn test_ticks_at_fixed_period() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let start = clock.now();
+        let mut ticker = interval(PERIOD);
+
+        // 第一次 tick 立即完成
+        assert_eq!(vec![start], ready_ticks(&mut ticker));
+        clock.advance(PERIOD / 2);
+        assert!(ready_ticks(&mut ticker).is_empty());
+        clock.advance(PERIOD / 2);
+        assert_eq!(Some(Some(start + PERIOD)), ticker.next().now_or_never());
+        clock.advance(PERIOD);
+        assert_eq!(vec![start + PERIOD * 2], ready_ticks(&mut ticker));
+        assert_eq!(start + PERIOD * 3, ticker.timer.deadline());
+    }
+
+    #[test]
+    fn test_real_clock() {
+        let start = Instant::now();
+        let period = Duration::from_millis(20);
+        let mut ticker = interval_at(start, period);
+        let ticks: Vec<Instant> = block_on(ticker.by_ref().take(3).collect());
+        assert_eq!(vec![start, start + period, start + period * 2], ticks);
+        assert!(start.elapsed() >= period * 2);
+    }
+
+    /// 第一次 tick 在 start 完成后停顿 2.5 个周期，返回之后所有立即就绪的 tick 和下一次 tick 的时间
+    fn ticks_after_stall(behavior: MissedTickBehavior) -> (Instant, Vec<Instant>, Instant) {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let start = clock.now() + PERIOD;
+        let mut ticker = interval_at(start, PERIOD);
+        ticker.set_missed_tick_behavior(behavior);
+        clock.advance(PERIOD);
+        assert_eq!(vec![start], ready_ticks(&mut ticker));
+
+        // 错过 start + PERIOD 和 start + PERIOD * 2 两个 tick
+        clock.advance(PERIOD * 5 / 2);
+        (start, ready_ticks(&mut ticker), ticker.timer.deadline())
+    }
+
+    #[test]
+    fn test_missed_tick_burst() {
+        let (start, ready, next) = ticks_after_stall(MissedTickBehavior::Burst);
+        assert_eq!(vec![start + PERIOD, start + PERIOD * 2], ready);
+        assert_eq!(start + PERIOD * 3, next);
+    }
+
+    #[test]
+    fn test_missed_tick_delay() {
+        let (start, ready, next) = ticks_after_stall(MissedTickBehavior::Delay);
+        assert_eq!(vec![start + PERIOD], ready);
+        // 下一次 tick 从补发的时刻开始计算，不在原来的节奏上
+        assert_eq!(start + PERIOD * 7 / 2, next);
+    }
+
+    #[test]
+    fn test_missed_tick_skip() {
+        let (start, ready, next) = ticks_after_stall(MissedTickBehavior::Skip);
+        assert_eq!(vec![start + PERIOD], ready);
+        assert_eq!(start + PERIOD * 3, next);
+    }
+
+    #[test]
+    fn test_skip_many_missed_ticks() {
+        // 1 纳秒的周期停顿 5 秒，错过的 tick 数超过 u32::MAX
+        let start = Instant::now();
+        let now = start + Duration::from_secs(5);
+        let next = MissedTickBehavior::Skip.next_tick(start, now, Duration::from_nanos(1));
+        assert_eq!(now + Duration::from_nanos(1), next);
+    }
+}
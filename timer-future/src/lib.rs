@@ -1,63 +1,185 @@
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
-use std::task::{Context, Poll, Waker};
-use std::thread;
-use std::time::Duration;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::driver::Handle;
+
+pub use blocking::{init_blocking_pool, spawn_blocking, BlockingPool};
+pub use executor::{
+    block_on, new_executor_and_spawner, new_executor_and_spawner_with_limit, Executor, SpawnError, Spawner,
+    TaskBuilder,
+};
+pub use interval::{interval, interval_at, Interval, MissedTickBehavior};
+pub use local::{LocalExecutor, LocalSpawner};
+pub use rate_limit::{AcquireTokens, RateLimiter, RateLimiterBuilder};
+pub use retry::{retry, RetryPolicy};
+pub use scope::{Scope, ScopeError};
+pub use task::{current_task_id, yield_now, JoinError, JoinHandle, TaskId, TaskInfo, TaskState};
+pub use thread_pool::{PoolSpawner, ThreadPool};
+pub use timeout::{timeout, timeout_at, Elapsed, Timeout};
+
+mod blocking;
+pub mod clock;
+pub mod driver;
+mod executor;
+mod interval;
+mod local;
+pub mod metrics;
+#[cfg(target_os = "linux")]
+pub mod net;
+mod rate_limit;
+#[cfg(target_os = "linux")]
+pub mod reactor;
+mod retry;
+mod rng;
+pub mod scheduler;
+mod scope;
+pub mod stream;
+pub mod sync;
+mod task;
+pub mod task_local;
+mod thread_pool;
+mod timeout;
 
 /// TimerFuture 定时任务
+///
+/// 定时器本身不再占用线程，而是把截止时间登记到共享的定时器驱动（[`driver::Handle`]）中，
+/// 由驱动的后台线程在到期时唤醒任务。`new` 和 `sleep_until` 使用 [`Handle::current`] 返回的驱动。
 pub struct TimerFuture {
-    shared_state: Arc<Mutex<SharedState>>,
-}
-
-/// SharedState 共享状态
-struct SharedState {
-    /// 是否完成
-    completed: bool,
-    /// 睡眠结束后，线程可以用 waker 通知 TimerFuture 唤醒任务
-    waker: Option<Waker>,
+    /// 定时器所在的驱动
+    handle: Handle,
+    /// 定时器在驱动中的 id
+    id: u64,
 }
 
 impl Future for TimerFuture {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut shared_state = self.shared_state.lock().unwrap();
-        // 检查共享状态，来确定定时器是否完成
-        if shared_state.completed {
-            Poll::Ready(())
-        } else {
-            // 设置 waker，这样新线程在睡眠结束后可以唤醒当前任务，再次 poll
-            //
-            // 下面的 clone 每次 poll 都会发生一次，
-            // 选择每次都 clone 是因为 TimerFuture 可以在执行器的不同任务间移动，
-            // 如果只 clone 一次，那么获得的 waker 可能已经被篡改并指向其他任务，导致执行器运行错误的任务。
-            shared_state.waker = Some(cx.waker().clone());
-            Poll::Pending
-        }
+        // 由驱动检查定时器是否到期，未到期时驱动会保存 waker，这样到期后可以唤醒当前任务，再次 poll
+        //
+        // 每次 poll 都要把 waker 交给驱动，
+        // 因为 TimerFuture 可以在执行器的不同任务间移动，
+        // 如果只保存一次，那么获得的 waker 可能已经被篡改并指向其他任务，导致执行器运行错误的任务。
+        self.handle.poll_elapsed(self.id, cx.waker())
     }
 }
 
 impl TimerFuture {
     pub fn new(duration: Duration) -> Self {
-        let shared_state = Arc::new(Mutex::new(SharedState {
-            completed: false,
-            waker: None,
-        }));
-
-        // 新建线程
-        let thread_shared_state = shared_state.clone();
-        thread::spawn(move || {
-            // 睡眠指定时间以实现定时功能
-            thread::sleep(duration);
-            let mut shared_state = thread_shared_state.lock().unwrap();
-            // 通知执行器可以继续 poll 对应的 Future 了
-            shared_state.completed = true;
-            if let Some(waker) = shared_state.waker.take() {
-                waker.wake()
-            }
-        });
-
-        TimerFuture { shared_state }
+        Handle::current().sleep(duration)
+    }
+
+    /// 创建一个在 deadline 到期的定时器
+    pub fn sleep_until(deadline: Instant) -> Self {
+        Handle::current().sleep_until(deadline)
+    }
+
+    /// 定时器所在的驱动
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    /// 定时器的截止时间
+    pub fn deadline(&self) -> Instant {
+        self.handle.deadline(self.id)
+    }
+
+    /// 把截止时间改为 deadline，不需要重新创建定时器
+    ///
+    /// 可以推迟也可以提前；已经完成的定时器重置后可以再次 `.await`，适合实现防抖（debounce）。
+    pub fn reset(&mut self, deadline: Instant) {
+        self.handle.reset(self.id, deadline);
+    }
+}
+
+impl Drop for TimerFuture {
+    /// drop 即取消：定时器从驱动中注销，到期后不会再唤醒任何任务
+    fn drop(&mut self) {
+        self.handle.deregister(self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::executor::block_on;
+    use futures::task::{waker, ArcWake};
+
+    use crate::clock::MockClock;
+    use crate::TimerFuture;
+
+    /// 记录被唤醒次数的 waker
+    #[derive(Default)]
+    struct CountingWaker {
+        wakes: AtomicUsize,
+    }
+
+    impl ArcWake for CountingWaker {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.wakes.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn poll_once(timer: &mut TimerFuture, counter: &Arc<CountingWaker>) -> Poll<()> {
+        let waker = waker(counter.clone());
+        Pin::new(timer).poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn test_no_wake_after_cancel() {
+        let counter = Arc::new(CountingWaker::default());
+        let mut timer = TimerFuture::new(Duration::from_millis(30));
+        assert!(poll_once(&mut timer, &counter).is_pending());
+
+        drop(timer);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(0, counter.wakes.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_reset_pushes_deadline_out() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let counter = Arc::new(CountingWaker::default());
+        let mut timer = TimerFuture::new(Duration::from_secs(3));
+        assert!(poll_once(&mut timer, &counter).is_pending());
+
+        let deadline = clock.now() + Duration::from_secs(15);
+        timer.reset(deadline);
+        assert_eq!(deadline, timer.deadline());
+
+        // 原来的截止时间已经过了，但不会被唤醒
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(0, counter.wakes.load(Ordering::SeqCst));
+        assert!(poll_once(&mut timer, &counter).is_pending());
+
+        clock.advance(Duration::from_secs(7));
+        assert_eq!(1, counter.wakes.load(Ordering::SeqCst));
+        assert!(poll_once(&mut timer, &counter).is_ready());
+    }
+
+    #[test]
+    fn test_reset_earlier_and_reuse() {
+        let start = Instant::now();
+        let mut timer = TimerFuture::new(Duration::from_secs(60));
+        timer.reset(Instant::now() + Duration::from_millis(20));
+        block_on(&mut timer);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // 完成后重置，可以再次等待
+        timer.reset(Instant::now() + Duration::from_millis(20));
+        let counter = Arc::new(CountingWaker::default());
+        assert!(poll_once(&mut timer, &counter).is_pending());
+        block_on(&mut timer);
+        assert!(start.elapsed() >= Duration::from_millis(40));
     }
 }
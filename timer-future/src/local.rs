@@ -0,0 +1,370 @@
+//! 本地执行器
+//!
+//! [`Spawner::spawn`](crate::Spawner::spawn) 要求 Future 是 Send 的，持有 `Rc` 之类的 Future 无法运行。
+//! [`LocalExecutor`] 固定在创建它的线程上（它本身不是 Send 的），[`LocalExecutor::spawn_local`] 接受 `!Send` 的 Future。
+//!
+//! 任务和运行队列只会在所属线程上访问，所以不需要 `Arc<Mutex<_>>`：
+//!
+//! - 在所属线程上唤醒时，任务 id 直接放进线程局部的运行队列
+//! - 在其他线程上唤醒时（例如定时器驱动的线程），任务 id 通过通道发回所属线程
+//!
+//! waker 里只有一个原子标记，保证任务在运行队列中最多出现一次。
+//!
+//! ```
+//! use std::rc::Rc;
+//! use std::time::Duration;
+//! use timer_future::{LocalExecutor, TimerFuture};
+//!
+//! #[derive(Default)]
+//! struct NoSend(Rc<()>);
+//!
+//! let executor = LocalExecutor::new();
+//! let handle = executor.spawn_local(async {
+//!     let x = NoSend::default();
+//!     TimerFuture::new(Duration::from_millis(10)).await;
+//!     Rc::strong_count(&x.0)
+//! });
+//! executor.run();
+//! assert_eq!(1, timer_future::block_on(handle).unwrap());
+//! ```
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread::{self, ThreadId};
+
+use futures::future::LocalBoxFuture;
+use futures::task::{waker_ref, ArcWake};
+
+use crate::task::{self, joinable_local, JoinHandle, TaskId};
+
+thread_local! {
+    /// 当前线程上每个本地执行器的运行队列，按执行器的 id 索引
+    static READY: RefCell<HashMap<u64, VecDeque<TaskId>>> = RefCell::new(HashMap::new());
+}
+
+/// 只在一个线程上运行任务的执行器，任务不需要是 Send 的
+///
+/// drop 时还没有完成的任务被丢弃，对应的 JoinHandle 得到“已取消”。
+pub struct LocalExecutor {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    id: u64,
+    owner: ThreadId,
+    /// 所有未完成的任务；正在 poll 的任务暂时不在这里
+    tasks: RefCell<HashMap<TaskId, LocalTask>>,
+    /// 在其他线程上被唤醒的任务
+    remote: Receiver<TaskId>,
+    remote_sender: Sender<TaskId>,
+}
+
+struct LocalTask {
+    future: LocalBoxFuture<'static, ()>,
+    waker: Arc<TaskWaker>,
+}
+
+/// 本地任务的 waker
+struct TaskWaker {
+    executor: u64,
+    task: TaskId,
+    owner: ThreadId,
+    /// 任务已经在运行队列中，重复的唤醒什么都不做
+    scheduled: AtomicBool,
+    remote: Sender<TaskId>,
+}
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if arc_self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // 执行器已经被 drop 时，运行队列和通道都不存在了，唤醒被直接忽略
+        if thread::current().id() == arc_self.owner {
+            let _ = READY.try_with(|ready| {
+                if let Some(queue) = ready.borrow_mut().get_mut(&arc_self.executor) {
+                    queue.push_back(arc_self.task);
+                }
+            });
+        } else {
+            let _ = arc_self.remote.send(arc_self.task);
+        }
+    }
+}
+
+impl LocalExecutor {
+    /// 创建一个固定在当前线程上的执行器
+    pub fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        READY.with(|ready| ready.borrow_mut().insert(id, VecDeque::new()));
+        let (remote_sender, remote) = channel();
+        LocalExecutor {
+            inner: Rc::new(Inner {
+                id,
+                owner: thread::current().id(),
+                tasks: RefCell::new(HashMap::new()),
+                remote,
+                remote_sender,
+            }),
+        }
+    }
+
+    /// 生成一个本地任务，返回的 JoinHandle 可以用来等待 Future 的输出
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        self.inner.spawn(future)
+    }
+
+    /// 创建一个 LocalSpawner，可以交给任务在运行时生成新的本地任务
+    pub fn spawner(&self) -> LocalSpawner {
+        LocalSpawner { inner: Rc::downgrade(&self.inner) }
+    }
+
+    /// 未完成的任务数
+    pub fn live_tasks(&self) -> usize {
+        self.inner.tasks.borrow().len()
+    }
+
+    /// 运行所有任务，直到已生成的任务全部完成，包括运行期间生成的新任务
+    pub fn run(&self) {
+        while self.live_tasks() > 0 {
+            let id = match self.inner.next_ready() {
+                Some(id) => id,
+                // 本地没有就绪的任务，只能等其他线程唤醒
+                None => self.inner.remote.recv().expect("executor holds a sender"),
+            };
+            self.inner.poll_task(id);
+        }
+    }
+
+    /// 运行所有已经就绪的任务，直到运行队列为空后返回，不等待还没有被唤醒的任务
+    pub fn run_until_stalled(&self) {
+        while let Some(id) = self.inner.next_ready() {
+            self.inner.poll_task(id);
+        }
+    }
+}
+
+impl Default for LocalExecutor {
+    fn default() -> Self {
+        LocalExecutor::new()
+    }
+}
+
+impl Inner {
+    fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        let id = TaskId::next();
+        let (future, handle) = joinable_local(future, id);
+        let waker = Arc::new(TaskWaker {
+            executor: self.id,
+            task: id,
+            owner: self.owner,
+            scheduled: AtomicBool::new(true),
+            remote: self.remote_sender.clone(),
+        });
+        self.tasks.borrow_mut().insert(id, LocalTask { future, waker });
+        READY.with(|ready| ready.borrow_mut().get_mut(&self.id).expect("executor is registered").push_back(id));
+        handle
+    }
+
+    /// 先取本地唤醒的任务，再取其他线程唤醒的任务
+    fn next_ready(&self) -> Option<TaskId> {
+        READY
+            .with(|ready| ready.borrow_mut().get_mut(&self.id).and_then(VecDeque::pop_front))
+            .or_else(|| self.remote.try_recv().ok())
+    }
+
+    fn poll_task(&self, id: TaskId) {
+        // 先把任务取出来，poll 期间任务可以继续生成新任务
+        let Some(mut task) = self.tasks.borrow_mut().remove(&id) else {
+            // 已经完成的任务留在队列里的唤醒
+            return;
+        };
+        // 在 poll 之前清除标记，poll 期间的唤醒会把任务重新放进队列
+        task.waker.scheduled.store(false, Ordering::Release);
+        let waker = waker_ref(&task.waker);
+        let mut cx = Context::from_waker(&waker);
+        // panic 已经由 joinable_local 交给 JoinHandle，panic 之后 future 被直接丢弃，不会再被 poll
+        let poll = task::enter(id, || panic::catch_unwind(AssertUnwindSafe(|| task.future.as_mut().poll(&mut cx))));
+        match poll {
+            Ok(Poll::Pending) => {
+                self.tasks.borrow_mut().insert(id, task);
+            }
+            Ok(Poll::Ready(())) => {}
+            Err(_) => {
+                // 丢弃 future 本身也可能 panic
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(task)));
+            }
+        }
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        let _ = READY.try_with(|ready| ready.borrow_mut().remove(&self.id));
+        // 丢弃未完成的任务，JoinHandle 得到“已取消”；任务析构时生成的新任务也会被直接取消
+        drop(std::mem::take(self.tasks.get_mut()));
+    }
+}
+
+/// 在 LocalExecutor 上生成本地任务，由 [`LocalExecutor::spawner`] 创建
+///
+/// 和执行器一样只能在所属线程上使用。不持有执行器本身，执行器已经被 drop 时任务会直接被取消。
+#[derive(Clone)]
+pub struct LocalSpawner {
+    inner: Weak<Inner>,
+}
+
+impl LocalSpawner {
+    /// 同 [`LocalExecutor::spawn_local`]
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        match self.inner.upgrade() {
+            Some(inner) => inner.spawn(future),
+            None => {
+                // 丢弃包装后的 future，JoinHandle 得到“已取消”
+                let (_, handle) = joinable_local(future, TaskId::next());
+                handle
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::{Cell, RefCell};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::rc::Rc;
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::channel::oneshot;
+
+    use crate::local::LocalExecutor;
+    use crate::{block_on, current_task_id, TimerFuture};
+
+    /// 每次 poll 都唤醒自己 wakes 次，一共返回 Pending polls 次
+    struct WakeSelf {
+        polls: usize,
+        wakes: usize,
+    }
+
+    impl Future for WakeSelf {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.polls == 0 {
+                return Poll::Ready(());
+            }
+            self.polls -= 1;
+            for _ in 0..self.wakes {
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn test_spawn_non_send_futures() {
+        let executor = LocalExecutor::new();
+        let spawner = executor.spawner();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let first = {
+            let log = log.clone();
+            executor.spawn_local(async move {
+                // 定时器由驱动线程唤醒，走的是跨线程的通道
+                TimerFuture::new(Duration::from_millis(20)).await;
+                log.borrow_mut().push("timer");
+                current_task_id()
+            })
+        };
+        let second = {
+            let log = log.clone();
+            executor.spawn_local(async move {
+                let nested = spawner.spawn_local({
+                    let log = log.clone();
+                    async move { log.borrow_mut().push("nested") }
+                });
+                nested.await.unwrap();
+                // 输出本身也可以不是 Send 的
+                Rc::new(log.borrow().len())
+            })
+        };
+        executor.run();
+        assert_eq!(0, executor.live_tasks());
+        assert_eq!(vec!["nested", "timer"], *log.borrow());
+        assert_eq!(Some(first.id()), block_on(first).unwrap());
+        assert_eq!(1, *block_on(second).unwrap());
+    }
+
+    #[test]
+    fn test_duplicate_wakes_poll_once() {
+        let executor = LocalExecutor::new();
+        let polls = Rc::new(Cell::new(0));
+        let handle = {
+            let polls = polls.clone();
+            let mut future = WakeSelf { polls: 3, wakes: 5 };
+            executor.spawn_local(futures::future::poll_fn(move |cx| {
+                polls.set(polls.get() + 1);
+                Pin::new(&mut future).poll(cx)
+            }))
+        };
+        executor.run_until_stalled();
+        // 每次 poll 唤醒 5 次，任务也只会被重新 poll 一次
+        assert_eq!(4, polls.get());
+        block_on(handle).unwrap();
+
+        // 没有被唤醒的任务停在那里，执行器被 drop 时取消
+        let pending = executor.spawn_local(futures::future::pending::<()>());
+        executor.run_until_stalled();
+        assert_eq!(1, executor.live_tasks());
+        drop(executor);
+        assert!(block_on(pending).unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn test_wake_from_other_thread() {
+        let executor = LocalExecutor::new();
+        let (sender, receiver) = oneshot::channel();
+        let handle = executor.spawn_local(async move { Rc::new(receiver.await.unwrap()) });
+        executor.run_until_stalled();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.send(7).unwrap();
+        });
+        executor.run();
+        assert_eq!(7, *block_on(handle).unwrap());
+    }
+
+    #[test]
+    fn test_panic_and_drop() {
+        let executor = LocalExecutor::new();
+        let spawner = executor.spawner();
+        let panicked = executor.spawn_local(async { panic!("local boom") });
+        let ok = executor.spawn_local(WakeSelf { polls: 2, wakes: 1 });
+        executor.run();
+        assert!(block_on(panicked).unwrap_err().is_panic());
+        block_on(ok).unwrap();
+
+        drop(executor);
+        // 执行器已经被 drop，生成的任务直接被取消
+        assert!(block_on(spawner.spawn_local(async {})).unwrap_err().is_cancelled());
+    }
+}
@@ -0,0 +1,371 @@
+//! 执行器的度量和追踪
+//!
+//! 实现了 [`Instrument`] 的对象通过 [`Executor::set_instrument`](crate::Executor::set_instrument) 交给执行器后，
+//! 执行器会在任务生成、进入运行队列、poll 开始和结束以及完成时调用它。
+//! 所有方法都有空的默认实现，只需要实现关心的事件。
+//!
+//! [`MetricsCollector`] 是内置的实现，记录运行队列的长度、poll 耗时和从唤醒到被 poll 的延迟的直方图，
+//! 并标记 poll 时间超过阈值的任务（在 poll 里做了阻塞操作的任务会拖住整个执行器）。
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use timer_future::metrics::MetricsCollector;
+//!
+//! let (executor, spawner) = timer_future::new_executor_and_spawner();
+//! let collector = Arc::new(MetricsCollector::new(Duration::from_millis(10)));
+//! executor.set_instrument(collector.clone());
+//! spawner.spawn(async {});
+//! executor.run();
+//!
+//! let metrics = collector.snapshot();
+//! assert_eq!(1, metrics.completed);
+//! println!("poll: {}", metrics.poll_duration);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::TaskId;
+
+/// 执行器事件的回调
+///
+/// 回调在执行器线程（`on_spawn` 和 `on_schedule` 在生成或者唤醒任务的线程）上同步调用，应该尽快返回。
+pub trait Instrument: Send + Sync {
+    /// 任务被生成
+    fn on_spawn(&self, _id: TaskId, _name: Option<&str>) {}
+
+    /// 任务进入运行队列，`queue_depth` 是进入之后队列中的任务数
+    fn on_schedule(&self, _id: TaskId, _queue_depth: usize) {}
+
+    /// 执行器开始 poll 任务，`waited` 是任务在运行队列中等待的时间
+    fn on_poll_start(&self, _id: TaskId, _waited: Duration) {}
+
+    /// 一次 poll 结束，`duration` 是这次 poll 花费的时间
+    fn on_poll_end(&self, _id: TaskId, _duration: Duration) {}
+
+    /// 任务完成（包括 panic）
+    fn on_complete(&self, _id: TaskId) {}
+}
+
+/// 最多保留的阻塞 poll 记录数，超过后丢弃最早的记录
+const MAX_BLOCKING_RECORDS: usize = 256;
+
+/// 内置的度量收集器
+pub struct MetricsCollector {
+    /// poll 时间超过它的任务会被记录为阻塞
+    blocking_threshold: Duration,
+    spawned: AtomicU64,
+    completed: AtomicU64,
+    scheduled: AtomicU64,
+    queue_depth: AtomicUsize,
+    max_queue_depth: AtomicUsize,
+    poll_duration: AtomicHistogram,
+    wake_latency: AtomicHistogram,
+    /// 有名字的存活任务，用于在阻塞记录中显示名字
+    names: Mutex<HashMap<TaskId, String>>,
+    blocking: Mutex<VecDeque<BlockingPoll>>,
+}
+
+impl MetricsCollector {
+    pub fn new(blocking_threshold: Duration) -> Self {
+        MetricsCollector {
+            blocking_threshold,
+            spawned: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            scheduled: AtomicU64::new(0),
+            queue_depth: AtomicUsize::new(0),
+            max_queue_depth: AtomicUsize::new(0),
+            poll_duration: AtomicHistogram::default(),
+            wake_latency: AtomicHistogram::default(),
+            names: Mutex::new(HashMap::new()),
+            blocking: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// 到目前为止收集到的度量
+    pub fn snapshot(&self) -> Metrics {
+        Metrics {
+            spawned: self.spawned.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            scheduled: self.scheduled.load(Ordering::Relaxed),
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            max_queue_depth: self.max_queue_depth.load(Ordering::Relaxed),
+            poll_duration: self.poll_duration.snapshot(),
+            wake_latency: self.wake_latency.snapshot(),
+            blocking_polls: self.blocking.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+}
+
+impl Instrument for MetricsCollector {
+    fn on_spawn(&self, id: TaskId, name: Option<&str>) {
+        self.spawned.fetch_add(1, Ordering::Relaxed);
+        if let Some(name) = name {
+            self.names.lock().unwrap().insert(id, name.to_string());
+        }
+    }
+
+    fn on_schedule(&self, _id: TaskId, queue_depth: usize) {
+        self.scheduled.fetch_add(1, Ordering::Relaxed);
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+        self.max_queue_depth.fetch_max(queue_depth, Ordering::Relaxed);
+    }
+
+    fn on_poll_start(&self, _id: TaskId, waited: Duration) {
+        // 设置收集器之前进入队列的任务没有计数，不能减到负数
+        let _ = self.queue_depth.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| depth.checked_sub(1));
+        self.wake_latency.record(waited);
+    }
+
+    fn on_poll_end(&self, id: TaskId, duration: Duration) {
+        self.poll_duration.record(duration);
+        if duration >= self.blocking_threshold {
+            let name = self.names.lock().unwrap().get(&id).cloned();
+            let mut blocking = self.blocking.lock().unwrap();
+            if blocking.len() == MAX_BLOCKING_RECORDS {
+                blocking.pop_front();
+            }
+            blocking.push_back(BlockingPoll { id, name, duration });
+        }
+    }
+
+    fn on_complete(&self, id: TaskId) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        self.names.lock().unwrap().remove(&id);
+    }
+}
+
+/// [`MetricsCollector::snapshot`] 返回的度量
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    /// 生成的任务数
+    pub spawned: u64,
+    /// 完成的任务数
+    pub completed: u64,
+    /// 任务进入运行队列的次数
+    pub scheduled: u64,
+    /// 当前运行队列中的任务数
+    pub queue_depth: usize,
+    /// 运行队列中最多同时有多少任务
+    pub max_queue_depth: usize,
+    /// 每次 poll 花费的时间
+    pub poll_duration: Histogram,
+    /// 任务从进入运行队列到被 poll 的延迟
+    pub wake_latency: Histogram,
+    /// poll 时间超过阈值的记录，从早到晚排列，只保留最近的一部分
+    pub blocking_polls: Vec<BlockingPoll>,
+}
+
+/// 一次 poll 时间超过阈值的记录
+#[derive(Clone, Debug)]
+pub struct BlockingPoll {
+    pub id: TaskId,
+    pub name: Option<String>,
+    pub duration: Duration,
+}
+
+impl Display for BlockingPoll {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "task {}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name:?}")?;
+        }
+        write!(f, " blocked for {:?}", self.duration)
+    }
+}
+
+/// 桶的个数：第 0 个桶是小于 1µs，第 i 个桶是 [2^(i-1)µs, 2^i µs)，最后一个桶收纳所有更长的时间
+const BUCKETS: usize = 32;
+
+/// 以 2 的幂次为边界的耗时直方图
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    /// 记录的样本数
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.sum.as_nanos() / self.count as u128) as u64)
+    }
+
+    /// 分位数 q（0.0 到 1.0）的上界：至少 q 比例的样本不超过返回值，精度受桶宽限制
+    pub fn percentile(&self, q: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let target = ((self.count as f64 * q.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return bucket_upper_bound(bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    /// 非空的桶，返回每个桶的上界和样本数
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| (bucket_upper_bound(bucket), *count))
+    }
+}
+
+impl Display for Histogram {
+    /// 例如 `count=120 mean=35µs p50=32µs p99=512µs max=480µs`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "count={} mean={:?} p50={:?} p99={:?} max={:?}",
+            self.count,
+            self.mean(),
+            self.percentile(0.5),
+            self.percentile(0.99),
+            self.max
+        )
+    }
+}
+
+fn bucket_of(duration: Duration) -> usize {
+    let micros = duration.as_micros();
+    if micros == 0 {
+        return 0;
+    }
+    // micros 在 [2^(i-1), 2^i) 中时落在第 i 个桶
+    ((u128::BITS - micros.leading_zeros()) as usize).min(BUCKETS - 1)
+}
+
+fn bucket_upper_bound(bucket: usize) -> Duration {
+    if bucket == BUCKETS - 1 {
+        return Duration::MAX;
+    }
+    Duration::from_micros(1 << bucket)
+}
+
+/// 可以在多个线程中并发记录的直方图
+struct AtomicHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Default for AtomicHistogram {
+    fn default() -> Self {
+        AtomicHistogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl AtomicHistogram {
+    fn record(&self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.buckets[bucket_of(duration)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// 各个字段分别读取，并发记录时快照中的计数之间可能略有出入
+    fn snapshot(&self) -> Histogram {
+        Histogram {
+            buckets: std::array::from_fn(|bucket| self.buckets[bucket].load(Ordering::Relaxed)),
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(self.max_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::metrics::{AtomicHistogram, MetricsCollector};
+    use crate::{new_executor_and_spawner, TimerFuture};
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let histogram = AtomicHistogram::default();
+        for _ in 0..90 {
+            histogram.record(Duration::from_micros(3));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_millis(1));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(100, snapshot.count());
+        assert_eq!(Duration::from_millis(1), snapshot.max());
+        // 3µs 落在 [2µs, 4µs) 中，1ms 落在 [512µs, 1024µs) 中
+        assert_eq!(Duration::from_micros(4), snapshot.percentile(0.5));
+        assert_eq!(Duration::from_micros(4), snapshot.percentile(0.9));
+        assert_eq!(Duration::from_millis(1), snapshot.percentile(0.99));
+        assert_eq!(
+            vec![(Duration::from_micros(4), 90), (Duration::from_micros(1024), 10)],
+            snapshot.buckets().collect::<Vec<_>>()
+        );
+        assert_eq!(Duration::ZERO, AtomicHistogram::default().snapshot().percentile(0.5));
+    }
+
+    #[test]
+    fn test_collector_records_executor_events() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let collector = Arc::new(MetricsCollector::new(Duration::from_millis(20)));
+        executor.set_instrument(collector.clone());
+
+        for _ in 0..10 {
+            spawner.spawn(async { TimerFuture::new(Duration::from_millis(5)).await });
+        }
+        let blocker = spawner.build_task().name("blocker").spawn(async {
+            // 在 poll 里阻塞线程
+            thread::sleep(Duration::from_millis(30));
+        });
+        executor.run();
+
+        let metrics = collector.snapshot();
+        assert_eq!(11, metrics.spawned);
+        assert_eq!(11, metrics.completed);
+        assert_eq!(11, metrics.max_queue_depth);
+        assert_eq!(0, metrics.queue_depth);
+        // 每个定时器任务被 poll 两次，阻塞的任务一次
+        assert_eq!(21, metrics.scheduled);
+        assert_eq!(21, metrics.poll_duration.count());
+        assert_eq!(21, metrics.wake_latency.count());
+        // 阻塞期间到期的定时器任务要在队列里等到阻塞结束
+        assert!(metrics.wake_latency.max() >= Duration::from_millis(20));
+
+        assert_eq!(1, metrics.blocking_polls.len());
+        let blocking = &metrics.blocking_polls[0];
+        assert_eq!((blocker.id(), Some("blocker")), (blocking.id, blocking.name.as_deref()));
+        assert!(blocking.duration >= Duration::from_millis(30));
+        assert!(blocking.to_string().starts_with(&format!("task {} \"blocker\" blocked for", blocker.id())));
+    }
+}
@@ -0,0 +1,350 @@
+//! 异步 socket
+//!
+//! 对标准库 socket 的非阻塞包装，通过 [`AsyncFd`] 登记到反应器。[`TcpStream`] 和 [`UnixStream`] 实现了
+//! futures 的 `AsyncRead` / `AsyncWrite`，可以配合 `AsyncReadExt` / `AsyncWriteExt` 使用。
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{self, Shutdown, SocketAddr, ToSocketAddrs};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net as unix;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future::poll_fn;
+use futures::io::{AsyncRead, AsyncWrite};
+
+use crate::reactor::{cvt, AsyncFd, Interest};
+
+/// 异步 TCP 监听器
+pub struct TcpListener {
+    io: AsyncFd<net::TcpListener>,
+}
+
+impl TcpListener {
+    /// 绑定地址并开始监听，地址解析是同步进行的
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<TcpListener> {
+        let listener = net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(TcpListener { io: AsyncFd::new(listener)? })
+    }
+
+    /// 等待并接受一个连接
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (stream, addr) = self.io.read_with(|listener| listener.accept()).await?;
+        // accept 得到的 socket 不会继承监听 socket 的非阻塞标志
+        stream.set_nonblocking(true)?;
+        Ok((TcpStream { io: AsyncFd::new(stream)? }, addr))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().local_addr()
+    }
+}
+
+impl AsRawFd for TcpListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+/// 异步 TCP 连接
+pub struct TcpStream {
+    io: AsyncFd<net::TcpStream>,
+}
+
+impl TcpStream {
+    /// 依次尝试解析出的每个地址，返回第一个连接成功的结果；地址解析是同步进行的
+    pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_addr(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to")))
+    }
+
+    async fn connect_addr(addr: SocketAddr) -> io::Result<TcpStream> {
+        let domain = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let fd = cvt(unsafe { libc::socket(domain, libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0) })?;
+        // SAFETY: socket 成功返回的描述符归我们所有
+        let stream = net::TcpStream::from(unsafe { OwnedFd::from_raw_fd(fd) });
+
+        // 非阻塞的 connect 通常返回 EINPROGRESS，连接建立或失败后 socket 变为可写
+        let (storage, len) = socket_addr_to_raw(&addr);
+        let result = unsafe { libc::connect(fd, &storage as *const _ as *const libc::sockaddr, len) };
+        if result == -1 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINPROGRESS) {
+                return Err(err);
+            }
+        }
+
+        let io = AsyncFd::new(stream)?;
+        poll_fn(|cx| loop {
+            let tick = match io.poll_ready(cx, Interest::Writable) {
+                Poll::Ready(tick) => tick,
+                Poll::Pending => return Poll::Pending,
+            };
+            if let Some(err) = io.get_ref().take_error()? {
+                return Poll::Ready(Err(err));
+            }
+            match io.get_ref().peer_addr() {
+                Ok(_) => return Poll::Ready(Ok(())),
+                // 新登记时默认视为可写，连接还没建立就继续等待
+                Err(err) if err.kind() == io::ErrorKind::NotConnected => io.clear_ready(Interest::Writable, tick),
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        })
+        .await?;
+        Ok(TcpStream { io })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.io.get_ref().peer_addr()
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.io.get_ref().set_nodelay(nodelay)
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.io.get_ref().shutdown(how)
+    }
+}
+
+impl AsyncRead for TcpStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.io.poll_read_with(cx, |mut stream| stream.read(buf))
+    }
+}
+
+impl AsyncWrite for TcpStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.io.poll_write_with(cx, |mut stream| stream.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // socket 没有用户态缓冲区
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.shutdown(Shutdown::Write))
+    }
+}
+
+impl AsRawFd for TcpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+/// 异步 Unix 域 socket 连接
+pub struct UnixStream {
+    io: AsyncFd<unix::UnixStream>,
+}
+
+impl UnixStream {
+    /// 连接到 path 上监听的 socket
+    ///
+    /// Unix 域 socket 的 connect 不经过网络，会立即完成或失败，这里直接同步连接。
+    pub async fn connect(path: impl AsRef<Path>) -> io::Result<UnixStream> {
+        UnixStream::from_std(unix::UnixStream::connect(path)?)
+    }
+
+    /// 创建一对互相连接的 socket
+    pub fn pair() -> io::Result<(UnixStream, UnixStream)> {
+        let (a, b) = unix::UnixStream::pair()?;
+        Ok((UnixStream::from_std(a)?, UnixStream::from_std(b)?))
+    }
+
+    /// 包装一个标准库的 UnixStream，会把它设置为非阻塞
+    pub fn from_std(stream: unix::UnixStream) -> io::Result<UnixStream> {
+        stream.set_nonblocking(true)?;
+        Ok(UnixStream { io: AsyncFd::new(stream)? })
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.io.get_ref().shutdown(how)
+    }
+}
+
+impl AsyncRead for UnixStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.io.poll_read_with(cx, |mut stream| stream.read(buf))
+    }
+}
+
+impl AsyncWrite for UnixStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.io.poll_write_with(cx, |mut stream| stream.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.shutdown(Shutdown::Write))
+    }
+}
+
+impl AsRawFd for UnixStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.io.as_raw_fd()
+    }
+}
+
+/// 把 SocketAddr 转换成 connect 需要的 sockaddr
+fn socket_addr_to_raw(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    // SAFETY: sockaddr_storage 全零是合法的值
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            // SAFETY: sockaddr_storage 足够大并且对齐满足所有 sockaddr 类型
+            let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            raw.sin_family = libc::AF_INET as libc::sa_family_t;
+            raw.sin_port = addr.port().to_be();
+            raw.sin_addr = libc::in_addr { s_addr: u32::from_ne_bytes(addr.ip().octets()) };
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            // SAFETY: 同上
+            let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            raw.sin6_port = addr.port().to_be();
+            raw.sin6_flowinfo = addr.flowinfo();
+            raw.sin6_addr = libc::in6_addr { s6_addr: addr.ip().octets() };
+            raw.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+    use std::net::TcpListener as StdTcpListener;
+    use std::os::unix::net::UnixListener as StdUnixListener;
+    use std::time::Duration;
+
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::net::{TcpListener, TcpStream, UnixStream};
+    use crate::{block_on, new_executor_and_spawner, timeout};
+
+    #[test]
+    fn test_tcp_echo_over_loopback() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // 服务端：接受两个连接，把收到的数据原样写回，直到对方关闭写端
+        let server = spawner.clone();
+        spawner.spawn(async move {
+            for _ in 0..2 {
+                let (mut stream, peer) = listener.accept().await.unwrap();
+                assert_eq!(peer, stream.peer_addr().unwrap());
+                server.spawn(async move {
+                    let mut buf = vec![0; 8192];
+                    loop {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        if n == 0 {
+                            break;
+                        }
+                        stream.write_all(&buf[..n]).await.unwrap();
+                    }
+                    stream.close().await.unwrap();
+                });
+            }
+        });
+
+        let clients: Vec<_> = (0..2u8)
+            .map(|id| {
+                spawner.spawn(async move {
+                    let stream = TcpStream::connect(addr).await.unwrap();
+                    // 超过 socket 缓冲区的数据量，读写两端都需要等待对方
+                    let data: Vec<u8> = (0..1 << 20).map(|i: u32| (i as u8).wrapping_add(id)).collect();
+                    let (mut reader, mut writer) = stream.split();
+                    let write = async {
+                        writer.write_all(&data).await.unwrap();
+                        writer.close().await.unwrap();
+                    };
+                    let read = async {
+                        let mut echoed = Vec::new();
+                        reader.read_to_end(&mut echoed).await.unwrap();
+                        echoed
+                    };
+                    let ((), echoed) = futures::join!(write, read);
+                    echoed == data
+                })
+            })
+            .collect();
+        executor.run();
+
+        for client in clients {
+            assert!(block_on(client).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_tcp_connect_refused() {
+        // 先占用一个端口再关闭，之后连接它会被拒绝
+        let addr = StdTcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let err = block_on(TcpStream::connect(addr)).err().unwrap();
+        assert_eq!(ErrorKind::ConnectionRefused, err.kind());
+    }
+
+    #[test]
+    fn test_accept_waits_for_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        // 没有连接时 accept 一直等待
+        let result = block_on(timeout(Duration::from_millis(20), listener.accept()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_unix_stream_pair_and_connect() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let (mut left, mut right) = UnixStream::pair().unwrap();
+        let pong = spawner.spawn(async move {
+            let mut buf = [0; 4];
+            right.read_exact(&mut buf).await.unwrap();
+            right.write_all(b"pong").await.unwrap();
+            buf
+        });
+        let ping = spawner.spawn(async move {
+            left.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            left.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+        executor.run();
+        assert_eq!(b"ping", &block_on(pong).unwrap());
+        assert_eq!(b"pong", &block_on(ping).unwrap());
+
+        let path = std::env::temp_dir().join(format!("timer-future-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = StdUnixListener::bind(&path).unwrap();
+        let mut client = block_on(UnixStream::connect(&path)).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        std::io::Write::write_all(&mut server, b"hello").unwrap();
+        drop(server);
+        let mut received = String::new();
+        block_on(client.read_to_string(&mut received)).unwrap();
+        assert_eq!("hello", received);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
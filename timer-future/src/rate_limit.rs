@@ -0,0 +1,296 @@
+//! 令牌桶限流
+//!
+//! [`RateLimiter`] 以固定的速率向桶里补充令牌，桶满之后多出的令牌被丢弃；`acquire(n)` 取走 n 个令牌，
+//! 令牌不够时挂起任务，直到补充到足够的令牌。桶的容量决定了空闲一段时间后最多能连续通过多少请求。
+//!
+//! 实现上不逐个计算令牌，而是记录“桶里的令牌恰好用完的时间点”：每次申请把这个时间点往后推 n 个令牌的补充时间，
+//! 申请者等到它减去整个桶的补充时间为止。申请在进入时就预定了令牌，所以等待者严格按到达顺序通过，
+//! 大的申请也不会被小的申请饿死。
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::driver::Handle;
+use crate::TimerFuture;
+
+/// 令牌桶限流器，可以放在 Arc 里由多个任务共享
+///
+/// ```
+/// use std::time::Duration;
+/// use timer_future::RateLimiter;
+///
+/// // 每秒 100 个请求，最多连续通过 10 个
+/// let limiter = RateLimiter::builder(100, Duration::from_secs(1)).burst(10).build();
+/// timer_future::block_on(async {
+///     for _ in 0..20 {
+///         limiter.acquire(1).await;
+///     }
+/// });
+/// ```
+pub struct RateLimiter {
+    handle: Handle,
+    /// 每 per 时间补充 rate 个令牌
+    rate: u32,
+    per: Duration,
+    /// 桶的容量
+    burst: u32,
+    /// 桶里的令牌恰好用完的时间点，早于当前时间说明桶是满的
+    empty_at: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// 每 per 时间补充 rate 个令牌，桶的容量为 rate，使用 [`Handle::current`] 返回的驱动
+    ///
+    /// # Panics
+    ///
+    /// rate 或者 per 为 0 时 panic；补充一个令牌的时间不足 1 纳秒时也会 panic。
+    pub fn new(rate: u32, per: Duration) -> Self {
+        RateLimiter::builder(rate, per).build()
+    }
+
+    /// 创建一个可以设置桶容量和定时器驱动的 RateLimiterBuilder
+    pub fn builder(rate: u32, per: Duration) -> RateLimiterBuilder {
+        RateLimiterBuilder { rate, per, burst: None, handle: None }
+    }
+
+    /// 申请 n 个令牌，令牌不够时等待
+    ///
+    /// 返回的 Future 在第一次 poll 时预定令牌；还没有等到就被 drop 时，预定的令牌会被退回。
+    ///
+    /// # Panics
+    ///
+    /// n 大于桶的容量时 panic，这样的申请永远无法满足。
+    pub fn acquire(&self, n: u32) -> AcquireTokens<'_> {
+        assert!(n <= self.burst, "cannot acquire {n} tokens from a bucket of {}", self.burst);
+        AcquireTokens { limiter: self, needed: n, timer: None }
+    }
+
+    /// 令牌足够时立即取走 n 个令牌并返回 true，否则返回 false，不会等待
+    pub fn try_acquire(&self, n: u32) -> bool {
+        let now = self.handle.now();
+        let mut empty_at = self.empty_at.lock().unwrap();
+        let next = (*empty_at).max(now) + self.refill_time(n);
+        if next > now + self.refill_time(self.burst) {
+            return false;
+        }
+        *empty_at = next;
+        true
+    }
+
+    /// 当前桶里的令牌数
+    pub fn available(&self) -> u32 {
+        let now = self.handle.now();
+        let empty_at = *self.empty_at.lock().unwrap();
+        // 不足一个令牌的部分还没有补充完，也算作欠下的
+        let owed =
+            (empty_at.saturating_duration_since(now).as_nanos() * u128::from(self.rate)).div_ceil(self.per.as_nanos());
+        self.burst.saturating_sub(owed.min(u128::from(u32::MAX)) as u32)
+    }
+
+    /// 补充 n 个令牌需要的时间
+    ///
+    /// 按纳秒先乘后除，per 不能被 rate 整除时（例如每秒 3 个）舍入误差不超过 1 纳秒，不会随 n 放大。
+    fn refill_time(&self, n: u32) -> Duration {
+        let nanos = self.per.as_nanos() * u128::from(n) / u128::from(self.rate);
+        let secs = nanos / 1_000_000_000;
+        Duration::new(secs.min(u128::from(u64::MAX)) as u64, (nanos % 1_000_000_000) as u32)
+    }
+
+    /// 预定 n 个令牌，返回可以使用它们的时间点
+    fn reserve(&self, n: u32) -> Instant {
+        let now = self.handle.now();
+        let mut empty_at = self.empty_at.lock().unwrap();
+        *empty_at = (*empty_at).max(now) + self.refill_time(n);
+        *empty_at - self.refill_time(self.burst)
+    }
+
+    /// 退回预定了但没有使用的令牌
+    fn refund(&self, n: u32) {
+        let now = self.handle.now();
+        let mut empty_at = self.empty_at.lock().unwrap();
+        *empty_at = empty_at.checked_sub(self.refill_time(n)).map_or(now, |refunded| refunded.max(now));
+    }
+}
+
+/// 设置 RateLimiter 的选项，由 [`RateLimiter::builder`] 创建
+#[must_use]
+pub struct RateLimiterBuilder {
+    rate: u32,
+    per: Duration,
+    burst: Option<u32>,
+    handle: Option<Handle>,
+}
+
+impl RateLimiterBuilder {
+    /// 桶的容量，即空闲之后最多能连续取走的令牌数，默认等于 rate
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = Some(burst);
+        self
+    }
+
+    /// 使用指定的定时器驱动，例如 [`MockClock::handle`](crate::clock::MockClock::handle)
+    pub fn handle(mut self, handle: Handle) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+
+    /// # Panics
+    ///
+    /// rate、per 或者 burst 为 0 时 panic；补充一个令牌的时间不足 1 纳秒时也会 panic。
+    pub fn build(self) -> RateLimiter {
+        assert!(self.rate > 0 && !self.per.is_zero(), "rate limit must be non-zero");
+        assert!(
+            self.per.as_nanos() >= u128::from(self.rate),
+            "rate limit must refill at most one token per nanosecond"
+        );
+        let burst = self.burst.unwrap_or(self.rate);
+        assert!(burst > 0, "burst must be non-zero");
+        let handle = self.handle.unwrap_or_else(Handle::current);
+        // 创建时桶是满的
+        let empty_at = handle.now();
+        RateLimiter { handle, rate: self.rate, per: self.per, burst, empty_at: Mutex::new(empty_at) }
+    }
+}
+
+/// 等待令牌的 Future，由 [`RateLimiter::acquire`] 创建
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+pub struct AcquireTokens<'a> {
+    limiter: &'a RateLimiter,
+    needed: u32,
+    /// 预定令牌之后等待的定时器
+    timer: Option<TimerFuture>,
+}
+
+impl Future for AcquireTokens<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        if this.timer.is_none() {
+            let ready_at = this.limiter.reserve(this.needed);
+            if ready_at <= this.limiter.handle.now() {
+                return Poll::Ready(());
+            }
+            this.timer = Some(this.limiter.handle.sleep_until(ready_at));
+        }
+        let timer = this.timer.as_mut().expect("reserved above");
+        if Pin::new(timer).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        // 令牌已经用掉，drop 时不再退回
+        this.needed = 0;
+        Poll::Ready(())
+    }
+}
+
+impl Drop for AcquireTokens<'_> {
+    fn drop(&mut self) {
+        if self.timer.is_some() && self.needed > 0 {
+            self.limiter.refund(self.needed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::pin::pin;
+    use std::time::Duration;
+
+    use futures::FutureExt;
+
+    use crate::clock::MockClock;
+    use crate::rate_limit::RateLimiter;
+
+    #[test]
+    fn test_burst_then_steady_rate() {
+        let clock = MockClock::new();
+        // 每秒 10 个令牌，桶容量 3
+        let limiter = RateLimiter::builder(10, Duration::from_secs(1)).burst(3).handle(clock.handle()).build();
+        assert_eq!(3, limiter.available());
+        for _ in 0..3 {
+            assert_eq!(Some(()), limiter.acquire(1).now_or_never());
+        }
+        assert_eq!(0, limiter.available());
+        assert!(!limiter.try_acquire(1));
+
+        let mut next = pin!(limiter.acquire(1));
+        assert_eq!(None, next.as_mut().now_or_never());
+        clock.advance(Duration::from_millis(99));
+        assert_eq!(None, next.as_mut().now_or_never());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(Some(()), next.now_or_never());
+
+        // 空闲很久之后也只能攒满一个桶
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(3, limiter.available());
+        assert!(limiter.try_acquire(3));
+        assert!(!limiter.try_acquire(1));
+        clock.advance(Duration::from_millis(150));
+        assert_eq!(1, limiter.available());
+    }
+
+    #[test]
+    fn test_waiters_served_in_order() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::builder(10, Duration::from_secs(1)).burst(4).handle(clock.handle()).build();
+        assert!(limiter.try_acquire(4));
+
+        // 先到的大申请不会被后到的小申请插队
+        let mut big = pin!(limiter.acquire(4));
+        let mut small = pin!(limiter.acquire(1));
+        assert_eq!(None, big.as_mut().now_or_never());
+        assert_eq!(None, small.as_mut().now_or_never());
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(None, small.as_mut().now_or_never());
+        clock.advance(Duration::from_millis(300));
+        assert_eq!(Some(()), big.now_or_never());
+        assert_eq!(None, small.as_mut().now_or_never());
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(Some(()), small.now_or_never());
+    }
+
+    #[test]
+    fn test_dropped_acquire_refunds_tokens() {
+        let clock = MockClock::new();
+        let limiter = RateLimiter::builder(10, Duration::from_secs(1)).burst(2).handle(clock.handle()).build();
+        assert!(limiter.try_acquire(2));
+        let mut waiting = Box::pin(limiter.acquire(2));
+        assert_eq!(None, waiting.as_mut().now_or_never());
+        clock.advance(Duration::from_millis(100));
+        drop(waiting);
+        // 退回之后只欠 100ms 补充的一个令牌
+        assert_eq!(1, limiter.available());
+        assert!(limiter.try_acquire(1));
+    }
+
+    #[test]
+    fn test_rate_not_dividing_period() {
+        let clock = MockClock::new();
+        // 每秒 3 个令牌：1 秒除以 3 不是整数纳秒，取完整个桶之后也要整整 1 秒才能补满
+        let limiter = RateLimiter::builder(3, Duration::from_secs(1)).handle(clock.handle()).build();
+        assert!(limiter.try_acquire(3));
+        clock.advance(Duration::from_nanos(999_999_999));
+        assert_eq!(2, limiter.available());
+        assert!(!limiter.try_acquire(3));
+        clock.advance(Duration::from_nanos(1));
+        assert_eq!(3, limiter.available());
+        assert!(limiter.try_acquire(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "at most one token per nanosecond")]
+    fn test_sub_nanosecond_interval() {
+        // 每个令牌的补充时间会被截断成 0
+        drop(RateLimiter::builder(1000, Duration::from_nanos(999)).handle(MockClock::new().handle()).build());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot acquire 3 tokens from a bucket of 2")]
+    fn test_acquire_more_than_burst() {
+        let limiter = RateLimiter::builder(2, Duration::from_secs(1)).handle(MockClock::new().handle()).build();
+        drop(limiter.acquire(3));
+    }
+}
@@ -0,0 +1,376 @@
+//! 基于 epoll 的 I/O 反应器
+//!
+//! 与定时器驱动类似，所有异步 I/O 对象共用一个后台线程：文件描述符创建时以边沿触发的方式登记到 epoll，
+//! 后台线程在 `epoll_wait` 上等待，描述符变为可读或可写时唤醒等待它的任务。
+//!
+//! 边沿触发下，就绪状态需要由使用方自己维护：收到事件后标记为就绪，直到系统调用返回 `WouldBlock`
+//! 才清除。为了不丢失清除之前刚到达的事件，每次事件都会增加一个计数，只有计数没有变化时才真正清除。
+
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use futures::future::poll_fn;
+
+/// 反应器的句柄，所有克隆共享同一个 epoll 实例
+#[derive(Clone)]
+pub struct Reactor {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    epoll: OwnedFd,
+    sources: Mutex<Sources>,
+}
+
+struct Sources {
+    /// 已登记的描述符，key 是登记时分配的 token，会作为 epoll 事件的数据
+    entries: HashMap<u64, Arc<ScheduledIo>>,
+    next_token: u64,
+}
+
+/// 关心的就绪方向
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interest {
+    Readable,
+    Writable,
+}
+
+/// 单个描述符的就绪状态
+struct ScheduledIo {
+    state: Mutex<IoState>,
+}
+
+#[derive(Default)]
+struct IoState {
+    read: Direction,
+    write: Direction,
+}
+
+/// 一个方向上的就绪状态
+#[derive(Default)]
+struct Direction {
+    ready: bool,
+    /// 收到的事件数，用来判断清除就绪之前是否又来了新的事件
+    tick: u64,
+    waker: Option<Waker>,
+}
+
+impl IoState {
+    fn direction(&mut self, interest: Interest) -> &mut Direction {
+        match interest {
+            Interest::Readable => &mut self.read,
+            Interest::Writable => &mut self.write,
+        }
+    }
+}
+
+impl Reactor {
+    /// 进程内共享的反应器，第一次使用时启动后台线程
+    pub fn global() -> io::Result<Reactor> {
+        static GLOBAL: OnceLock<Reactor> = OnceLock::new();
+        if let Some(reactor) = GLOBAL.get() {
+            return Ok(reactor.clone());
+        }
+        let reactor = Reactor::new()?;
+        // 并发初始化时只保留第一个，多创建的实例没有启动线程，直接丢弃即可
+        Ok(GLOBAL.get_or_init(|| reactor.spawn()).clone())
+    }
+
+    fn new() -> io::Result<Reactor> {
+        let epoll = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        Ok(Reactor {
+            inner: Arc::new(Inner {
+                // SAFETY: epoll_create1 成功返回的描述符归我们所有
+                epoll: unsafe { OwnedFd::from_raw_fd(epoll) },
+                sources: Mutex::new(Sources { entries: HashMap::new(), next_token: 0 }),
+            }),
+        })
+    }
+
+    /// 启动后台线程
+    fn spawn(self) -> Reactor {
+        let inner = self.inner.clone();
+        thread::Builder::new()
+            .name("io-reactor".to_string())
+            .spawn(move || inner.run())
+            .expect("无法启动 I/O 反应器线程");
+        self
+    }
+
+    /// 以边沿触发方式登记描述符，返回的 Registration 被 drop 时注销
+    ///
+    /// 描述符必须已经设置为非阻塞，并且在 Registration 存活期间保持打开。
+    pub(crate) fn register(&self, fd: RawFd) -> io::Result<Registration> {
+        // 新登记的描述符先假设两个方向都就绪，第一次系统调用返回 WouldBlock 时才开始等待事件
+        let io = Arc::new(ScheduledIo {
+            state: Mutex::new(IoState {
+                read: Direction { ready: true, ..Default::default() },
+                write: Direction { ready: true, ..Default::default() },
+            }),
+        });
+        let token = {
+            let mut sources = self.inner.lock();
+            let token = sources.next_token;
+            sources.next_token += 1;
+            sources.entries.insert(token, io.clone());
+            token
+        };
+
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET) as u32,
+            u64: token,
+        };
+        let result = cvt(unsafe { libc::epoll_ctl(self.inner.epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, fd, &mut event) });
+        if let Err(err) = result {
+            self.inner.lock().entries.remove(&token);
+            return Err(err);
+        }
+        Ok(Registration { reactor: self.clone(), fd, token, io })
+    }
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, Sources> {
+        self.sources.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// 后台线程的主循环
+    fn run(&self) {
+        let mut events: Vec<libc::epoll_event> = vec![libc::epoll_event { events: 0, u64: 0 }; 1024];
+        loop {
+            let count = unsafe {
+                libc::epoll_wait(self.epoll.as_raw_fd(), events.as_mut_ptr(), events.len() as i32, -1)
+            };
+            if count < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                panic!("epoll_wait 失败: {err}");
+            }
+
+            let mut wakers = Vec::new();
+            for event in &events[..count as usize] {
+                // 复制出来，避免引用 packed 结构体的字段
+                let (flags, token) = (event.events as i32, event.u64);
+                // 已经注销的描述符直接跳过
+                let Some(io) = self.lock().entries.get(&token).cloned() else {
+                    continue;
+                };
+                let mut state = io.state.lock().unwrap();
+                // 出错或者挂断时两个方向都视为就绪，让使用方通过系统调用拿到具体的错误
+                let closed = flags & (libc::EPOLLHUP | libc::EPOLLERR) != 0;
+                if closed || flags & (libc::EPOLLIN | libc::EPOLLRDHUP) != 0 {
+                    state.read.ready = true;
+                    state.read.tick += 1;
+                    wakers.extend(state.read.waker.take());
+                }
+                if closed || flags & libc::EPOLLOUT != 0 {
+                    state.write.ready = true;
+                    state.write.tick += 1;
+                    wakers.extend(state.write.waker.take());
+                }
+            }
+            // 在锁外唤醒
+            wakers.drain(..).for_each(Waker::wake);
+        }
+    }
+}
+
+/// 描述符在反应器中的登记，drop 时注销
+pub(crate) struct Registration {
+    reactor: Reactor,
+    fd: RawFd,
+    token: u64,
+    io: Arc<ScheduledIo>,
+}
+
+impl Registration {
+    /// 等待某个方向就绪，返回就绪时的事件计数，用于之后的 `clear_ready`
+    pub(crate) fn poll_ready(&self, cx: &mut Context<'_>, interest: Interest) -> Poll<u64> {
+        let mut state = self.io.state.lock().unwrap();
+        let direction = state.direction(interest);
+        if direction.ready {
+            return Poll::Ready(direction.tick);
+        }
+        match &direction.waker {
+            Some(old) if old.will_wake(cx.waker()) => {}
+            _ => direction.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+
+    /// 系统调用返回 WouldBlock 后清除就绪状态；如果期间又收到了事件就保持就绪
+    pub(crate) fn clear_ready(&self, interest: Interest, tick: u64) {
+        let mut state = self.io.state.lock().unwrap();
+        let direction = state.direction(interest);
+        if direction.tick == tick {
+            direction.ready = false;
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        // 描述符此时还没有关闭，从 epoll 中删除后就不会再收到它的事件
+        unsafe {
+            libc::epoll_ctl(
+                self.reactor.inner.epoll.as_raw_fd(),
+                libc::EPOLL_CTL_DEL,
+                self.fd,
+                std::ptr::null_mut(),
+            )
+        };
+        self.reactor.inner.lock().entries.remove(&self.token);
+    }
+}
+
+/// 登记到反应器的描述符，可以用于 socket、管道等任何支持 epoll 的非阻塞描述符
+///
+/// 通过 `poll_read_with` / `poll_write_with` 执行非阻塞的系统调用：
+/// 调用返回 `WouldBlock` 时自动清除就绪状态并等待下一次事件。
+pub struct AsyncFd<T: AsRawFd> {
+    // 字段按声明顺序 drop：先注销，再关闭描述符
+    registration: Registration,
+    inner: Option<T>,
+}
+
+impl<T: AsRawFd> AsyncFd<T> {
+    /// 把 inner 登记到全局反应器，inner 必须已经设置为非阻塞
+    pub fn new(inner: T) -> io::Result<Self> {
+        let registration = Reactor::global()?.register(inner.as_raw_fd())?;
+        Ok(AsyncFd { registration, inner: Some(inner) })
+    }
+
+    pub fn get_ref(&self) -> &T {
+        self.inner.as_ref().expect("inner 只会在 into_inner 中被取走")
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.as_mut().expect("inner 只会在 into_inner 中被取走")
+    }
+
+    /// 注销并取回内部的描述符
+    pub fn into_inner(mut self) -> T {
+        self.inner.take().expect("inner 只会在 into_inner 中被取走")
+    }
+
+    /// 等待可读后执行 f，f 返回 WouldBlock 时继续等待
+    pub fn poll_read_with<R>(&self, cx: &mut Context<'_>, f: impl FnMut(&T) -> io::Result<R>) -> Poll<io::Result<R>> {
+        self.poll_io(cx, Interest::Readable, f)
+    }
+
+    /// 等待可写后执行 f，f 返回 WouldBlock 时继续等待
+    pub fn poll_write_with<R>(&self, cx: &mut Context<'_>, f: impl FnMut(&T) -> io::Result<R>) -> Poll<io::Result<R>> {
+        self.poll_io(cx, Interest::Writable, f)
+    }
+
+    pub async fn read_with<R>(&self, mut f: impl FnMut(&T) -> io::Result<R>) -> io::Result<R> {
+        poll_fn(|cx| self.poll_read_with(cx, &mut f)).await
+    }
+
+    pub async fn write_with<R>(&self, mut f: impl FnMut(&T) -> io::Result<R>) -> io::Result<R> {
+        poll_fn(|cx| self.poll_write_with(cx, &mut f)).await
+    }
+
+    /// 只等待某个方向就绪，不执行系统调用；就绪状态需要调用方通过 `poll_*_with` 清除
+    pub(crate) fn poll_ready(&self, cx: &mut Context<'_>, interest: Interest) -> Poll<u64> {
+        self.registration.poll_ready(cx, interest)
+    }
+
+    pub(crate) fn clear_ready(&self, interest: Interest, tick: u64) {
+        self.registration.clear_ready(interest, tick)
+    }
+
+    fn poll_io<R>(
+        &self,
+        cx: &mut Context<'_>,
+        interest: Interest,
+        mut f: impl FnMut(&T) -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        loop {
+            let tick = match self.registration.poll_ready(cx, interest) {
+                Poll::Ready(tick) => tick,
+                Poll::Pending => return Poll::Pending,
+            };
+            match f(self.get_ref()) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.registration.clear_ready(interest, tick);
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+}
+
+impl<T: AsRawFd> AsRawFd for AsyncFd<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.get_ref().as_raw_fd()
+    }
+}
+
+/// 把返回 -1 的系统调用转换成 io::Error
+pub(crate) fn cvt(result: i32) -> io::Result<i32> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    use crate::reactor::{cvt, AsyncFd};
+    use crate::{block_on, new_executor_and_spawner};
+
+    /// 创建一对非阻塞的管道
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        cvt(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) }).unwrap();
+        // SAFETY: pipe2 成功返回的两个描述符归我们所有
+        unsafe { (File::from(OwnedFd::from_raw_fd(fds[0])), File::from(OwnedFd::from_raw_fd(fds[1]))) }
+    }
+
+    #[test]
+    fn test_pipe_read_waits_for_writer() {
+        let (reader, writer) = pipe();
+        let reader = AsyncFd::new(reader).unwrap();
+        let writer = AsyncFd::new(writer).unwrap();
+        let (executor, spawner) = new_executor_and_spawner();
+
+        // 读端先开始等待，写端之后才写入；数据量超过管道缓冲区，写端也会等待读端
+        const LEN: usize = 1 << 20;
+        let received = spawner.spawn(async move {
+            let mut received = Vec::new();
+            let mut buf = [0; 4096];
+            loop {
+                let n = reader.read_with(|mut file| file.read(&mut buf)).await.unwrap();
+                if n == 0 {
+                    break received;
+                }
+                received.extend_from_slice(&buf[..n]);
+            }
+        });
+        spawner.spawn(async move {
+            let data: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+            let mut written = 0;
+            while written < LEN {
+                written += writer.write_with(|mut file| file.write(&data[written..])).await.unwrap();
+            }
+            // drop 写端后读端读到 EOF
+        });
+        executor.run();
+
+        let received = block_on(received).unwrap();
+        assert_eq!(LEN, received.len());
+        assert!(received.iter().enumerate().all(|(i, b)| *b == i as u8));
+    }
+}
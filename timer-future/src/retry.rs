@@ -0,0 +1,264 @@
+//! 失败重试
+//!
+//! [`retry`] 反复调用一个返回 `Result` 的异步操作，直到成功或者 [`RetryPolicy`] 规定的次数、时间用完。
+//! 两次尝试之间按指数退避等待：第 k 次重试前的退避上限是 `initial * multiplier^(k-1)`，不超过 `max_backoff`；
+//! 默认使用全抖动（full jitter），实际等待 [0, 上限) 中的随机时间，避免大量客户端在同一时刻重试。
+
+use std::future::Future;
+use std::time::Duration;
+
+use crate::driver::Handle;
+use crate::rng::Rng;
+
+/// 重试策略
+///
+/// ```
+/// use std::time::Duration;
+/// use timer_future::{retry, RetryPolicy};
+///
+/// let policy = RetryPolicy::exponential(Duration::from_millis(1)).max_attempts(5);
+/// let mut calls = 0;
+/// let result = timer_future::block_on(retry(policy, || {
+///     calls += 1;
+///     let attempt = calls;
+///     async move { if attempt < 3 { Err("unavailable") } else { Ok(attempt) } }
+/// }));
+/// assert_eq!(Ok(3), result);
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    initial_backoff: Duration,
+    multiplier: f64,
+    max_backoff: Duration,
+    jitter: bool,
+    max_attempts: Option<u32>,
+    max_elapsed: Option<Duration>,
+    seed: Option<u64>,
+}
+
+impl RetryPolicy {
+    /// 第一次重试前的退避上限为 initial，之后每次翻倍，最长 60 秒，使用全抖动
+    ///
+    /// 默认不限制尝试次数和总时间，一般应该用 [`max_attempts`](Self::max_attempts) 或者
+    /// [`max_elapsed`](Self::max_elapsed) 设置上限。
+    pub fn exponential(initial: Duration) -> Self {
+        RetryPolicy {
+            initial_backoff: initial,
+            multiplier: 2.0,
+            max_backoff: Duration::from_secs(60),
+            jitter: true,
+            max_attempts: None,
+            max_elapsed: None,
+            seed: None,
+        }
+    }
+
+    /// 每次重试退避上限增长的倍数，默认为 2
+    ///
+    /// # Panics
+    ///
+    /// multiplier 小于 1 或者不是有限的数时 panic。
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        assert!(multiplier.is_finite() && multiplier >= 1.0, "backoff multiplier must be finite and at least 1");
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// 退避上限的最大值，默认为 60 秒
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// 是否使用全抖动，默认为 true；为 false 时每次都等待完整的退避上限
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// 最多尝试几次，包括第一次
+    ///
+    /// # Panics
+    ///
+    /// attempts 为 0 时 panic。
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        assert!(attempts > 0, "max attempts must be non-zero");
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// 从第一次尝试开始，最多花多长时间；下一次退避结束时会超过这个时间就不再重试
+    pub fn max_elapsed(mut self, max_elapsed: Duration) -> Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    /// 生成抖动的随机数种子，默认每次调用 retry 使用不同的随机种子
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// 第 retry 次重试（从 1 开始）前的退避上限
+    fn backoff_cap(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.powi(retry.saturating_sub(1).min(i32::MAX as u32) as i32);
+        let secs = self.initial_backoff.as_secs_f64() * factor;
+        // factor 溢出成无穷大时 secs 是无穷大，initial 为 0 时 0 * inf 是 NaN
+        if !secs.is_finite() || secs >= self.max_backoff.as_secs_f64() {
+            self.max_backoff
+        } else {
+            Duration::from_secs_f64(secs)
+        }
+    }
+}
+
+/// 按 policy 重试 operation，返回第一次成功的结果，或者放弃时最后一次的错误
+///
+/// 每次尝试调用 operation 创建一个新的 Future。退避使用调用 retry 时 [`Handle::current`] 返回的驱动，
+/// 配合 [`MockClock::enter`](crate::clock::MockClock::enter) 可以在测试中控制重试的时间。
+pub fn retry<F, Fut, T, E>(policy: RetryPolicy, mut operation: F) -> impl Future<Output = Result<T, E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let handle = Handle::current();
+    async move {
+        let mut rng = policy.seed.map_or_else(Rng::from_entropy, Rng::new);
+        let start = handle.now();
+        let mut attempts = 0;
+        loop {
+            let err = match operation().await {
+                Ok(output) => return Ok(output),
+                Err(err) => err,
+            };
+            attempts += 1;
+            if policy.max_attempts.is_some_and(|max| attempts >= max) {
+                return Err(err);
+            }
+            let cap = policy.backoff_cap(attempts);
+            let backoff = if policy.jitter { rng.below(cap) } else { cap };
+            let resume_at = handle.now() + backoff;
+            if policy.max_elapsed.is_some_and(|max| resume_at - start > max) {
+                return Err(err);
+            }
+            handle.sleep_until(resume_at).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::pin::pin;
+    use std::time::{Duration, Instant};
+
+    use futures::FutureExt;
+
+    use crate::clock::MockClock;
+    use crate::retry::{retry, RetryPolicy};
+
+    #[test]
+    fn test_backoff_cap() {
+        let policy = RetryPolicy::exponential(Duration::from_secs(1)).max_backoff(Duration::from_secs(10));
+        let caps: Vec<_> = (1..=6).map(|retry| policy.backoff_cap(retry)).collect();
+        assert_eq!(
+            vec![
+                Duration::from_secs(1),
+                Duration::from_secs(2),
+                Duration::from_secs(4),
+                Duration::from_secs(8),
+                Duration::from_secs(10),
+                Duration::from_secs(10)
+            ],
+            caps
+        );
+        assert_eq!(Duration::from_secs(10), policy.backoff_cap(u32::MAX));
+        let policy = RetryPolicy::exponential(Duration::from_secs(1)).multiplier(1.5);
+        assert_eq!(Duration::from_millis(2250), policy.backoff_cap(3));
+        // multiplier^k 溢出成无穷大，乘上 0 得到 NaN
+        let policy = RetryPolicy::exponential(Duration::ZERO).multiplier(10.0).max_backoff(Duration::from_secs(10));
+        assert_eq!(Duration::ZERO, policy.backoff_cap(1));
+        assert_eq!(Duration::from_secs(10), policy.backoff_cap(400));
+    }
+
+    #[test]
+    #[should_panic(expected = "backoff multiplier must be finite")]
+    fn test_infinite_multiplier() {
+        let _ = RetryPolicy::exponential(Duration::from_secs(1)).multiplier(f64::INFINITY);
+    }
+
+    #[test]
+    fn test_exponential_backoff_without_jitter() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let start = clock.now();
+        let attempts = RefCell::new(Vec::<Instant>::new());
+        let policy = RetryPolicy::exponential(Duration::from_secs(1)).jitter(false).max_attempts(4);
+        let mut retrying = pin!(retry(policy, || {
+            attempts.borrow_mut().push(clock.now());
+            async { Err::<(), _>(attempts.borrow().len()) }
+        }));
+        for _ in 0..7 {
+            assert_eq!(None, retrying.as_mut().now_or_never());
+            clock.advance(Duration::from_secs(1));
+        }
+        // 第 4 次失败后放弃，返回最后一次的错误
+        assert_eq!(Some(Err(4)), retrying.now_or_never());
+        let offsets: Vec<_> = attempts.borrow().iter().map(|at| (*at - start).as_secs()).collect();
+        assert_eq!(vec![0, 1, 3, 7], offsets);
+    }
+
+    #[test]
+    fn test_full_jitter_and_max_elapsed() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let start = clock.now();
+        let attempts = RefCell::new(Vec::<Instant>::new());
+        let policy = RetryPolicy::exponential(Duration::from_secs(4))
+            .max_backoff(Duration::from_secs(16))
+            .max_elapsed(Duration::from_secs(60))
+            .seed(42);
+        let mut retrying = pin!(retry(policy, || {
+            attempts.borrow_mut().push(clock.now());
+            async { Err::<(), _>("unavailable") }
+        }));
+        let mut result = None;
+        while result.is_none() {
+            result = retrying.as_mut().now_or_never();
+            clock.advance(Duration::from_millis(100));
+        }
+        assert_eq!(Some(Err("unavailable")), result);
+
+        let attempts = attempts.borrow();
+        for (retry, pair) in attempts.windows(2).enumerate() {
+            let cap = Duration::from_secs(4 << retry.min(2));
+            assert!(pair[1] - pair[0] < cap + Duration::from_millis(100), "{:?}", pair[1] - pair[0]);
+        }
+        // 抖动之后的等待时间各不相同，总时间不超过 max_elapsed
+        assert!(attempts.windows(2).map(|pair| pair[1] - pair[0]).any(|gap| gap < Duration::from_secs(4)));
+        assert!(*attempts.last().unwrap() - start <= Duration::from_secs(60));
+        assert!(attempts.len() > 4);
+    }
+
+    #[test]
+    fn test_success_stops_retrying() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let mut calls = 0;
+        let policy = RetryPolicy::exponential(Duration::from_secs(1)).jitter(false);
+        let mut retrying = pin!(retry(policy, || {
+            calls += 1;
+            let attempt = calls;
+            async move {
+                if attempt < 2 {
+                    Err(())
+                } else {
+                    Ok(attempt)
+                }
+            }
+        }));
+        assert_eq!(None, retrying.as_mut().now_or_never());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Some(Ok(2)), retrying.now_or_never());
+    }
+}
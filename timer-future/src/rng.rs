@@ -0,0 +1,35 @@
+//! 生成抖动用的伪随机数
+//!
+//! 只用来把定时打散，不需要密码学强度，也不值得为此引入 rand。
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+/// splitmix64 伪随机数生成器
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    /// 每次调用得到不同的种子，来源是标准库 HashMap 的随机 key
+    pub(crate) fn from_entropy() -> Self {
+        Rng(RandomState::new().hash_one(0u64))
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// [0, max) 中均匀分布的时间，max 为 0 时返回 0
+    pub(crate) fn below(&mut self, max: Duration) -> Duration {
+        let nanos = (self.next_u64() as u128 * max.as_nanos()) >> 64;
+        Duration::from_nanos(nanos as u64)
+    }
+}
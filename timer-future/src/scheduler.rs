@@ -0,0 +1,596 @@
+//! 周期任务调度器
+//!
+//! [`Scheduler`] 在执行器上按计划反复运行异步任务。计划可以是 cron 表达式、固定频率或者固定间隔：
+//!
+//! - [`Schedule::Cron`]：在 cron 表达式匹配的每个整分钟运行，时间按 UTC 计算
+//! - [`Schedule::FixedRate`]：每隔固定的时间运行一次，不考虑上一次运行花了多久
+//! - [`Schedule::FixedDelay`]：上一次运行结束后，等待固定的时间再运行下一次
+//!
+//! 每个任务由一个调度循环负责：睡到下一个计划时间，生成一次运行，再计算下一个计划时间。
+//! 每次运行都是执行器上独立的任务，panic 不会影响调度循环。
+//!
+//! ```
+//! use std::time::Duration;
+//! use timer_future::clock::MockClock;
+//! use timer_future::scheduler::{Schedule, Scheduler};
+//!
+//! let clock = MockClock::new();
+//! let (executor, spawner) = timer_future::new_executor_and_spawner();
+//! let scheduler = Scheduler::with_clock(&spawner, clock.handle(), std::time::UNIX_EPOCH);
+//! let job = scheduler.job(Schedule::cron("*/5 * * * *").unwrap()).spawn(|| async {
+//!     println!("every five minutes");
+//! });
+//! executor.run_until_stalled();
+//!
+//! clock.advance(Duration::from_secs(600));
+//! executor.run_until_stalled();
+//! // 一次拨动跨过了 00:05 和 00:10 两个计划时间，默认跳过错过的 00:05，只运行 00:10 这一次
+//! assert_eq!((1, 1), (job.stats().runs, job.stats().missed));
+//! clock.advance(Duration::from_secs(300));
+//! executor.run_until_stalled();
+//! assert_eq!(2, job.stats().runs);
+//! job.cancel();
+//! ```
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::future::{select, Either};
+
+use crate::driver::Handle;
+use crate::rng::Rng;
+use crate::sync::CancellationToken;
+use crate::{JoinHandle, Spawner};
+
+pub use cron::{CronError, CronExpr};
+
+mod cron;
+
+/// 任务的运行计划
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// 在 cron 表达式匹配的时间运行
+    Cron(CronExpr),
+    /// 从生成任务开始，每隔一段时间运行一次
+    FixedRate(Duration),
+    /// 生成任务一段时间后第一次运行，之后每次运行结束后再等待这么久
+    FixedDelay(Duration),
+}
+
+impl Schedule {
+    /// 解析 cron 表达式
+    pub fn cron(expr: &str) -> Result<Schedule, CronError> {
+        expr.parse().map(Schedule::Cron)
+    }
+}
+
+/// 错过计划时间时的处理策略
+///
+/// 调度循环醒来时，如果一个计划时间之后的下一个计划时间也已经过去了（执行器被阻塞、进程被挂起，
+/// 或者时钟被一次拨过了多个周期），这个计划时间就算作错过。已经到了、但下一个计划时间还没到的
+/// 最近一次计划时间不算错过，它总是会运行。只对 `Cron` 和 `FixedRate` 有效。
+///
+/// 和 [`MissedTickBehavior::Skip`](crate::MissedTickBehavior::Skip) 一样，默认的 `Skip` 醒来后只运行一次。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MissedRunPolicy {
+    /// 跳过所有错过的运行，只运行最近一次计划时间
+    #[default]
+    Skip,
+    /// 先补一次运行代替所有错过的运行，再运行最近一次计划时间
+    RunOnce,
+    /// 逐个补上所有错过的运行，再运行最近一次计划时间；受最大并发数限制时等待之前的运行结束
+    RunAll,
+}
+
+/// 周期任务调度器，克隆开销很小
+#[derive(Clone)]
+pub struct Scheduler {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    spawner: Spawner,
+    /// 定时器使用的驱动
+    handle: Handle,
+    /// 创建调度器时驱动时钟的时间和对应的墙上时间，用来在两者之间换算
+    origin: Instant,
+    wall_origin: SystemTime,
+}
+
+impl Scheduler {
+    /// 在 spawner 对应的执行器上运行任务，使用当前线程的定时器驱动和系统时间
+    pub fn new(spawner: &Spawner) -> Scheduler {
+        Scheduler::with_clock(spawner, Handle::current(), SystemTime::now())
+    }
+
+    /// 使用指定的定时器驱动，wall_time 是驱动当前时间对应的墙上时间，cron 表达式按它计算
+    ///
+    /// 配合 [`MockClock`](crate::clock::MockClock) 可以在测试中精确地控制任务什么时候运行。
+    pub fn with_clock(spawner: &Spawner, handle: Handle, wall_time: SystemTime) -> Scheduler {
+        Scheduler {
+            inner: Arc::new(Inner {
+                spawner: spawner.clone(),
+                origin: handle.now(),
+                handle,
+                wall_origin: wall_time,
+            }),
+        }
+    }
+
+    /// 创建一个按 schedule 运行的任务，通过返回的 JobBuilder 设置其他选项
+    pub fn job(&self, schedule: Schedule) -> JobBuilder<'_> {
+        JobBuilder {
+            scheduler: self,
+            schedule,
+            name: None,
+            jitter: Duration::ZERO,
+            max_concurrency: 1,
+            missed_run_policy: MissedRunPolicy::default(),
+            seed: None,
+        }
+    }
+}
+
+impl Inner {
+    fn wall_time(&self, at: Instant) -> SystemTime {
+        match at.checked_duration_since(self.origin) {
+            Some(since) => self.wall_origin + since,
+            None => self.wall_origin - (self.origin - at),
+        }
+    }
+
+    fn instant(&self, wall: SystemTime) -> Instant {
+        match wall.duration_since(self.wall_origin) {
+            Ok(since) => self.origin + since,
+            Err(err) => self.origin.checked_sub(err.duration()).unwrap_or(self.origin),
+        }
+    }
+
+    /// 从 now 开始的第一个计划时间
+    fn first_run(&self, schedule: &Schedule, now: Instant) -> Option<Instant> {
+        match schedule {
+            Schedule::Cron(expr) => expr.next_after(self.wall_time(now)).map(|wall| self.instant(wall)),
+            Schedule::FixedRate(period) | Schedule::FixedDelay(period) => Some(now + *period),
+        }
+    }
+
+    /// 计划时间 due 已经到了，返回到 now 为止到期的计划时间个数（包括 due）和下一个还没到的计划时间
+    fn due_runs(&self, schedule: &Schedule, due: Instant, now: Instant) -> (u64, Option<Instant>) {
+        match schedule {
+            Schedule::Cron(expr) => {
+                let mut count = 1;
+                let mut last = due;
+                loop {
+                    match expr.next_after(self.wall_time(last)).map(|wall| self.instant(wall)) {
+                        Some(next) if next <= now => {
+                            count += 1;
+                            last = next;
+                        }
+                        next => return (count, next),
+                    }
+                }
+            }
+            Schedule::FixedRate(period) | Schedule::FixedDelay(period) => {
+                let passed = (now.saturating_duration_since(due).as_nanos() / period.as_nanos()) as u64;
+                let next = due + Duration::from_nanos((period.as_nanos() * (passed as u128 + 1)) as u64);
+                (passed + 1, Some(next))
+            }
+        }
+    }
+}
+
+/// 设置任务的选项，由 [`Scheduler::job`] 创建
+#[must_use]
+pub struct JobBuilder<'a> {
+    scheduler: &'a Scheduler,
+    schedule: Schedule,
+    name: Option<String>,
+    jitter: Duration,
+    max_concurrency: usize,
+    missed_run_policy: MissedRunPolicy,
+    seed: Option<u64>,
+}
+
+impl JobBuilder<'_> {
+    /// 任务的名字，调度循环和每次运行都以它命名，会出现在执行器的 dump 里
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// 每次运行在计划时间之后再随机推迟 [0, jitter) 的时间，避免大量任务在同一时刻运行，默认不推迟
+    ///
+    /// 推迟不会改变之后的计划时间。
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// 同时进行的运行最多有几个，默认为 1；达到上限时，到期的运行被跳过
+    ///
+    /// # Panics
+    ///
+    /// max 为 0 时 panic。
+    pub fn max_concurrency(mut self, max: usize) -> Self {
+        assert!(max > 0, "max concurrency must be non-zero");
+        self.max_concurrency = max;
+        self
+    }
+
+    /// 错过计划时间时的处理策略，默认为 [`MissedRunPolicy::Skip`]
+    pub fn missed_run_policy(mut self, policy: MissedRunPolicy) -> Self {
+        self.missed_run_policy = policy;
+        self
+    }
+
+    /// 生成抖动的随机数种子，默认每个任务使用不同的随机种子
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// 生成任务，每次运行时调用 job 创建一个新的 Future
+    ///
+    /// # Panics
+    ///
+    /// 固定频率或者固定间隔的周期为 0 时 panic。
+    pub fn spawn<F, Fut>(self, job: F) -> JobHandle
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        if let Schedule::FixedRate(period) | Schedule::FixedDelay(period) = self.schedule {
+            assert!(!period.is_zero(), "schedule period must be non-zero");
+        }
+        let inner = self.scheduler.inner.clone();
+        let handle = JobHandle { token: CancellationToken::new(), stats: Arc::new(Stats::default()) };
+        let job_loop = JobLoop {
+            inner: inner.clone(),
+            schedule: self.schedule,
+            name: self.name.clone(),
+            jitter: self.jitter,
+            max_concurrency: self.max_concurrency,
+            missed_run_policy: self.missed_run_policy,
+            rng: self.seed.map_or_else(Rng::from_entropy, Rng::new),
+            job,
+            in_flight: Vec::new(),
+            token: handle.token.clone(),
+            stats: handle.stats.clone(),
+        };
+        let builder = inner.spawner.build_task();
+        let builder = match self.name {
+            Some(name) => builder.name(name),
+            None => builder,
+        };
+        builder.spawn(job_loop.run());
+        handle
+    }
+}
+
+/// 一个任务的调度循环
+struct JobLoop<F> {
+    inner: Arc<Inner>,
+    schedule: Schedule,
+    name: Option<String>,
+    jitter: Duration,
+    max_concurrency: usize,
+    missed_run_policy: MissedRunPolicy,
+    rng: Rng,
+    job: F,
+    /// 还没有结束的运行
+    in_flight: Vec<JoinHandle<()>>,
+    token: CancellationToken,
+    stats: Arc<Stats>,
+}
+
+impl<F, Fut> JobLoop<F>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    async fn run(mut self) {
+        let mut next = self.inner.first_run(&self.schedule, self.inner.handle.now());
+        // cron 表达式不会再匹配时结束
+        while let Some(due) = next {
+            let timer = pin!(self.inner.handle.sleep_until(due));
+            let token = self.token.clone();
+            if let Either::Right(_) = select(timer, pin!(token.cancelled())).await {
+                break;
+            }
+            let now = self.inner.handle.now();
+
+            if let Schedule::FixedDelay(delay) = self.schedule {
+                // 等这次运行结束后再计算下一次
+                if let Some(run) = self.start_run() {
+                    let _ = run.await;
+                }
+                next = Some(self.inner.handle.now() + delay);
+                continue;
+            }
+
+            let (count, following) = self.inner.due_runs(&self.schedule, due, now);
+            next = following;
+            // 最后一次计划时间总是运行，前面的 count - 1 次都是错过的
+            let runs = match self.missed_run_policy {
+                MissedRunPolicy::Skip => 1,
+                MissedRunPolicy::RunOnce => count.min(2),
+                MissedRunPolicy::RunAll => count,
+            };
+            self.stats.missed.fetch_add(count - runs, Ordering::Relaxed);
+            if runs > 1 {
+                // 补上的运行不能因为并发上限被跳过，等待空位
+                for _ in 0..runs {
+                    self.wait_for_slot().await;
+                    self.start_run();
+                }
+            } else {
+                self.in_flight.retain(|run| !run.is_finished());
+                if self.in_flight.len() < self.max_concurrency {
+                    self.start_run();
+                } else {
+                    self.stats.overlapped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// 生成一次运行；固定间隔的任务直接返回它的 JoinHandle，其他任务把它记录在 in_flight 中
+    fn start_run(&mut self) -> Option<JoinHandle<()>> {
+        self.stats.runs.fetch_add(1, Ordering::Relaxed);
+        let delay = self.rng.below(self.jitter);
+        let handle = self.inner.handle.clone();
+        let run = (self.job)();
+        let run = async move {
+            if !delay.is_zero() {
+                handle.sleep(delay).await;
+            }
+            run.await;
+        };
+        let builder = self.inner.spawner.build_task();
+        let builder = match &self.name {
+            Some(name) => builder.name(name.clone()),
+            None => builder,
+        };
+        let run = builder.spawn(run);
+        if let Schedule::FixedDelay(_) = self.schedule {
+            return Some(run);
+        }
+        self.in_flight.push(run);
+        None
+    }
+
+    /// 等到同时进行的运行少于上限
+    async fn wait_for_slot(&mut self) {
+        loop {
+            self.in_flight.retain(|run| !run.is_finished());
+            if self.in_flight.len() < self.max_concurrency {
+                return;
+            }
+            // 结果被取走后 is_finished 不再为 true，所以直接移除
+            let _ = self.in_flight.remove(0).await;
+        }
+    }
+}
+
+/// 调度任务的句柄，用来查看运行统计或者停止任务
+///
+/// drop 掉句柄不会停止任务。
+#[derive(Debug)]
+pub struct JobHandle {
+    token: CancellationToken,
+    stats: Arc<Stats>,
+}
+
+impl JobHandle {
+    /// 停止调度，之后不会再开始新的运行；已经开始的运行不受影响
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// 到目前为止的运行统计
+    pub fn stats(&self) -> JobStats {
+        JobStats {
+            runs: self.stats.runs.load(Ordering::Relaxed),
+            overlapped: self.stats.overlapped.load(Ordering::Relaxed),
+            missed: self.stats.missed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    runs: AtomicU64,
+    overlapped: AtomicU64,
+    missed: AtomicU64,
+}
+
+/// 任务的运行统计
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JobStats {
+    /// 开始的运行次数
+    pub runs: u64,
+    /// 因为同时进行的运行达到上限而跳过的次数
+    pub overlapped: u64,
+    /// 错过的计划时间中，按 [`MissedRunPolicy`] 没有补上的次数
+    pub missed: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    use crate::clock::MockClock;
+    use crate::scheduler::{JobHandle, MissedRunPolicy, Schedule, Scheduler};
+    use crate::{new_executor_and_spawner, Executor};
+
+    /// 2024-01-01 00:00:00 UTC
+    const NEW_YEAR_2024: u64 = 1_704_067_200;
+
+    struct Harness {
+        executor: Executor,
+        clock: MockClock,
+        scheduler: Scheduler,
+        start: Instant,
+        /// 每次运行开始时距离 start 的时间
+        started: Arc<Mutex<Vec<Duration>>>,
+    }
+
+    impl Harness {
+        fn new(wall_time: SystemTime) -> Harness {
+            let clock = MockClock::new();
+            let (executor, spawner) = new_executor_and_spawner();
+            let scheduler = Scheduler::with_clock(&spawner, clock.handle(), wall_time);
+            Harness { executor, start: clock.now(), clock, scheduler, started: Arc::default() }
+        }
+
+        /// 生成一个记录开始时间、运行 duration 的任务
+        fn spawn(&self, schedule: Schedule, duration: Duration, configure: impl FnOnce(&mut JobOptions)) -> JobHandle {
+            let mut options = JobOptions::default();
+            configure(&mut options);
+            let (handle, start, started) = (self.clock.handle(), self.start, self.started.clone());
+            let mut builder = self
+                .scheduler
+                .job(schedule)
+                .max_concurrency(options.max_concurrency)
+                .missed_run_policy(options.policy)
+                .jitter(options.jitter)
+                .seed(7);
+            if let Some(name) = options.name {
+                builder = builder.name(name);
+            }
+            let job = builder.spawn(move || {
+                let (handle, started) = (handle.clone(), started.clone());
+                async move {
+                    started.lock().unwrap().push(handle.now() - start);
+                    if !duration.is_zero() {
+                        handle.sleep(duration).await;
+                    }
+                }
+            });
+            self.executor.run_until_stalled();
+            job
+        }
+
+        /// 每次前进 step，直到总共前进 total
+        fn advance(&self, total: Duration, step: Duration) {
+            let mut advanced = Duration::ZERO;
+            while advanced < total {
+                self.clock.advance(step);
+                self.executor.run_until_stalled();
+                advanced += step;
+            }
+        }
+
+        fn started_secs(&self) -> Vec<u64> {
+            self.started.lock().unwrap().iter().map(Duration::as_secs).collect()
+        }
+    }
+
+    struct JobOptions {
+        max_concurrency: usize,
+        policy: MissedRunPolicy,
+        jitter: Duration,
+        name: Option<&'static str>,
+    }
+
+    impl Default for JobOptions {
+        fn default() -> Self {
+            JobOptions { max_concurrency: 1, policy: MissedRunPolicy::Skip, jitter: Duration::ZERO, name: None }
+        }
+    }
+
+    #[test]
+    fn test_cron_job_runs_on_schedule() {
+        // 从 00:02:30 开始，每 5 分钟运行一次：00:05 和 00:10
+        let harness = Harness::new(UNIX_EPOCH + Duration::from_secs(NEW_YEAR_2024 + 150));
+        let job = harness.spawn(Schedule::cron("*/5 * * * *").unwrap(), Duration::ZERO, |options| {
+            options.name = Some("cron");
+        });
+        assert_eq!(Some("cron"), harness.executor.dump()[0].name.as_deref());
+        harness.advance(Duration::from_secs(720), Duration::from_secs(30));
+        assert_eq!(vec![150, 450], harness.started_secs());
+        assert_eq!(2, job.stats().runs);
+    }
+
+    #[test]
+    fn test_fixed_rate_skips_overlapping_runs() {
+        let harness = Harness::new(SystemTime::now());
+        // 每 10 秒运行一次，每次运行 25 秒，同时只允许一个
+        let job = harness.spawn(Schedule::FixedRate(Duration::from_secs(10)), Duration::from_secs(25), |_| {});
+        harness.advance(Duration::from_secs(60), Duration::from_secs(5));
+        assert_eq!(vec![10, 40], harness.started_secs());
+        assert_eq!((2, 4), (job.stats().runs, job.stats().overlapped));
+    }
+
+    #[test]
+    fn test_fixed_rate_with_more_concurrency() {
+        let harness = Harness::new(SystemTime::now());
+        let job = harness.spawn(Schedule::FixedRate(Duration::from_secs(10)), Duration::from_secs(25), |options| {
+            options.max_concurrency = 2
+        });
+        harness.advance(Duration::from_secs(60), Duration::from_secs(5));
+        // 10-35 和 20-45 同时进行时跳过 30，40-65 和 50-75 同时进行时跳过 60
+        assert_eq!(vec![10, 20, 40, 50], harness.started_secs());
+        assert_eq!(2, job.stats().overlapped);
+    }
+
+    #[test]
+    fn test_fixed_delay_waits_for_previous_run() {
+        let harness = Harness::new(SystemTime::now());
+        harness.spawn(Schedule::FixedDelay(Duration::from_secs(10)), Duration::from_secs(5), |_| {});
+        harness.advance(Duration::from_secs(60), Duration::from_secs(5));
+        assert_eq!(vec![10, 25, 40, 55], harness.started_secs());
+    }
+
+    #[test]
+    fn test_missed_run_policies() {
+        for (policy, runs, missed) in [
+            (MissedRunPolicy::Skip, vec![10, 45, 50], 2),
+            (MissedRunPolicy::RunOnce, vec![10, 45, 45, 50], 1),
+            (MissedRunPolicy::RunAll, vec![10, 45, 45, 45, 50], 0),
+        ] {
+            let harness = Harness::new(SystemTime::now());
+            let job = harness.spawn(Schedule::FixedRate(Duration::from_secs(10)), Duration::ZERO, |options| {
+                options.policy = policy
+            });
+            harness.advance(Duration::from_secs(10), Duration::from_secs(10));
+            // 暂停 35 秒：20、30 两次错过了，40 是最近一次计划时间，不算错过
+            harness.advance(Duration::from_secs(35), Duration::from_secs(35));
+            harness.advance(Duration::from_secs(5), Duration::from_secs(5));
+            assert_eq!(runs, harness.started_secs(), "{policy:?}");
+            assert_eq!(missed, job.stats().missed, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn test_jitter_and_cancel() {
+        let harness = Harness::new(SystemTime::now());
+        let job = harness.spawn(Schedule::FixedRate(Duration::from_secs(60)), Duration::ZERO, |options| {
+            options.jitter = Duration::from_secs(30)
+        });
+        // 最后一次运行可能推迟到 330 之前
+        harness.advance(Duration::from_secs(330), Duration::from_secs(1));
+        let started = harness.started_secs();
+        assert_eq!(5, started.len());
+        for (run, started) in started.iter().enumerate() {
+            // 推迟不影响之后的计划时间
+            let scheduled = 60 * (run as u64 + 1);
+            assert!((scheduled..scheduled + 30).contains(started), "{started:?}");
+        }
+        assert!(started.iter().zip([60, 120, 180, 240, 300]).any(|(started, scheduled)| *started != scheduled));
+
+        job.cancel();
+        harness.executor.run_until_stalled();
+        harness.advance(Duration::from_secs(300), Duration::from_secs(60));
+        assert_eq!(5, harness.started_secs().len());
+        assert_eq!(0, harness.executor.dump().len());
+    }
+}
@@ -0,0 +1,347 @@
+//! cron 表达式
+//!
+//! 支持标准的五个字段：分钟（0-59）、小时（0-23）、日（1-31）、月（1-12 或 JAN-DEC）、
+//! 星期（0-7 或 SUN-SAT，0 和 7 都是星期日）。每个字段可以是 `*`、单个值、范围 `a-b`、
+//! 带步长的 `*/n`、`a-b/n` 或 `a/n`，以及用逗号分隔的列表。也支持 `@yearly`、`@monthly`、
+//! `@weekly`、`@daily`、`@hourly` 这几个简写。
+//!
+//! 和 cron 一样，日和星期都被限制（都不以 `*` 开头）时，满足其中一个即可；时间按 UTC 计算。
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 往后最多查找多少年，超过后认为表达式不会再匹配（例如 `0 0 30 2 *`）
+const MAX_YEARS_AHEAD: i64 = 8;
+
+const MONTH_NAMES: [&str; 12] = ["JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC"];
+const DAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// 解析后的 cron 表达式，每个字段用位图表示允许的值
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// 日字段以 `*` 开头
+    any_day_of_month: bool,
+    /// 星期字段以 `*` 开头
+    any_day_of_week: bool,
+}
+
+impl CronExpr {
+    /// 严格晚于 time 的下一个匹配时间（整分钟），找不到时返回 None
+    pub fn next_after(&self, time: SystemTime) -> Option<SystemTime> {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            // 1970 年之前的时间向下取整到秒
+            Err(err) => -(err.duration().as_secs_f64().ceil() as i64),
+        };
+        // 从下一个整分钟开始查找
+        let mut minute = secs.div_euclid(60) + 1;
+        let limit_year = civil_from_days(minute.div_euclid(1440)).0 + MAX_YEARS_AHEAD;
+        loop {
+            let days = minute.div_euclid(1440);
+            let (year, month, day) = civil_from_days(days);
+            if year > limit_year {
+                return None;
+            }
+            if !contains(self.months, month) {
+                // 跳到下个月的第一天
+                let (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+                minute = days_from_civil(year, month, 1) * 1440;
+                continue;
+            }
+            if !self.matches_day(day, weekday(days)) {
+                minute = (days + 1) * 1440;
+                continue;
+            }
+            let minute_of_day = minute.rem_euclid(1440);
+            if !contains(self.hours, minute_of_day / 60) {
+                minute = minute - minute_of_day % 60 + 60;
+                continue;
+            }
+            if !contains(self.minutes, minute_of_day % 60) {
+                minute += 1;
+                continue;
+            }
+            let secs = minute * 60;
+            return Some(if secs >= 0 {
+                UNIX_EPOCH + Duration::from_secs(secs as u64)
+            } else {
+                UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+            });
+        }
+    }
+
+    fn matches_day(&self, day: i64, weekday: i64) -> bool {
+        let day_of_month = contains(self.days_of_month, day);
+        let day_of_week = contains(self.days_of_week, weekday);
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+}
+
+impl FromStr for CronExpr {
+    type Err = CronError;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let expr = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expr => expr,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err(CronError::FieldCount(fields.len()));
+        };
+        let mut days_of_week_bits = parse_field(days_of_week, Field::DayOfWeek)?;
+        // 7 也表示星期日
+        if contains(days_of_week_bits, 7) {
+            days_of_week_bits = (days_of_week_bits & !(1 << 7)) | 1;
+        }
+        Ok(CronExpr {
+            minutes: parse_field(minutes, Field::Minute)?,
+            hours: parse_field(hours, Field::Hour)?,
+            days_of_month: parse_field(days_of_month, Field::DayOfMonth)?,
+            months: parse_field(months, Field::Month)?,
+            days_of_week: days_of_week_bits,
+            any_day_of_month: days_of_month.starts_with('*'),
+            any_day_of_week: days_of_week.starts_with('*'),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Minute,
+    Hour,
+    DayOfMonth,
+    Month,
+    DayOfWeek,
+}
+
+impl Field {
+    fn range(self) -> (i64, i64) {
+        match self {
+            Field::Minute => (0, 59),
+            Field::Hour => (0, 23),
+            Field::DayOfMonth => (1, 31),
+            Field::Month => (1, 12),
+            Field::DayOfWeek => (0, 7),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Minute => "minute",
+            Field::Hour => "hour",
+            Field::DayOfMonth => "day-of-month",
+            Field::Month => "month",
+            Field::DayOfWeek => "day-of-week",
+        }
+    }
+
+    /// 解析单个值，月和星期可以用英文缩写
+    fn parse_value(self, value: &str) -> Option<i64> {
+        let names: &[&str] = match self {
+            Field::Month => &MONTH_NAMES,
+            Field::DayOfWeek => &DAY_NAMES,
+            _ => &[],
+        };
+        let offset = if self == Field::Month { 1 } else { 0 };
+        let value = match names.iter().position(|name| name.eq_ignore_ascii_case(value)) {
+            Some(index) => index as i64 + offset,
+            None => value.parse().ok()?,
+        };
+        let (min, max) = self.range();
+        (min..=max).contains(&value).then_some(value)
+    }
+}
+
+/// 把一个字段解析成位图
+fn parse_field(field: &str, kind: Field) -> Result<u64, CronError> {
+    let invalid = || CronError::InvalidField { field: kind.name(), value: field.to_string() };
+    let (min, max) = kind.range();
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<i64>().ok().filter(|step| *step > 0).ok_or_else(invalid)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => {
+                    (kind.parse_value(start).ok_or_else(invalid)?, kind.parse_value(end).ok_or_else(invalid)?)
+                }
+                // `a/n` 表示从 a 开始到最大值
+                None if part.contains('/') => (kind.parse_value(range).ok_or_else(invalid)?, max),
+                None => {
+                    let value = kind.parse_value(range).ok_or_else(invalid)?;
+                    (value, value)
+                }
+            },
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn contains(bits: u64, value: i64) -> bool {
+    bits & (1 << value) != 0
+}
+
+/// 1970-01-01 之后的第 days 天对应的公历日期 (年, 月, 日)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    // 算法来自 Howard Hinnant 的 chrono-Compatible Low-Level Date Algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// 公历日期对应的 1970-01-01 之后的天数
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// 星期几，0 是星期日（1970-01-01 是星期四）
+fn weekday(days: i64) -> i64 {
+    (days + 4).rem_euclid(7)
+}
+
+/// cron 表达式解析失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CronError {
+    /// 字段个数不是 5
+    FieldCount(usize),
+    /// 某个字段的值无法解析或者超出范围
+    InvalidField { field: &'static str, value: String },
+}
+
+impl Display for CronError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CronError::FieldCount(count) => write!(f, "expected 5 fields in cron expression, found {count}"),
+            CronError::InvalidField { field, value } => write!(f, "invalid {field} field: {value:?}"),
+        }
+    }
+}
+
+impl Error for CronError {}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use crate::scheduler::cron::{civil_from_days, days_from_civil, CronError, CronExpr};
+
+    /// UTC 时间对应的 SystemTime
+    fn utc(year: i64, month: i64, day: i64, hour: u64, minute: u64) -> SystemTime {
+        let days = days_from_civil(year, month, day) as u64;
+        UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60)
+    }
+
+    fn next(expr: &str, after: SystemTime) -> Option<SystemTime> {
+        expr.parse::<CronExpr>().unwrap().next_after(after)
+    }
+
+    #[test]
+    fn test_civil_dates() {
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!((2000, 2, 29), civil_from_days(days_from_civil(2000, 2, 29)));
+        assert_eq!((1969, 12, 31), civil_from_days(-1));
+        for days in -1000..100_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days, days_from_civil(year, month, day));
+        }
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        let expr: CronExpr = "*/15 9-17 1,15 JAN-mar mon-fri".parse().unwrap();
+        assert_eq!((1 << 0) | (1 << 15) | (1 << 30) | (1 << 45), expr.minutes);
+        assert_eq!(((1 << 18) - 1) & !((1 << 9) - 1), expr.hours);
+        assert_eq!((1 << 1) | (1 << 15), expr.days_of_month);
+        assert_eq!(0b1110, expr.months);
+        assert_eq!(0b111110, expr.days_of_week);
+
+        // a/n 从 a 开始到最大值，7 和 0 都是星期日
+        let expr: CronExpr = "50/5 */6 * * 7".parse().unwrap();
+        assert_eq!((1 << 50) | (1 << 55), expr.minutes);
+        assert_eq!((1 << 0) | (1 << 6) | (1 << 12) | (1 << 18), expr.hours);
+        assert_eq!(1, expr.days_of_week);
+
+        assert_eq!("0 0 * * *".parse::<CronExpr>(), "@daily".parse::<CronExpr>());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Err(CronError::FieldCount(4)), "* * * *".parse::<CronExpr>());
+        for (expr, field) in [
+            ("60 * * * *", "minute"),
+            ("* 24 * * *", "hour"),
+            ("* * 0 * *", "day-of-month"),
+            ("* * * 13 *", "month"),
+            ("* * * * 8", "day-of-week"),
+            ("*/0 * * * *", "minute"),
+            ("5-1 * * * *", "minute"),
+            ("* * * FOO *", "month"),
+            ("1,,2 * * * *", "minute"),
+        ] {
+            let err = expr.parse::<CronExpr>().unwrap_err();
+            assert!(matches!(err, CronError::InvalidField { field: f, .. } if f == field), "{expr}: {err}");
+        }
+        assert_eq!("invalid hour field: \"24\"", "* 24 * * *".parse::<CronExpr>().unwrap_err().to_string());
+    }
+
+    #[test]
+    fn test_next_after() {
+        let start = utc(2024, 1, 1, 0, 2) + Duration::from_secs(30);
+        assert_eq!(Some(utc(2024, 1, 1, 0, 5)), next("*/5 * * * *", start));
+        // 严格晚于给定时间
+        assert_eq!(Some(utc(2024, 1, 1, 0, 10)), next("*/5 * * * *", utc(2024, 1, 1, 0, 5)));
+        // 跨天、跨月、跨年
+        assert_eq!(Some(utc(2024, 1, 2, 0, 0)), next("@daily", start));
+        assert_eq!(Some(utc(2024, 2, 1, 0, 0)), next("@monthly", start));
+        assert_eq!(Some(utc(2025, 1, 1, 0, 0)), next("@yearly", start));
+        assert_eq!(Some(utc(2024, 12, 31, 23, 59)), next("59 23 31 12 *", utc(2024, 6, 1, 0, 0)));
+        // 2024-01-01 是星期一，下一个星期日是 1 月 7 日
+        assert_eq!(Some(utc(2024, 1, 7, 0, 0)), next("@weekly", start));
+        // 闰年的 2 月 29 日
+        assert_eq!(Some(utc(2028, 2, 29, 12, 0)), next("0 12 29 2 *", utc(2024, 3, 1, 0, 0)));
+        // 日和星期都被限制时满足其中一个即可：1 月 3 日是星期三
+        assert_eq!(Some(utc(2024, 1, 3, 0, 0)), next("0 0 15 * WED", start));
+        assert_eq!(Some(utc(2024, 1, 15, 0, 0)), next("0 0 15 * WED", utc(2024, 1, 13, 0, 0)));
+        // 只限制星期时日字段不起作用
+        assert_eq!(Some(utc(2024, 1, 3, 0, 0)), next("0 0 * * 3", start));
+        // 永远不会匹配的日期
+        assert_eq!(None, next("0 0 30 2 *", start));
+    }
+}
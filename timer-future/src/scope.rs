@@ -0,0 +1,461 @@
+//! 结构化并发
+//!
+//! [`Spawner::scope`] 创建一个作用域，在其中生成的子任务都必须结束之后，作用域才会完成：
+//!
+//! - 子任务返回 `Err` 或者 panic 时，取消作用域的 [`CancellationToken`]，其余子任务在下一次 poll 时被丢弃
+//! - 作用域的主体也会在取消时被丢弃；作用域返回第一个错误，子任务的 panic 在作用域完成时重新抛出
+//! - 嵌套作用域的令牌是外层令牌的子令牌，外层取消会传播到所有内层作用域
+//!
+//! ```
+//! use timer_future::{block_on, new_executor_and_spawner, ScopeError};
+//!
+//! let (executor, spawner) = new_executor_and_spawner();
+//! let inner = spawner.clone();
+//! let handle = spawner.spawn(async move {
+//!     inner
+//!         .scope(|s| async move {
+//!             s.spawn(async { Ok(()) });
+//!             s.spawn(async { Err::<(), _>("boom") });
+//!             Ok::<_, &str>(())
+//!         })
+//!         .await
+//! });
+//! executor.run();
+//! assert_eq!(Err(ScopeError::Failed("boom")), block_on(handle).unwrap());
+//! ```
+
+use std::any::Any;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::panic::{resume_unwind, AssertUnwindSafe};
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+use futures::future::{poll_fn, select, Either};
+use futures::FutureExt;
+
+use crate::sync::CancellationToken;
+use crate::{JoinHandle, Spawner};
+
+/// 作用域的句柄，可以克隆并传给子任务，用来继续生成子任务或者创建嵌套作用域
+pub struct Scope<E> {
+    inner: Arc<Inner<E>>,
+}
+
+struct Inner<E> {
+    spawner: Spawner,
+    token: CancellationToken,
+    state: Mutex<State<E>>,
+}
+
+struct State<E> {
+    /// 还没有结束的子任务数
+    remaining: usize,
+    /// 第一个错误，之后的错误被丢弃
+    error: Option<E>,
+    /// 第一个 panic 的载荷
+    panic: Option<Box<dyn Any + Send>>,
+    /// 等待子任务全部结束的作用域主体
+    waker: Option<Waker>,
+}
+
+impl Spawner {
+    /// 创建一个作用域，等主体和其中生成的所有子任务都结束后返回
+    ///
+    /// 主体或者任意子任务返回错误时，其余子任务被取消，作用域返回第一个错误；子任务 panic 时同样取消其余子任务，
+    /// 并在作用域结束时把 panic 重新抛出。作用域必须在执行器的任务中等待，否则子任务无法运行。
+    pub async fn scope<T, E, F, Fut>(&self, f: F) -> Result<T, ScopeError<E>>
+    where
+        E: Send + 'static,
+        F: FnOnce(Scope<E>) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        Scope::run(self.clone(), CancellationToken::new(), f).await
+    }
+}
+
+impl<E: Send + 'static> Scope<E> {
+    /// 生成一个子任务，返回的 JoinHandle 在子任务成功时得到 Some，失败或者被取消时得到 None
+    pub fn spawn<T, Fut>(&self, future: Fut) -> JoinHandle<Option<T>>
+    where
+        T: Send + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        self.inner.state.lock().unwrap().remaining += 1;
+        let guard = ChildGuard { scope: self.clone() };
+        let token = self.inner.token.clone();
+        self.inner.spawner.spawn(async move {
+            // 子任务不论以什么方式结束（包括执行器关闭时被直接丢弃），guard 都会通知作用域
+            let guard = guard;
+            let run = pin!(AssertUnwindSafe(future).catch_unwind());
+            let cancelled = pin!(token.cancelled());
+            // 先检查取消：在已经取消的作用域里生成的子任务一次也不会被 poll
+            match select(cancelled, run).await {
+                Either::Left(_) => None,
+                Either::Right((Ok(Ok(output)), _)) => Some(output),
+                Either::Right((Ok(Err(err)), _)) => {
+                    guard.scope.fail(err);
+                    None
+                }
+                Either::Right((Err(panic), _)) => {
+                    guard.scope.panicked(panic);
+                    None
+                }
+            }
+        })
+    }
+
+    /// 在当前作用域内创建嵌套作用域，外层作用域被取消时它也会被取消
+    pub async fn scope<T, F, Fut>(&self, f: F) -> Result<T, ScopeError<E>>
+    where
+        F: FnOnce(Scope<E>) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        Scope::run(self.inner.spawner.clone(), self.inner.token.child_token(), f).await
+    }
+
+    /// 作用域的取消令牌，子任务可以用它主动检查是否已经被取消
+    pub fn token(&self) -> CancellationToken {
+        self.inner.token.clone()
+    }
+
+    async fn run<T, F, Fut>(spawner: Spawner, token: CancellationToken, f: F) -> Result<T, ScopeError<E>>
+    where
+        F: FnOnce(Scope<E>) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        // 作用域没有等到子任务全部结束就被丢弃（外层超时、select 或者 unwind）时取消子任务，
+        // 子任务不能比作用域活得更久
+        let mut guard = CancelOnDrop(Some(token.clone()));
+        let scope = Scope {
+            inner: Arc::new(Inner {
+                spawner,
+                token,
+                state: Mutex::new(State { remaining: 0, error: None, panic: None, waker: None }),
+            }),
+        };
+
+        // 主体和取消竞争，取消时直接丢弃主体
+        let body = {
+            let body = pin!(f(scope.clone()));
+            let cancelled = pin!(scope.inner.token.cancelled());
+            match select(body, cancelled).await {
+                Either::Left((Ok(output), _)) => Some(output),
+                Either::Left((Err(err), _)) => {
+                    scope.fail(err);
+                    None
+                }
+                Either::Right(_) => None,
+            }
+        };
+
+        // 等待所有子任务结束
+        poll_fn(|cx| {
+            let mut state = scope.inner.state.lock().unwrap();
+            if state.remaining == 0 {
+                return Poll::Ready(());
+            }
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await;
+        guard.0 = None;
+
+        let (error, panic) = {
+            let mut state = scope.inner.state.lock().unwrap();
+            (state.error.take(), state.panic.take())
+        };
+        if let Some(panic) = panic {
+            resume_unwind(panic);
+        }
+        match (error, body) {
+            (Some(err), _) => Err(ScopeError::Failed(err)),
+            (None, Some(output)) if !scope.inner.token.is_cancelled() => Ok(output),
+            (None, _) => Err(ScopeError::Cancelled),
+        }
+    }
+
+    /// 记录错误并取消其余子任务
+    fn fail(&self, err: E) {
+        self.inner.state.lock().unwrap().error.get_or_insert(err);
+        self.inner.token.cancel();
+    }
+
+    fn panicked(&self, panic: Box<dyn Any + Send>) {
+        self.inner.state.lock().unwrap().panic.get_or_insert(panic);
+        self.inner.token.cancel();
+    }
+}
+
+/// drop 时取消令牌，除非已经被解除
+struct CancelOnDrop(Option<CancellationToken>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            token.cancel();
+        }
+    }
+}
+
+impl<E> Clone for Scope<E> {
+    fn clone(&self) -> Self {
+        Scope { inner: self.inner.clone() }
+    }
+}
+
+impl<E> Debug for Scope<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let remaining = self.inner.state.lock().unwrap().remaining;
+        f.debug_struct("Scope")
+            .field("remaining", &remaining)
+            .field("cancelled", &self.inner.token.is_cancelled())
+            .finish()
+    }
+}
+
+/// 子任务结束时减少作用域的计数，最后一个子任务结束时唤醒作用域主体
+struct ChildGuard<E> {
+    scope: Scope<E>,
+}
+
+impl<E> Drop for ChildGuard<E> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.scope.inner.state.lock().unwrap();
+            state.remaining -= 1;
+            if state.remaining == 0 {
+                state.waker.take()
+            } else {
+                None
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// 作用域没有正常完成的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScopeError<E> {
+    /// 主体或者某个子任务返回的第一个错误
+    Failed(E),
+    /// 作用域被外层作用域取消
+    Cancelled,
+}
+
+impl<E: Display> Display for ScopeError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScopeError::Failed(err) => write!(f, "scope failed: {err}"),
+            ScopeError::Cancelled => write!(f, "scope was cancelled"),
+        }
+    }
+}
+
+impl<E: Debug + Display> Error for ScopeError<E> {}
+
+#[cfg(test)]
+mod test {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::future::pending;
+
+    use crate::sync::oneshot;
+    use crate::{block_on, new_executor_and_spawner, timeout, ScopeError, TimerFuture};
+
+    /// 在执行器上运行 future 并返回结果
+    fn run<T: Send + 'static>(make: impl FnOnce(crate::Spawner) -> futures::future::BoxFuture<'static, T>) -> T {
+        let (executor, spawner) = new_executor_and_spawner();
+        let handle = spawner.spawn(make(spawner.clone()));
+        executor.run();
+        block_on(handle).unwrap()
+    }
+
+    #[test]
+    fn test_waits_for_all_children() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let counter = finished.clone();
+        let result = run(move |spawner| {
+            Box::pin(async move {
+                spawner
+                    .scope(|s| async move {
+                        for i in 0..5u64 {
+                            let counter = counter.clone();
+                            s.spawn(async move {
+                                TimerFuture::new(Duration::from_millis(i * 2)).await;
+                                counter.fetch_add(1, Ordering::SeqCst);
+                                Ok(())
+                            });
+                        }
+                        // 主体先返回，作用域仍然要等子任务
+                        Ok::<_, ()>("body")
+                    })
+                    .await
+            })
+        });
+        assert_eq!(Ok("body"), result);
+        assert_eq!(5, finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_error_cancels_siblings() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let counter = finished.clone();
+        let result = run(move |spawner| {
+            Box::pin(async move {
+                spawner
+                    .scope(|s| async move {
+                        let slow = counter.clone();
+                        s.spawn(async move {
+                            TimerFuture::new(Duration::from_secs(60)).await;
+                            slow.fetch_add(1, Ordering::SeqCst);
+                            Ok(())
+                        });
+                        s.spawn::<(), _>(async move {
+                            TimerFuture::new(Duration::from_millis(5)).await;
+                            Err("first")
+                        });
+                        s.spawn::<(), _>(async move {
+                            TimerFuture::new(Duration::from_millis(50)).await;
+                            Err("second")
+                        });
+                        // 主体在等待时也会被取消
+                        pending::<()>().await;
+                        Ok(())
+                    })
+                    .await
+            })
+        });
+        assert_eq!(Err(ScopeError::Failed("first")), result);
+        assert_eq!(0, finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_dropped_scope_cancels_children() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let counter = dropped.clone();
+        let result = run(move |spawner| {
+            Box::pin(async move {
+                let scope = spawner.scope(|s| async move {
+                    for _ in 0..3 {
+                        let guard = DropCounter(counter.clone());
+                        s.spawn(async move {
+                            let _guard = guard;
+                            pending::<Result<(), ()>>().await
+                        });
+                    }
+                    pending::<()>().await;
+                    Ok(())
+                });
+                // 作用域在超时时被丢弃，子任务随之被取消，执行器才能结束
+                timeout(Duration::from_millis(10), scope).await.is_err()
+            })
+        });
+        assert!(result);
+        assert_eq!(3, dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_child_spawned_after_cancel_never_runs() {
+        let polled = Arc::new(AtomicUsize::new(0));
+        let counter = polled.clone();
+        let result = run(move |spawner| {
+            Box::pin(async move {
+                spawner
+                    .scope(|s| async move {
+                        s.token().cancel();
+                        let handle = s.spawn(async move {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            Ok(())
+                        });
+                        assert_eq!(None, handle.await.unwrap());
+                        Ok::<_, ()>(())
+                    })
+                    .await
+            })
+        });
+        assert_eq!(Err(ScopeError::Cancelled), result);
+        assert_eq!(0, polled.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_panic_cancels_siblings_and_resumes() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let inner = spawner.clone();
+        let handle = spawner.spawn(async move {
+            let result = AssertUnwindSafe(inner.scope(|s| async move {
+                s.spawn(async { pending::<Result<(), ()>>().await });
+                s.spawn::<(), _>(async {
+                    TimerFuture::new(Duration::from_millis(1)).await;
+                    panic!("child panicked");
+                });
+                Ok::<_, ()>(())
+            }));
+            futures::FutureExt::catch_unwind(result).await
+        });
+        executor.run();
+        let panic = block_on(handle).unwrap().unwrap_err();
+        assert_eq!(Some(&"child panicked"), panic.downcast_ref::<&str>());
+        // panic 在 scope 中被捕获，不会影响执行器线程
+        assert!(catch_unwind(|| ()).is_ok());
+    }
+
+    /// drop 时计数，用来确认子任务是被取消而不是泄漏
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_cancellation_propagates_to_nested_scopes() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let counter = dropped.clone();
+        let (result, nested) = run(move |spawner| {
+            Box::pin(async move {
+                let (tx, rx) = oneshot::channel();
+                let plain = spawner.clone();
+                let result = spawner
+                    .scope(|outer| async move {
+                        // 嵌套作用域在一个普通任务里运行，外层取消时它不会被直接丢弃，而是通过令牌得知取消
+                        let nested_scope = outer.clone();
+                        let nested = plain.spawn(async move {
+                            nested_scope
+                                .scope(|inner| async move {
+                                    for _ in 0..3 {
+                                        let guard = DropCounter(counter.clone());
+                                        inner.spawn(async move {
+                                            let _guard = guard;
+                                            pending::<Result<(), &str>>().await
+                                        });
+                                    }
+                                    pending::<()>().await;
+                                    Ok(())
+                                })
+                                .await
+                        });
+                        tx.send(nested).unwrap();
+                        outer.spawn::<(), _>(async {
+                            TimerFuture::new(Duration::from_millis(5)).await;
+                            Err("outer failed")
+                        });
+                        Ok(())
+                    })
+                    .await;
+                (result, rx.await.unwrap().await.unwrap())
+            })
+        });
+        assert_eq!(Err(ScopeError::Failed("outer failed")), result);
+        assert_eq!(Err(ScopeError::Cancelled), nested);
+        assert_eq!(3, dropped.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,412 @@
+//! 基于时间的 Stream 组合子
+//!
+//! [`StreamTimeExt`] 为所有 [`Stream`] 提供按时间窗口处理元素的适配器，定时器使用创建适配器时
+//! [`Handle::current`] 返回的驱动：
+//!
+//! - [`debounce`](StreamTimeExt::debounce)：元素之后安静了一段时间才输出它，期间的新元素替换旧元素
+//! - [`throttle`](StreamTimeExt::throttle)：相邻两个元素的输出至少间隔一段时间，不丢弃元素
+//! - [`chunks_timeout`](StreamTimeExt::chunks_timeout)：攒够 n 个元素，或者第一个元素等待超时时输出一批
+//! - [`sample`](StreamTimeExt::sample)：每个周期输出一次这个周期内最新的元素
+//!
+//! 上游结束时，还没有输出的元素会立即输出，然后结束。
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Stream;
+
+use crate::driver::Handle;
+use crate::{interval_at, Interval, MissedTickBehavior, TimerFuture};
+
+/// debounce 和 sample 每次 poll 最多从上游取出的元素数，上游一直就绪时也要把执行权交还给执行器
+const MAX_ITEMS_PER_POLL: usize = 32;
+
+/// 基于时间的 Stream 适配器
+///
+/// ```
+/// use std::time::Duration;
+/// use futures::StreamExt;
+/// use timer_future::stream::StreamTimeExt;
+///
+/// let batches: Vec<Vec<i32>> = timer_future::block_on(
+///     futures::stream::iter(1..=5).chunks_timeout(2, Duration::from_millis(10)).collect(),
+/// );
+/// assert_eq!(vec![vec![1, 2], vec![3, 4], vec![5]], batches);
+/// ```
+pub trait StreamTimeExt: Stream {
+    /// 元素之后 duration 内没有新元素才输出它；有新元素则丢弃旧元素，重新计时
+    fn debounce(self, duration: Duration) -> Debounce<Self>
+    where
+        Self: Sized,
+    {
+        Debounce { stream: self, duration, handle: Handle::current(), timer: None, pending: None, done: false }
+    }
+
+    /// 相邻两个元素的输出至少间隔 duration，间隔内不会 poll 上游，元素不会被丢弃
+    fn throttle(self, duration: Duration) -> Throttle<Self>
+    where
+        Self: Sized,
+    {
+        Throttle { stream: self, duration, handle: Handle::current(), timer: None }
+    }
+
+    /// 把元素攒成批：攒够 capacity 个，或者批次中第一个元素到达后超过 duration 时输出
+    ///
+    /// # Panics
+    ///
+    /// capacity 为 0 时 panic。
+    fn chunks_timeout(self, capacity: usize, duration: Duration) -> ChunksTimeout<Self>
+    where
+        Self: Sized,
+    {
+        assert!(capacity > 0, "chunk capacity must be non-zero");
+        ChunksTimeout {
+            stream: self,
+            capacity,
+            duration,
+            handle: Handle::current(),
+            timer: None,
+            batch: Vec::with_capacity(capacity),
+            done: false,
+        }
+    }
+
+    /// 每隔 period 输出一次这段时间内最新的元素，这段时间内没有新元素时不输出
+    ///
+    /// 第一次采样在一个周期之后；采样不及时错过的周期会被跳过，不会连续输出。
+    ///
+    /// # Panics
+    ///
+    /// period 为 0 时 panic。
+    fn sample(self, period: Duration) -> Sample<Self>
+    where
+        Self: Sized,
+    {
+        let mut ticker = interval_at(Handle::current().now() + period, period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        Sample { stream: self, ticker, latest: None, done: false }
+    }
+}
+
+impl<S: Stream + ?Sized> StreamTimeExt for S {}
+
+/// 由 [`StreamTimeExt::debounce`] 创建
+#[must_use = "streams do nothing unless polled"]
+pub struct Debounce<S: Stream> {
+    stream: S,
+    duration: Duration,
+    handle: Handle,
+    /// 第一次收到元素时创建，之后通过 reset 重新计时
+    timer: Option<TimerFuture>,
+    pending: Option<S::Item>,
+    done: bool,
+}
+
+impl<S: Stream> Stream for Debounce<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        // SAFETY: stream 字段是结构性固定的，之后不会再移动它；其他字段不需要固定
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+
+        // 先取走上游就绪的元素，只保留最新的一个
+        let mut budget = MAX_ITEMS_PER_POLL;
+        while !this.done && budget > 0 {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    budget -= 1;
+                    this.pending = Some(item);
+                    let deadline = this.handle.now() + this.duration;
+                    match &mut this.timer {
+                        Some(timer) => timer.reset(deadline),
+                        None => this.timer = Some(this.handle.sleep_until(deadline)),
+                    }
+                }
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+        if this.done {
+            return Poll::Ready(this.pending.take());
+        }
+        let elapsed =
+            this.pending.is_some() && this.timer.as_mut().is_some_and(|timer| Pin::new(timer).poll(cx).is_ready());
+        if elapsed {
+            Poll::Ready(this.pending.take())
+        } else {
+            if budget == 0 {
+                // 上游可能还有就绪的元素，但没有人会再唤醒我们，自己安排下一次 poll
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        }
+    }
+}
+
+/// 由 [`StreamTimeExt::throttle`] 创建
+#[must_use = "streams do nothing unless polled"]
+pub struct Throttle<S> {
+    stream: S,
+    duration: Duration,
+    handle: Handle,
+    /// 上一个元素输出后，到期前不能输出下一个元素
+    timer: Option<TimerFuture>,
+}
+
+impl<S: Stream> Stream for Throttle<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        // SAFETY: stream 字段是结构性固定的，之后不会再移动它；其他字段不需要固定
+        let this = unsafe { self.get_unchecked_mut() };
+        let stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+
+        if let Some(timer) = &mut this.timer {
+            if Pin::new(timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.timer = None;
+        }
+        let item = stream.poll_next(cx);
+        if let Poll::Ready(Some(_)) = item {
+            this.timer = Some(this.handle.sleep(this.duration));
+        }
+        item
+    }
+}
+
+/// 由 [`StreamTimeExt::chunks_timeout`] 创建
+#[must_use = "streams do nothing unless polled"]
+pub struct ChunksTimeout<S: Stream> {
+    stream: S,
+    capacity: usize,
+    duration: Duration,
+    handle: Handle,
+    /// 批次中第一个元素到达时开始计时
+    timer: Option<TimerFuture>,
+    batch: Vec<S::Item>,
+    done: bool,
+}
+
+impl<S: Stream> ChunksTimeout<S> {
+    fn take_batch(&mut self) -> Vec<S::Item> {
+        self.timer = None;
+        std::mem::replace(&mut self.batch, Vec::with_capacity(self.capacity))
+    }
+}
+
+impl<S: Stream> Stream for ChunksTimeout<S> {
+    type Item = Vec<S::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Vec<S::Item>>> {
+        // SAFETY: stream 字段是结构性固定的，之后不会再移动它；其他字段不需要固定
+        let this = unsafe { self.get_unchecked_mut() };
+
+        while !this.done {
+            match unsafe { Pin::new_unchecked(&mut this.stream) }.poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    if this.batch.is_empty() {
+                        this.timer = Some(this.handle.sleep(this.duration));
+                    }
+                    this.batch.push(item);
+                    if this.batch.len() == this.capacity {
+                        return Poll::Ready(Some(this.take_batch()));
+                    }
+                }
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+        if this.done {
+            return Poll::Ready((!this.batch.is_empty()).then(|| this.take_batch()));
+        }
+        if this.timer.as_mut().is_some_and(|timer| Pin::new(timer).poll(cx).is_ready()) {
+            Poll::Ready(Some(this.take_batch()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// 由 [`StreamTimeExt::sample`] 创建
+#[must_use = "streams do nothing unless polled"]
+pub struct Sample<S: Stream> {
+    stream: S,
+    ticker: Interval,
+    /// 上一次采样之后最新的元素
+    latest: Option<S::Item>,
+    done: bool,
+}
+
+impl<S: Stream> Stream for Sample<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        // SAFETY: stream 字段是结构性固定的，之后不会再移动它；其他字段不需要固定
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+
+        let mut budget = MAX_ITEMS_PER_POLL;
+        while !this.done && budget > 0 {
+            match stream.as_mut().poll_next(cx) {
+                Poll::Ready(Some(item)) => {
+                    budget -= 1;
+                    this.latest = Some(item);
+                }
+                Poll::Ready(None) => this.done = true,
+                Poll::Pending => break,
+            }
+        }
+        if this.done {
+            return Poll::Ready(this.latest.take());
+        }
+        // 没有新元素的 tick 直接跳过，继续 poll 让 ticker 登记下一次的 waker
+        while this.ticker.poll_tick(cx).is_ready() {
+            if let Some(item) = this.latest.take() {
+                return Poll::Ready(Some(item));
+            }
+        }
+        if budget == 0 {
+            // 上游可能还有就绪的元素，但没有人会再唤醒我们，自己安排下一次 poll
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures::channel::mpsc::{unbounded, UnboundedSender};
+    use futures::{FutureExt, Stream, StreamExt};
+
+    use crate::clock::MockClock;
+    use crate::stream::StreamTimeExt;
+
+    /// 取出所有立即就绪的元素，None 表示流已经结束
+    fn ready_items<S: Stream + Unpin>(stream: &mut S) -> Vec<Option<S::Item>> {
+        let mut ready = Vec::new();
+        while let Some(item) = stream.next().now_or_never() {
+            let end = item.is_none();
+            ready.push(item);
+            if end {
+                break;
+            }
+        }
+        ready
+    }
+
+    /// 按 (时间间隔, 元素) 推进时钟并发送元素，每一步之后收集立即就绪的输出
+    fn drive<S: Stream + Unpin>(
+        clock: &MockClock,
+        sender: &UnboundedSender<u32>,
+        stream: &mut S,
+        steps: &[(u64, Option<u32>)],
+    ) -> Vec<Vec<Option<S::Item>>> {
+        steps
+            .iter()
+            .map(|&(wait, item)| {
+                clock.advance(Duration::from_millis(wait));
+                if let Some(item) = item {
+                    sender.unbounded_send(item).unwrap();
+                }
+                ready_items(stream)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_debounce() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let (sender, receiver) = unbounded();
+        let mut debounced = receiver.debounce(Duration::from_millis(100));
+        let output = drive(
+            &clock,
+            &sender,
+            &mut debounced,
+            &[(0, Some(1)), (50, Some(2)), (99, None), (1, None), (10, Some(3)), (100, None), (0, Some(4))],
+        );
+        // 1 被 2 替换，2 安静了 100ms 后输出
+        assert_eq!(vec![vec![], vec![], vec![], vec![Some(2)], vec![], vec![Some(3)], vec![]], output);
+        // 上游结束时立即输出还在等待的元素
+        drop(sender);
+        assert_eq!(vec![Some(4), None], ready_items(&mut debounced));
+    }
+
+    #[test]
+    fn test_throttle() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let (sender, receiver) = unbounded();
+        let mut throttled = receiver.throttle(Duration::from_millis(100));
+        for item in 1..=3 {
+            sender.unbounded_send(item).unwrap();
+        }
+        let output =
+            drive(&clock, &sender, &mut throttled, &[(0, None), (60, None), (40, None), (150, None), (200, Some(4))]);
+        // 积压的元素每 100ms 放出一个；空闲超过间隔后的新元素立即输出
+        assert_eq!(vec![vec![Some(1)], vec![], vec![Some(2)], vec![Some(3)], vec![Some(4)]], output);
+        drop(sender);
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(vec![None], ready_items(&mut throttled));
+    }
+
+    #[test]
+    fn test_chunks_timeout() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let (sender, receiver) = unbounded();
+        let mut chunks = receiver.chunks_timeout(3, Duration::from_millis(100));
+        let output = drive(
+            &clock,
+            &sender,
+            &mut chunks,
+            &[(0, Some(1)), (10, Some(2)), (10, Some(3)), (500, Some(4)), (60, Some(5)), (40, None), (10, Some(6))],
+        );
+        // 攒满 3 个立即输出；第二批从 4 到达开始计时，100ms 后只有两个元素也输出
+        assert_eq!(
+            vec![vec![], vec![], vec![Some(vec![1, 2, 3])], vec![], vec![], vec![Some(vec![4, 5])], vec![]],
+            output
+        );
+        drop(sender);
+        assert_eq!(vec![Some(vec![6]), None], ready_items(&mut chunks));
+    }
+
+    #[test]
+    fn test_sample() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        let (sender, receiver) = unbounded();
+        let mut sampled = receiver.sample(Duration::from_millis(100));
+        let output = drive(
+            &clock,
+            &sender,
+            &mut sampled,
+            &[(10, Some(1)), (10, Some(2)), (80, None), (100, None), (50, Some(3)), (300, None), (0, Some(4))],
+        );
+        // 100ms 采到 2；200ms 没有新元素；3 在 250ms 到达，错过的 300、400、500 三次采样只输出一次
+        assert_eq!(vec![vec![], vec![], vec![Some(2)], vec![], vec![], vec![Some(3)], vec![]], output);
+        drop(sender);
+        assert_eq!(vec![Some(4), None], ready_items(&mut sampled));
+    }
+
+    #[test]
+    fn test_always_ready_upstream() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+        // 上游永远就绪，每次 poll 只取有限个元素后返回
+        let mut debounced = futures::stream::iter(0..).debounce(Duration::from_millis(10));
+        let mut sampled = futures::stream::iter(0..).sample(Duration::from_millis(10));
+        assert_eq!(None, debounced.next().now_or_never());
+        assert_eq!(None, sampled.next().now_or_never());
+        clock.advance(Duration::from_millis(10));
+        // 不断到来的新元素让 debounce 一直重新计时；sample 按周期采到最新的元素
+        assert_eq!(None, debounced.next().now_or_never());
+        assert_eq!(Some(Some(63)), sampled.next().now_or_never());
+    }
+}
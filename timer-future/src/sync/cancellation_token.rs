@@ -0,0 +1,178 @@
+//! 取消令牌
+//!
+//! [`CancellationToken`] 可以克隆并在任务之间传递，调用 `cancel` 后所有克隆都会看到取消状态，
+//! 等待 `cancelled()` 的任务会被唤醒。令牌可以派生子令牌：父令牌取消时子令牌一起取消，反之则不会。
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+
+/// 取消令牌，所有克隆共享同一个取消状态
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Node>,
+}
+
+#[derive(Default)]
+struct Node {
+    state: Mutex<NodeState>,
+}
+
+#[derive(Default)]
+struct NodeState {
+    cancelled: bool,
+    /// 正在等待取消的任务
+    waiters: HashMap<u64, Waker>,
+    next_id: u64,
+    /// 派生出的子令牌，不持有它们，子令牌全部被 drop 后自然失效
+    children: Vec<Weak<Node>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 派生一个子令牌，父令牌被取消时它也会被取消；父令牌已经被取消时，子令牌一创建就是取消状态
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut state = self.inner.state.lock().unwrap();
+        if state.cancelled {
+            child.inner.state.lock().unwrap().cancelled = true;
+        } else {
+            state.children.retain(|child| child.strong_count() > 0);
+            state.children.push(Arc::downgrade(&child.inner));
+        }
+        child
+    }
+
+    /// 取消令牌和它的所有子令牌，并唤醒所有等待者；重复调用没有效果
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.state.lock().unwrap().cancelled
+    }
+
+    /// 等待令牌被取消
+    pub fn cancelled(&self) -> WaitForCancellation<'_> {
+        WaitForCancellation { token: self, id: None }
+    }
+}
+
+impl Node {
+    fn cancel(&self) {
+        let (waiters, children) = {
+            let mut state = self.state.lock().unwrap();
+            if state.cancelled {
+                return;
+            }
+            state.cancelled = true;
+            (std::mem::take(&mut state.waiters), std::mem::take(&mut state.children))
+        };
+        // 在锁外唤醒并向下传播
+        waiters.into_values().for_each(Waker::wake);
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+impl Debug for CancellationToken {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken").field("cancelled", &self.is_cancelled()).finish()
+    }
+}
+
+/// `CancellationToken::cancelled` 返回的 Future
+pub struct WaitForCancellation<'a> {
+    token: &'a CancellationToken,
+    /// 登记的 waker 的 id，drop 时移除
+    id: Option<u64>,
+}
+
+impl Future for WaitForCancellation<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.token.inner.state.lock().unwrap();
+        if state.cancelled {
+            return Poll::Ready(());
+        }
+        let id = match self.id {
+            Some(id) => id,
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                id
+            }
+        };
+        state.waiters.insert(id, cx.waker().clone());
+        drop(state);
+        self.id = Some(id);
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitForCancellation<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.token.inner.state.lock().unwrap().waiters.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::pin::pin;
+    use std::task::Context;
+
+    use futures::task::noop_waker_ref;
+    use futures::Future;
+
+    use crate::sync::CancellationToken;
+    use crate::{block_on, new_executor_and_spawner};
+
+    #[test]
+    fn test_cancel_wakes_waiters() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let token = CancellationToken::new();
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let token = token.clone();
+                spawner.spawn(async move { token.cancelled().await })
+            })
+            .collect();
+        let canceller = token.clone();
+        spawner.spawn(async move { canceller.cancel() });
+        executor.run();
+        assert!(token.is_cancelled());
+        for waiter in waiters {
+            block_on(waiter).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cancellation_propagates_to_children_only() {
+        let parent = CancellationToken::new();
+        let child = parent.child_token();
+        let grandchild = child.child_token();
+
+        // 子令牌取消不影响父令牌
+        let sibling = parent.child_token();
+        sibling.cancel();
+        assert!(!parent.is_cancelled() && !child.is_cancelled());
+
+        let cx = &mut Context::from_waker(noop_waker_ref());
+        let mut waiting = pin!(grandchild.cancelled());
+        assert!(waiting.as_mut().poll(cx).is_pending());
+        parent.cancel();
+        assert!(child.is_cancelled());
+        assert!(waiting.as_mut().poll(cx).is_ready());
+        assert!(parent.child_token().is_cancelled());
+    }
+}
@@ -0,0 +1,19 @@
+//! 异步同步原语
+//!
+//! 这些原语都基于 waker 实现，等待时挂起任务而不是阻塞线程，不依赖特定的执行器：
+//!
+//! - [`Mutex`]：公平的异步互斥锁
+//! - [`Semaphore`]：按到达顺序分配许可的信号量
+//! - [`oneshot`]：只发送一个值的通道
+//! - [`mpsc`]：有界的多生产者单消费者通道
+//! - [`CancellationToken`]：可以逐级向下传播的取消令牌
+
+mod cancellation_token;
+pub mod mpsc;
+mod mutex;
+pub mod oneshot;
+mod semaphore;
+
+pub use cancellation_token::{CancellationToken, WaitForCancellation};
+pub use mutex::{Mutex, MutexGuard};
+pub use semaphore::{Acquire, AcquireError, Semaphore, SemaphorePermit, TryAcquireError};
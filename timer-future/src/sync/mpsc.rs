@@ -0,0 +1,324 @@
+//! 有界多生产者单消费者通道
+//!
+//! 通道的容量由一个信号量控制：发送前先拿一个许可，接收端取出一个值后归还一个许可。
+//! 通道满了的时候 `send` 会挂起，直到接收端腾出空间，从而把背压传递给发送方。
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::future::poll_fn;
+use futures::Stream;
+
+use crate::sync::semaphore::{Semaphore, TryAcquireError};
+
+/// 创建一个最多缓存 capacity 个值的通道
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "mpsc channel capacity must be non-zero");
+    let chan = Arc::new(Chan {
+        semaphore: Semaphore::new(capacity),
+        state: Mutex::new(State { queue: VecDeque::new(), waker: None, senders: 1 }),
+    });
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+struct Chan<T> {
+    /// 剩余容量
+    semaphore: Semaphore,
+    state: Mutex<State<T>>,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    /// 等待接收的任务
+    waker: Option<Waker>,
+    /// 存活的发送端数量
+    senders: usize,
+}
+
+impl<T> Chan<T> {
+    /// 已经拿到许可，放入值并唤醒接收端
+    fn push(&self, value: T) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.queue.push_back(value);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// 发送端，可以克隆
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// 发送一个值，通道满了就等待；接收端已经关闭时把值放在错误里返回
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match self.chan.semaphore.acquire().await {
+            Ok(permit) => {
+                // 许可由接收端取出值时归还
+                permit.forget();
+                self.chan.push(value);
+                Ok(())
+            }
+            Err(_) => Err(SendError(value)),
+        }
+    }
+
+    /// 不等待，通道满了或者接收端已经关闭时返回错误
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match self.chan.semaphore.try_acquire() {
+            Ok(permit) => {
+                permit.forget();
+                self.chan.push(value);
+                Ok(())
+            }
+            Err(TryAcquireError::NoPermits) => Err(TrySendError::Full(value)),
+            Err(TryAcquireError::Closed) => Err(TrySendError::Closed(value)),
+        }
+    }
+
+    /// 接收端是否已经关闭
+    pub fn is_closed(&self) -> bool {
+        self.chan.semaphore.is_closed()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.state.lock().unwrap().senders += 1;
+        Sender { chan: self.chan.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.chan.state.lock().unwrap();
+            state.senders -= 1;
+            // 最后一个发送端离开，唤醒接收端让它看到通道结束
+            if state.senders == 0 {
+                state.waker.take()
+            } else {
+                None
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Debug for Sender<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender").field("closed", &self.is_closed()).finish()
+    }
+}
+
+/// 接收端
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// 等待下一个值，所有发送端都被 drop 或者通道被关闭，并且缓存的值都取完之后返回 None
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.chan.state.lock().unwrap();
+        if let Some(value) = state.queue.pop_front() {
+            drop(state);
+            self.chan.semaphore.add_permits(1);
+            return Poll::Ready(Some(value));
+        }
+        if state.senders == 0 || self.chan.semaphore.is_closed() {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// 关闭通道：之后的发送都会失败，已经缓存的值仍然可以取出
+    pub fn close(&mut self) {
+        self.chan.semaphore.close();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+        // 丢弃缓存的值，它们可能持有发送端，留在通道里会形成引用环
+        let queue = std::mem::take(&mut self.chan.state.lock().unwrap().queue);
+        drop(queue);
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+impl<T> Debug for Receiver<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// 接收端已经关闭，包含没有发出去的值
+pub struct SendError<T>(pub T);
+
+impl<T> Debug for SendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> Display for SendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl<T> Error for SendError<T> {}
+
+/// `try_send` 失败的原因，包含没有发出去的值
+pub enum TrySendError<T> {
+    /// 通道已满
+    Full(T),
+    /// 接收端已经关闭
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(value) | TrySendError::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full(..)"),
+            TrySendError::Closed(_) => write!(f, "Closed(..)"),
+        }
+    }
+}
+
+impl<T> Display for TrySendError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "channel full"),
+            TrySendError::Closed(_) => write!(f, "channel closed"),
+        }
+    }
+}
+
+impl<T> Error for TrySendError<T> {}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use futures::StreamExt;
+
+    use crate::sync::mpsc::{channel, TrySendError};
+    use crate::{block_on, new_executor_and_spawner};
+
+    #[test]
+    fn test_bounded_backpressure() {
+        const CAPACITY: usize = 2;
+        let (executor, spawner) = new_executor_and_spawner();
+        let (tx, mut rx) = channel(CAPACITY);
+        let sent = Arc::new(AtomicUsize::new(0));
+        let received = Arc::new(AtomicUsize::new(0));
+
+        for producer in 0..3 {
+            let tx = tx.clone();
+            let sent = sent.clone();
+            spawner.spawn(async move {
+                for i in 0..10 {
+                    tx.send(producer * 100 + i).await.unwrap();
+                    sent.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        }
+        drop(tx);
+
+        let consumer_received = received.clone();
+        let consumer_sent = sent.clone();
+        let values = spawner.spawn(async move {
+            let mut values = Vec::new();
+            while let Some(value) = rx.recv().await {
+                // 发送方最多领先接收方通道容量个值
+                assert!(consumer_sent.load(Ordering::SeqCst) <= consumer_received.load(Ordering::SeqCst) + CAPACITY);
+                consumer_received.fetch_add(1, Ordering::SeqCst);
+                values.push(value);
+            }
+            values
+        });
+        executor.run();
+
+        let values = block_on(values).unwrap();
+        assert_eq!(30, values.len());
+        // 同一个生产者的值保持发送顺序
+        for producer in 0..3 {
+            let own: Vec<_> = values.iter().filter(|v| **v / 100 == producer).collect();
+            assert!(own.windows(2).all(|w| w[0] < w[1]));
+        }
+    }
+
+    #[test]
+    fn test_try_send_and_close() {
+        let (tx, mut rx) = channel(1);
+        tx.try_send(1).unwrap();
+        assert!(matches!(tx.try_send(2), Err(TrySendError::Full(2))));
+        assert_eq!(Some(1), block_on(rx.recv()));
+
+        rx.close();
+        assert!(tx.is_closed());
+        assert!(matches!(tx.try_send(3), Err(TrySendError::Closed(3))));
+        assert_eq!(4, block_on(tx.send(4)).unwrap_err().0);
+    }
+
+    #[test]
+    fn test_recv_ends_after_close_with_live_sender() {
+        let (tx, mut rx) = channel(2);
+        tx.try_send(1).unwrap();
+        rx.close();
+        // 发送端还活着，缓存的值取完之后也不会再有新值
+        assert_eq!(Some(1), block_on(rx.recv()));
+        assert_eq!(None, block_on(rx.recv()));
+        drop(tx);
+    }
+
+    #[test]
+    fn test_stream_ends_when_senders_dropped() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let (tx, rx) = channel(4);
+        let collected = spawner.spawn(rx.collect::<Vec<u32>>());
+        for i in 0..3 {
+            let tx = tx.clone();
+            spawner.spawn(async move { tx.send(i).await.unwrap() });
+        }
+        drop(tx);
+        executor.run();
+        let mut collected = block_on(collected).unwrap();
+        collected.sort();
+        assert_eq!(vec![0, 1, 2], collected);
+    }
+}
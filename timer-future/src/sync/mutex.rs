@@ -0,0 +1,166 @@
+//! 异步互斥锁
+//!
+//! 等待锁的任务会挂起而不是阻塞线程；锁被释放时按等待顺序交给下一个任务（公平），
+//! 不会出现刚释放锁的任务马上又抢到锁、其他任务一直拿不到的情况。
+
+use std::cell::UnsafeCell;
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+
+use crate::sync::semaphore::{Semaphore, SemaphorePermit};
+
+/// 异步互斥锁，内部用只有一个许可的公平信号量实现
+pub struct Mutex<T: ?Sized> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: 同一时间只有持有许可的一方（MutexGuard）可以访问 value
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(value: T) -> Self {
+        Mutex { semaphore: Semaphore::new(1), value: UnsafeCell::new(value) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// 等待并获取锁
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        let permit = self.semaphore.acquire().await.expect("互斥锁的信号量不会被关闭");
+        MutexGuard { mutex: self, _permit: permit }
+    }
+
+    /// 不等待，锁被占用或者有任务在排队时返回 None
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire().ok()?;
+        Some(MutexGuard { mutex: self, _permit: permit })
+    }
+
+    /// 通过可变引用直接访问，不需要加锁
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for Mutex<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("value", &&*guard).finish(),
+            None => f.debug_struct("Mutex").field("value", &format_args!("<locked>")).finish(),
+        }
+    }
+}
+
+/// 持有锁期间可以访问数据，drop 时释放锁
+#[must_use]
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    /// drop 时归还许可，也就是释放锁
+    _permit: SemaphorePermit<'a>,
+}
+
+// SAFETY: MutexGuard 只提供对 T 的引用，可以跨线程共享的条件和 &T 一样
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: 持有许可期间没有其他人能访问 value
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: 同上，并且 &mut self 保证了这里是唯一的访问
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::sync::{oneshot, Mutex};
+    use crate::{block_on, new_executor_and_spawner, TimerFuture};
+
+    #[test]
+    fn test_lock_held_across_await() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let counter = Arc::new(Mutex::new(0));
+        for _ in 0..10 {
+            let counter = counter.clone();
+            spawner.spawn(async move {
+                let mut value = counter.lock().await;
+                let read = *value;
+                // 持有锁期间让出执行权，其他任务只能等待
+                TimerFuture::new(Duration::from_millis(1)).await;
+                *value = read + 1;
+            });
+        }
+        executor.run();
+        assert_eq!(10, *block_on(counter.lock()));
+    }
+
+    #[test]
+    fn test_lock_is_fair() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (release, released) = oneshot::channel();
+
+        let holder = order.clone();
+        spawner.spawn(async move {
+            let guard = holder.lock().await;
+            released.await.unwrap();
+            drop(guard);
+            // 刚释放锁的任务重新加锁时要排在等待者后面
+            holder.lock().await.push(0);
+        });
+        for id in 1..=3 {
+            let order = order.clone();
+            spawner.spawn(async move {
+                order.lock().await.push(id);
+            });
+        }
+        spawner.spawn(async move {
+            release.send(()).unwrap();
+        });
+        executor.run();
+
+        let order = Arc::try_unwrap(order).unwrap().into_inner();
+        assert_eq!(vec![1, 2, 3, 0], order);
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let mut mutex = Mutex::new(String::from("a"));
+        {
+            let mut guard = mutex.try_lock().unwrap();
+            guard.push('b');
+            assert!(mutex.try_lock().is_none());
+            assert_eq!("Mutex { value: <locked> }", format!("{mutex:?}"));
+        }
+        mutex.get_mut().push('c');
+        assert_eq!("abc", *mutex.try_lock().unwrap());
+    }
+}
@@ -0,0 +1,203 @@
+//! 一次性通道
+//!
+//! 只能发送一个值。[`Receiver`] 本身是一个 Future，在值到达或者 [`Sender`] 被 drop 时完成。
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// 创建一个一次性通道
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Mutex::new(Inner {
+        value: None,
+        waker: None,
+        sender_dropped: false,
+        receiver_dropped: false,
+    }));
+    (Sender { inner: inner.clone() }, Receiver { inner })
+}
+
+struct Inner<T> {
+    value: Option<T>,
+    /// 等待接收的任务
+    waker: Option<Waker>,
+    sender_dropped: bool,
+    receiver_dropped: bool,
+}
+
+/// 发送端，`send` 会消耗掉自己
+pub struct Sender<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// 发送一个值，接收端已经被 drop 时把值原样返回
+    pub fn send(self, value: T) -> Result<(), T> {
+        let waker = {
+            let mut inner = self.inner.lock().unwrap();
+            if inner.receiver_dropped {
+                return Err(value);
+            }
+            inner.value = Some(value);
+            inner.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// 接收端是否已经被 drop
+    pub fn is_closed(&self) -> bool {
+        self.inner.lock().unwrap().receiver_dropped
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.sender_dropped = true;
+            inner.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Debug for Sender<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender").field("closed", &self.is_closed()).finish()
+    }
+}
+
+/// 接收端，作为 Future 等待发送的值
+pub struct Receiver<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+}
+
+impl<T> Receiver<T> {
+    /// 不等待，立即尝试取出值
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.value.take() {
+            Some(value) => Ok(value),
+            None if inner.sender_dropped => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(value) = inner.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if inner.sender_dropped {
+            return Poll::Ready(Err(RecvError(())));
+        }
+        inner.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.lock().unwrap().receiver_dropped = true;
+    }
+}
+
+impl<T> Debug for Receiver<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// 发送端没有发送值就被 drop 了
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecvError(());
+
+impl Display for RecvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sender dropped without sending")
+    }
+}
+
+impl Error for RecvError {}
+
+/// `try_recv` 失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// 值还没有发送
+    Empty,
+    /// 发送端没有发送值就被 drop 了
+    Closed,
+}
+
+impl Display for TryRecvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel is empty"),
+            TryRecvError::Closed => write!(f, "sender dropped without sending"),
+        }
+    }
+}
+
+impl Error for TryRecvError {}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::sync::oneshot::{channel, TryRecvError};
+    use crate::{block_on, new_executor_and_spawner, TimerFuture};
+
+    #[test]
+    fn test_send_between_tasks() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let (tx, rx) = channel();
+        let received = spawner.spawn(async move { rx.await.unwrap() });
+        spawner.spawn(async move {
+            TimerFuture::new(Duration::from_millis(5)).await;
+            tx.send("done").unwrap();
+        });
+        executor.run();
+        assert_eq!("done", block_on(received).unwrap());
+    }
+
+    #[test]
+    fn test_sender_dropped() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let (tx, rx) = channel::<u32>();
+        let received = spawner.spawn(rx);
+        spawner.spawn(async move {
+            TimerFuture::new(Duration::from_millis(1)).await;
+            drop(tx);
+        });
+        executor.run();
+        let err = block_on(received).unwrap().unwrap_err();
+        assert_eq!("sender dropped without sending", err.to_string());
+    }
+
+    #[test]
+    fn test_receiver_dropped_and_try_recv() {
+        let (tx, rx) = channel();
+        assert!(!tx.is_closed());
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(Err(5), tx.send(5));
+
+        let (tx, mut rx) = channel();
+        assert_eq!(Err(TryRecvError::Empty), rx.try_recv());
+        tx.send(1).unwrap();
+        assert_eq!(Ok(1), rx.try_recv());
+        assert_eq!(Err(TryRecvError::Closed), rx.try_recv());
+    }
+}
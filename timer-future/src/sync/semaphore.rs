@@ -0,0 +1,364 @@
+//! 异步信号量
+//!
+//! 等待者按到达顺序排队：队首的请求没有满足之前，后来的请求即使许可足够也不会插队，
+//! 这样申请大量许可的任务不会被源源不断的小请求饿死。
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+/// 异步信号量
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    closed: bool,
+    /// 等待者的 id，按到达顺序排列
+    queue: VecDeque<u64>,
+    waiters: HashMap<u64, Waiter>,
+    next_id: u64,
+}
+
+struct Waiter {
+    needed: usize,
+    /// 许可已经分配给这个等待者
+    granted: bool,
+    waker: Option<Waker>,
+}
+
+impl State {
+    /// 按顺序把许可分配给队首的等待者，返回需要唤醒的 waker
+    fn grant(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while let Some(&id) = self.queue.front() {
+            let waiter = self.waiters.get_mut(&id).expect("排队的等待者一定存在");
+            if waiter.needed > self.permits {
+                break;
+            }
+            self.permits -= waiter.needed;
+            waiter.granted = true;
+            wakers.extend(waiter.waker.take());
+            self.queue.pop_front();
+        }
+        wakers
+    }
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            state: Mutex::new(State {
+                permits,
+                closed: false,
+                queue: VecDeque::new(),
+                waiters: HashMap::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    /// 当前可用的许可数
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    /// 申请一个许可
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// 申请 n 个许可，要么全部拿到，要么继续等待
+    pub fn acquire_many(&self, n: usize) -> Acquire<'_> {
+        Acquire { semaphore: self, needed: n, id: None }
+    }
+
+    /// 不等待，立即申请一个许可
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        self.try_acquire_many(1)
+    }
+
+    pub fn try_acquire_many(&self, n: usize) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(TryAcquireError::Closed);
+        }
+        // 有人在排队时不插队
+        if !state.queue.is_empty() || state.permits < n {
+            return Err(TryAcquireError::NoPermits);
+        }
+        state.permits -= n;
+        Ok(SemaphorePermit { semaphore: self, permits: n })
+    }
+
+    /// 增加 n 个许可，并唤醒因此可以拿到许可的等待者
+    pub fn add_permits(&self, n: usize) {
+        let wakers = {
+            let mut state = self.state.lock().unwrap();
+            state.permits += n;
+            state.grant()
+        };
+        // 在锁外唤醒，避免被唤醒的任务立即抢锁
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// 关闭信号量：正在等待和之后的申请都会失败，已经拿到的许可不受影响
+    pub fn close(&self) {
+        let wakers: Vec<Waker> = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            state.waiters.values_mut().filter_map(|waiter| waiter.waker.take()).collect()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+}
+
+impl Debug for Semaphore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("Semaphore")
+            .field("permits", &state.permits)
+            .field("waiters", &state.queue.len())
+            .field("closed", &state.closed)
+            .finish()
+    }
+}
+
+/// `acquire` 和 `acquire_many` 返回的 Future
+///
+/// 在拿到许可之前被 drop 会离开队列；已经分配了许可但还没被 poll 到时被 drop，许可会归还给信号量。
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    needed: usize,
+    /// 排队之后在等待者表中的 id
+    id: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Result<SemaphorePermit<'a>, AcquireError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let needed = self.needed;
+        let mut state = semaphore.state.lock().unwrap();
+
+        let Some(id) = self.id else {
+            if state.closed {
+                return Poll::Ready(Err(AcquireError(())));
+            }
+            if state.queue.is_empty() && state.permits >= needed {
+                state.permits -= needed;
+                return Poll::Ready(Ok(SemaphorePermit { semaphore, permits: needed }));
+            }
+            let id = state.next_id;
+            state.next_id += 1;
+            state.queue.push_back(id);
+            state.waiters.insert(id, Waiter { needed, granted: false, waker: Some(cx.waker().clone()) });
+            self.id = Some(id);
+            return Poll::Pending;
+        };
+
+        if state.waiters[&id].granted {
+            state.waiters.remove(&id);
+            self.id = None;
+            return Poll::Ready(Ok(SemaphorePermit { semaphore, permits: needed }));
+        }
+        if state.closed {
+            state.waiters.remove(&id);
+            state.queue.retain(|queued| *queued != id);
+            self.id = None;
+            return Poll::Ready(Err(AcquireError(())));
+        }
+        state.waiters.get_mut(&id).expect("排队的等待者一定存在").waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id.take() else {
+            return;
+        };
+        let wakers = {
+            let mut state = self.semaphore.state.lock().unwrap();
+            let waiter = state.waiters.remove(&id).expect("排队的等待者一定存在");
+            if waiter.granted {
+                state.permits += waiter.needed;
+            } else {
+                state.queue.retain(|queued| *queued != id);
+            }
+            // 归还的许可，或者离开队首后排在后面的等待者，都可能可以拿到许可了
+            state.grant()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// 从信号量拿到的许可，drop 时归还
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// 不归还许可，相当于永久减少了信号量的许可数
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+
+    pub fn num_permits(&self) -> usize {
+        self.permits
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
+
+impl Debug for SemaphorePermit<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SemaphorePermit").field("permits", &self.permits).finish()
+    }
+}
+
+/// 信号量已经关闭
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AcquireError(());
+
+impl Display for AcquireError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "semaphore closed")
+    }
+}
+
+impl Error for AcquireError {}
+
+/// `try_acquire` 失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryAcquireError {
+    /// 信号量已经关闭
+    Closed,
+    /// 许可不够，或者已经有其他任务在排队
+    NoPermits,
+}
+
+impl Display for TryAcquireError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TryAcquireError::Closed => write!(f, "semaphore closed"),
+            TryAcquireError::NoPermits => write!(f, "no permits available"),
+        }
+    }
+}
+
+impl Error for TryAcquireError {}
+
+#[cfg(test)]
+mod test {
+    use std::pin::pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use futures::task::noop_waker_ref;
+    use futures::Future;
+
+    use crate::sync::{Semaphore, TryAcquireError};
+    use crate::{new_executor_and_spawner, TimerFuture};
+
+    #[test]
+    fn test_limits_concurrency() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let semaphore = Arc::new(Semaphore::new(3));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        for _ in 0..20 {
+            let semaphore = semaphore.clone();
+            let running = running.clone();
+            let max_running = max_running.clone();
+            spawner.spawn(async move {
+                let _permit = semaphore.acquire().await.unwrap();
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                TimerFuture::new(Duration::from_millis(1)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        executor.run();
+        assert_eq!(3, max_running.load(Ordering::SeqCst));
+        assert_eq!(3, semaphore.available_permits());
+    }
+
+    #[test]
+    fn test_waiters_do_not_jump_the_queue() {
+        let cx = &mut Context::from_waker(noop_waker_ref());
+        let semaphore = Semaphore::new(1);
+        let held = semaphore.try_acquire().unwrap();
+        let mut big = pin!(semaphore.acquire_many(2));
+        assert!(big.as_mut().poll(cx).is_pending());
+
+        // 许可够一个小请求，但是大请求在排队，小请求不能插队
+        drop(held);
+        assert_eq!(TryAcquireError::NoPermits, semaphore.try_acquire().unwrap_err());
+        let mut small = pin!(semaphore.acquire());
+        assert!(small.as_mut().poll(cx).is_pending());
+
+        semaphore.add_permits(1);
+        let Poll::Ready(Ok(permit)) = big.as_mut().poll(cx) else {
+            panic!("big request should be granted");
+        };
+        assert_eq!(2, permit.num_permits());
+        assert!(small.as_mut().poll(cx).is_pending());
+        drop(permit);
+        assert!(small.as_mut().poll(cx).is_ready());
+    }
+
+    #[test]
+    fn test_dropped_waiter_leaves_queue() {
+        let cx = &mut Context::from_waker(noop_waker_ref());
+        let semaphore = Semaphore::new(0);
+        let mut first = Box::pin(semaphore.acquire_many(2));
+        assert!(first.as_mut().poll(cx).is_pending());
+        let mut second = pin!(semaphore.acquire());
+        assert!(second.as_mut().poll(cx).is_pending());
+
+        semaphore.add_permits(1);
+        assert!(second.as_mut().poll(cx).is_pending());
+        // 队首的等待者离开后，许可交给下一个
+        drop(first);
+        assert!(second.as_mut().poll(cx).is_ready());
+        assert_eq!(1, semaphore.available_permits());
+    }
+
+    #[test]
+    fn test_close_fails_waiters() {
+        let cx = &mut Context::from_waker(noop_waker_ref());
+        let semaphore = Semaphore::new(0);
+        let mut waiting = pin!(semaphore.acquire());
+        assert!(waiting.as_mut().poll(cx).is_pending());
+        semaphore.close();
+        assert!(matches!(waiting.as_mut().poll(cx), Poll::Ready(Err(_))));
+        assert_eq!(TryAcquireError::Closed, semaphore.try_acquire().unwrap_err());
+        semaphore.add_permits(1);
+        assert!(crate::block_on(semaphore.acquire()).is_err());
+    }
+}
@@ -0,0 +1,723 @@
+//! 任务与 JoinHandle
+//!
+//! 执行器里的每个 [`Task`] 包装了一个 `()` 输出的 Future。用户 Future 的输出通过一对共享的槽位传给
+//! [`JoinHandle`]：任务完成时写入结果并唤醒等待者；如果任务还没完成就被丢弃（例如执行器被 drop），
+//! 则写入 [`JoinError`]，等待者不会永远挂起。
+//!
+//! 任务 poll 时发生的 panic 会被捕获：payload 交给 [`JoinHandle`]（[`JoinError::into_panic`]），
+//! 任务被标记为 panic 结束，执行线程和其他任务不受影响。
+//!
+//! 每个任务还带有一个进程内唯一的 [`TaskId`]、可选的名字和一些运行统计，执行器可以据此列出
+//! 所有存活任务的 [`TaskInfo`]，用来排查卡住的任务。
+
+use std::any::Any;
+use std::cell::{Cell, UnsafeCell};
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use futures::future::{BoxFuture, LocalBoxFuture};
+use futures::task::{waker_ref, ArcWake};
+use futures::FutureExt;
+
+thread_local! {
+    /// 当前线程正在 poll 的任务
+    static CURRENT_TASK: Cell<Option<TaskId>> = const { Cell::new(None) };
+}
+
+/// 任务的调度方式：把被唤醒的任务放进执行器的运行队列
+pub(crate) trait Schedule: Send + Sync {
+    fn schedule(&self, task: Arc<Task>);
+}
+
+/// 没有在队列中，也没有被 poll，等待唤醒
+const IDLE: u8 = 0;
+/// 已经放进运行队列，等待执行器 poll
+const SCHEDULED: u8 = 1;
+/// 正在被某个线程 poll
+const RUNNING: u8 = 2;
+/// poll 期间被唤醒，poll 结束后需要重新调度一次
+const NOTIFIED: u8 = 3;
+/// Future 已经完成或者被丢弃
+const COMPLETE: u8 = 4;
+/// poll 时发生了 panic，Future 已经被丢弃
+const PANICKED: u8 = 5;
+
+/// 一个 Future 任务，被唤醒时通过调度器把自己放进运行队列，然后等待执行器 poll
+///
+/// 任务的状态保存在一个原子变量里，状态转换保证了：
+///
+/// - 同一时间只有一个线程能 poll 任务（只有把 SCHEDULED 改成 RUNNING 的线程可以访问 `future`），
+///   所以 `future` 可以放在 `UnsafeCell` 里而不需要 `Mutex`
+/// - 任务在队列中最多只有一份：已经是 SCHEDULED 或 NOTIFIED 时，重复的唤醒什么都不做
+/// - poll 期间的唤醒不会丢失：状态改为 NOTIFIED，poll 结束后恰好重新调度一次
+pub(crate) struct Task {
+    state: AtomicU8,
+    /// 进行中的 Future，完成后被置为 None
+    future: UnsafeCell<Option<BoxFuture<'static, ()>>>,
+    /// 把任务放回运行队列的调度器
+    scheduler: Arc<dyn Schedule>,
+    id: TaskId,
+    name: Option<String>,
+    spawned_at: Instant,
+    /// 被 poll 的次数
+    polls: AtomicU64,
+    /// poll 累计花费的时间，单位纳秒
+    poll_nanos: AtomicU64,
+    /// 最近一次被唤醒的时间，记录为距离 spawned_at 的纳秒数加一，0 表示从未被唤醒
+    last_wake: AtomicU64,
+}
+
+// SAFETY: future 只会被持有 RUNNING 状态的线程访问（或者在 shutdown 中由独占队列的一方访问），
+// 而 BoxFuture 本身是 Send 的，所以可以在线程间共享 Task
+unsafe impl Sync for Task {}
+
+impl Task {
+    /// 创建任务并立即调度它
+    pub(crate) fn spawn(
+        future: BoxFuture<'static, ()>,
+        scheduler: Arc<dyn Schedule>,
+        id: TaskId,
+        name: Option<String>,
+    ) -> Arc<Task> {
+        let task = Arc::new(Task {
+            state: AtomicU8::new(SCHEDULED),
+            future: UnsafeCell::new(Some(future)),
+            scheduler,
+            id,
+            name,
+            spawned_at: Instant::now(),
+            polls: AtomicU64::new(0),
+            poll_nanos: AtomicU64::new(0),
+            last_wake: AtomicU64::new(0),
+        });
+        task.scheduler.schedule(task.clone());
+        task
+    }
+
+    pub(crate) fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// 任务当前的状态和运行统计
+    pub(crate) fn info(&self) -> TaskInfo {
+        let state = match self.state.load(Ordering::Acquire) {
+            IDLE => TaskState::Idle,
+            SCHEDULED => TaskState::Scheduled,
+            RUNNING => TaskState::Running,
+            NOTIFIED => TaskState::Notified,
+            PANICKED => TaskState::Panicked,
+            _ => TaskState::Complete,
+        };
+        let last_wake = self.last_wake.load(Ordering::Relaxed);
+        TaskInfo {
+            id: self.id,
+            name: self.name.clone(),
+            state,
+            polls: self.polls.load(Ordering::Relaxed),
+            total_poll_time: Duration::from_nanos(self.poll_nanos.load(Ordering::Relaxed)),
+            spawned_at: self.spawned_at,
+            last_woken_at: (last_wake > 0).then(|| self.spawned_at + Duration::from_nanos(last_wake - 1)),
+        }
+    }
+
+    /// 唤醒任务：空闲的任务被放进运行队列，正在 poll 的任务被标记为 NOTIFIED，其他状态下什么都不做
+    fn schedule(self: &Arc<Self>) {
+        let since_spawn = self.spawned_at.elapsed().as_nanos() as u64;
+        self.last_wake.store(since_spawn + 1, Ordering::Relaxed);
+
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let next = match state {
+                IDLE => SCHEDULED,
+                RUNNING => NOTIFIED,
+                _ => return,
+            };
+            match self.state.compare_exchange_weak(state, next, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(actual) => state = actual,
+            }
+        }
+        if state == IDLE {
+            self.scheduler.schedule(self.clone());
+        }
+    }
+
+    /// poll 一次从运行队列中取出的任务
+    ///
+    /// 任务在这次 poll 中结束时返回 Ready：正常完成为 `Ok(())`，panic 时为 `Err(panic 信息)`。
+    pub(crate) fn run(self: &Arc<Self>) -> Poll<Result<(), String>> {
+        if self
+            .state
+            .compare_exchange(SCHEDULED, RUNNING, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            // 已经在 shutdown 中被取消
+            return Poll::Pending;
+        }
+
+        // SAFETY: 只有把状态从 SCHEDULED 改成 RUNNING 的线程可以访问 future
+        let slot = unsafe { &mut *self.future.get() };
+        let Some(future) = slot.as_mut() else {
+            return Poll::Pending;
+        };
+        // 基于任务自身创建 waker
+        let waker = waker_ref(self);
+        let ctx = &mut Context::from_waker(&waker);
+        let previous = CURRENT_TASK.with(|current| current.replace(Some(self.id)));
+        let started = Instant::now();
+        // BoxFuture<'a, T> 是 Pin<alloc::boxed::Box<dyn Future<Output = T> + Send + 'a>> 的类型别名
+        // 通过调用 as_mut 方法，可以将上面的类型转换成 Pin<&mut dyn Future + Send + 'static>
+        // panic 之后 future 会被直接丢弃，不会再被 poll，所以可以断言 unwind 安全
+        let poll = panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(ctx)));
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.poll_nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        CURRENT_TASK.with(|current| current.set(previous));
+
+        match poll {
+            Ok(Poll::Ready(())) => {
+                *slot = None;
+                self.state.store(COMPLETE, Ordering::Release);
+                return Poll::Ready(Ok(()));
+            }
+            Err(payload) => {
+                let message = panic_message(&*payload);
+                // 丢弃 future 本身也可能 panic，同样不能让它越过执行线程
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| *slot = None));
+                self.state.store(PANICKED, Ordering::Release);
+                return Poll::Ready(Err(message));
+            }
+            Ok(Poll::Pending) => {}
+        }
+
+        // Pending：没有被唤醒就回到 IDLE；poll 期间被唤醒过则重新调度一次
+        if self
+            .state
+            .compare_exchange(RUNNING, IDLE, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            self.state.store(SCHEDULED, Ordering::Release);
+            self.scheduler.schedule(self.clone());
+        }
+        Poll::Pending
+    }
+
+    /// 丢弃还在运行队列中的任务的 Future，用于执行器关闭时打破任务和调度器之间的引用环
+    pub(crate) fn shutdown(&self) {
+        if self
+            .state
+            .compare_exchange(SCHEDULED, COMPLETE, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+        {
+            // SAFETY: 队列中的任务没有被任何线程 poll，状态已经是 COMPLETE，之后也不会再被 poll
+            unsafe { (*self.future.get()).take() };
+        }
+    }
+}
+
+impl ArcWake for Task {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.schedule();
+    }
+}
+
+/// 任务的 id，在进程内唯一
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    pub(crate) fn next() -> TaskId {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        TaskId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Display for TaskId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// 当前正在 poll 的任务的 id，不在执行器的任务中调用时返回 None
+pub fn current_task_id() -> Option<TaskId> {
+    CURRENT_TASK.with(Cell::get)
+}
+
+/// 让出一次执行权：第一次 poll 时唤醒自己并返回 Pending，让执行器先运行其他就绪的任务
+pub async fn yield_now() {
+    let mut yielded = false;
+    std::future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+/// 在 f 运行期间把当前任务设为 id，f 不能 unwind
+pub(crate) fn enter<R>(id: TaskId, f: impl FnOnce() -> R) -> R {
+    let previous = CURRENT_TASK.with(|current| current.replace(Some(id)));
+    let result = f();
+    CURRENT_TASK.with(|current| current.set(previous));
+    result
+}
+
+/// 任务的调度状态
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskState {
+    /// 等待被唤醒
+    Idle,
+    /// 已经在运行队列中，等待被 poll
+    Scheduled,
+    /// 正在被 poll
+    Running,
+    /// 正在被 poll，并且期间又被唤醒了
+    Notified,
+    /// 已经完成或者被取消
+    Complete,
+    /// poll 时发生了 panic
+    Panicked,
+}
+
+impl Display for TaskState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            TaskState::Idle => "idle",
+            TaskState::Scheduled => "scheduled",
+            TaskState::Running => "running",
+            TaskState::Notified => "notified",
+            TaskState::Complete => "complete",
+            TaskState::Panicked => "panicked",
+        };
+        f.write_str(state)
+    }
+}
+
+/// 任务在某一时刻的状态和运行统计，由执行器的 `dump` 返回
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: Option<String>,
+    pub state: TaskState,
+    /// 被 poll 的次数
+    pub polls: u64,
+    /// poll 累计花费的时间
+    pub total_poll_time: Duration,
+    pub spawned_at: Instant,
+    /// 最近一次被唤醒的时间，从未被唤醒过时为 None
+    pub last_woken_at: Option<Instant>,
+}
+
+impl Display for TaskInfo {
+    /// 单行的可读格式，例如 `task 3 "worker" idle polls=2 poll_time=1.2ms last_wake=15ms ago`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "task {}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name:?}")?;
+        }
+        write!(f, " {} polls={} poll_time={:?}", self.state, self.polls, self.total_poll_time)?;
+        match self.last_woken_at {
+            Some(at) => write!(f, " last_wake={:?} ago", at.elapsed()),
+            None => write!(f, " last_wake=never"),
+        }
+    }
+}
+
+/// 把 future 包装成执行器可以运行的 `()` 输出的 Future，并返回获取其结果的 JoinHandle
+pub(crate) fn joinable<F>(future: F, id: TaskId) -> (BoxFuture<'static, ()>, JoinHandle<F::Output>)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (sender, handle) = join_channel(id);
+    (complete_on_exit(future, sender).boxed(), handle)
+}
+
+/// 同 [`joinable`]，用于不需要 Send 的本地任务
+pub(crate) fn joinable_local<F>(future: F, id: TaskId) -> (LocalBoxFuture<'static, ()>, JoinHandle<F::Output>)
+where
+    F: Future + 'static,
+{
+    let (sender, handle) = join_channel(id);
+    (complete_on_exit(future, sender).boxed_local(), handle)
+}
+
+/// 运行 future，把输出或者 panic 写入 JoinHandle
+async fn complete_on_exit<F: Future>(future: F, mut sender: JoinSender<F::Output>) {
+    match AssertUnwindSafe(future).catch_unwind().await {
+        Ok(output) => sender.complete(Ok(output)),
+        Err(payload) => {
+            // 原始 payload 交给 JoinHandle，再带着 panic 信息继续 unwind，让执行器知道任务 panic 了。
+            // resume_unwind 不会再次调用 panic hook，panic 信息只会被打印一次
+            let message = panic_message(&*payload);
+            sender.complete(Err(JoinError::panic(payload)));
+            panic::resume_unwind(Box::new(message));
+        }
+    }
+}
+
+/// 创建一对结果的发送端和 JoinHandle，用于不经过执行器运行的任务，例如 `spawn_blocking`
+pub(crate) fn join_channel<T>(id: TaskId) -> (JoinSender<T>, JoinHandle<T>) {
+    let slot = Arc::new(Mutex::new(JoinSlot { result: None, waker: None }));
+    (JoinSender { slot: Some(slot.clone()) }, JoinHandle { slot, id })
+}
+
+/// 从 panic payload 中取出 panic 信息，`panic!` 的参数只会是 `&str` 或者 `String`
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+/// 任务和 JoinHandle 之间共享的结果槽位
+struct JoinSlot<T> {
+    result: Option<Result<T, JoinError>>,
+    /// 正在等待结果的任务
+    waker: Option<Waker>,
+}
+
+/// 任务一侧持有的发送端，被 drop 时如果还没有写入结果，就写入“已取消”
+pub(crate) struct JoinSender<T> {
+    slot: Option<Arc<Mutex<JoinSlot<T>>>>,
+}
+
+impl<T> JoinSender<T> {
+    pub(crate) fn complete(&mut self, result: Result<T, JoinError>) {
+        if let Some(slot) = self.slot.take() {
+            let waker = {
+                let mut slot = slot.lock().unwrap();
+                slot.result = Some(result);
+                slot.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Drop for JoinSender<T> {
+    fn drop(&mut self) {
+        self.complete(Err(JoinError::cancelled()));
+    }
+}
+
+/// 等待任务结果的句柄，由 `Spawner::spawn` 返回
+///
+/// drop 掉 JoinHandle 不会影响任务的运行，只是不再能拿到它的结果。
+pub struct JoinHandle<T> {
+    slot: Arc<Mutex<JoinSlot<T>>>,
+    id: TaskId,
+}
+
+impl<T> JoinHandle<T> {
+    /// 对应任务的 id
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// 任务是否已经结束（完成、panic 或者被取消）
+    pub fn is_finished(&self) -> bool {
+        self.slot.lock().unwrap().result.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinHandle").field("id", &self.id).field("finished", &self.is_finished()).finish()
+    }
+}
+
+/// 任务没有正常完成的原因
+pub struct JoinError {
+    repr: Repr,
+}
+
+enum Repr {
+    /// 任务在完成之前被丢弃
+    Cancelled,
+    /// 任务 poll 时发生了 panic，保存 panic 的 payload
+    Panic(Box<dyn Any + Send + 'static>),
+}
+
+impl JoinError {
+    pub(crate) fn cancelled() -> Self {
+        JoinError { repr: Repr::Cancelled }
+    }
+
+    pub(crate) fn panic(payload: Box<dyn Any + Send + 'static>) -> Self {
+        JoinError { repr: Repr::Panic(payload) }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.repr, Repr::Cancelled)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self.repr, Repr::Panic(_))
+    }
+
+    /// 取出 panic 的 payload，任务不是因为 panic 结束时 panic
+    ///
+    /// 可以用 `std::panic::resume_unwind` 在等待结果的一侧继续这个 panic。
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        self.try_into_panic().expect("`JoinError` reason is not a panic")
+    }
+
+    /// 取出 panic 的 payload，任务不是因为 panic 结束时原样返回错误
+    pub fn try_into_panic(self) -> Result<Box<dyn Any + Send + 'static>, JoinError> {
+        match self.repr {
+            Repr::Panic(payload) => Ok(payload),
+            _ => Err(self),
+        }
+    }
+}
+
+impl Display for JoinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.repr {
+            Repr::Cancelled => write!(f, "task was cancelled"),
+            Repr::Panic(payload) => write!(f, "task panicked: {}", panic_message(&**payload)),
+        }
+    }
+}
+
+impl Debug for JoinError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.repr {
+            Repr::Cancelled => write!(f, "JoinError::Cancelled"),
+            Repr::Panic(payload) => write!(f, "JoinError::Panic({:?})", panic_message(&**payload)),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use futures::{Future, FutureExt};
+
+    use crate::task::{Schedule, Task, TaskId};
+
+    /// 测试用的运行队列，检查同一个任务不会同时在队列中出现两次
+    #[derive(Default)]
+    struct TestQueue {
+        queue: Mutex<VecDeque<Arc<Task>>>,
+        scheduled: AtomicUsize,
+    }
+
+    impl Schedule for TestQueue {
+        fn schedule(&self, task: Arc<Task>) {
+            let mut queue = self.queue.lock().unwrap();
+            assert!(
+                !queue.iter().any(|queued| Arc::ptr_eq(queued, &task)),
+                "task scheduled twice"
+            );
+            queue.push_back(task);
+            self.scheduled.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl TestQueue {
+        fn pop(&self) -> Option<Arc<Task>> {
+            self.queue.lock().unwrap().pop_front()
+        }
+    }
+
+    /// 等待 events 达到 target 的 Future，检查不会被并发 poll，并记录 poll 次数和最近的 waker
+    struct WaitEvents {
+        events: Arc<AtomicUsize>,
+        target: usize,
+        waker: Arc<Mutex<Option<Waker>>>,
+        in_poll: Arc<AtomicBool>,
+        polls: Arc<AtomicUsize>,
+    }
+
+    impl Future for WaitEvents {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            assert!(!self.in_poll.swap(true, Ordering::SeqCst), "task polled concurrently");
+            self.polls.fetch_add(1, Ordering::SeqCst);
+            // 先登记 waker 再检查条件，之后的事件一定能唤醒任务
+            *self.waker.lock().unwrap() = Some(cx.waker().clone());
+            // 让 poll 持续一小段时间，增加在 poll 期间被唤醒的机会
+            thread::yield_now();
+            let ready = self.events.load(Ordering::SeqCst) >= self.target;
+            self.in_poll.store(false, Ordering::SeqCst);
+            if ready {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn test_duplicate_wakes_schedule_once() {
+        let queue = Arc::new(TestQueue::default());
+        let waker = Arc::new(Mutex::new(None));
+        let events = Arc::new(AtomicUsize::new(0));
+        let polls = Arc::new(AtomicUsize::new(0));
+        let future = WaitEvents {
+            events: events.clone(),
+            target: 1,
+            waker: waker.clone(),
+            in_poll: Arc::new(AtomicBool::new(false)),
+            polls: polls.clone(),
+        };
+        Task::spawn(future.boxed(), queue.clone(), TaskId::next(), None);
+        assert!(queue.pop().unwrap().run().is_pending());
+
+        let waker = waker.lock().unwrap().clone().unwrap();
+        for _ in 0..10 {
+            waker.wake_by_ref();
+        }
+        assert_eq!(2, queue.scheduled.load(Ordering::SeqCst));
+
+        events.store(1, Ordering::SeqCst);
+        assert!(queue.pop().unwrap().run().is_ready());
+        assert!(queue.pop().is_none());
+        assert_eq!(2, polls.load(Ordering::SeqCst));
+
+        // 完成之后的唤醒什么都不做
+        waker.wake();
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn test_wake_during_poll_reschedules_once() {
+        struct WakeSelf {
+            polls: usize,
+        }
+        impl Future for WakeSelf {
+            type Output = ();
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+                self.polls += 1;
+                if self.polls == 3 {
+                    return Poll::Ready(());
+                }
+                cx.waker().wake_by_ref();
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+
+        let queue = Arc::new(TestQueue::default());
+        Task::spawn(WakeSelf { polls: 0 }.boxed(), queue.clone(), TaskId::next(), None);
+        let mut runs = 0;
+        while let Some(task) = queue.pop() {
+            runs += 1;
+            let _ = task.run();
+        }
+        assert_eq!(3, runs);
+    }
+
+    /// 多个线程不停地产生事件并唤醒任务，同时多个执行线程从队列中取任务 poll：
+    /// 任务不能被并发 poll，也不能在队列里出现两次，最后一次事件的唤醒不能丢失
+    #[test]
+    fn test_stress_no_lost_or_double_polls() {
+        const ROUNDS: usize = 200;
+        const WAKERS: usize = 4;
+        const EVENTS_PER_WAKER: usize = 200;
+
+        for _ in 0..ROUNDS {
+            let queue = Arc::new(TestQueue::default());
+            let events = Arc::new(AtomicUsize::new(0));
+            let waker = Arc::new(Mutex::new(None::<Waker>));
+            let done = Arc::new(AtomicBool::new(false));
+            let polls = Arc::new(AtomicUsize::new(0));
+            let future = WaitEvents {
+                events: events.clone(),
+                target: WAKERS * EVENTS_PER_WAKER,
+                waker: waker.clone(),
+                in_poll: Arc::new(AtomicBool::new(false)),
+                polls: polls.clone(),
+            };
+            let future = async move {
+                future.await;
+            };
+            Task::spawn(future.boxed(), queue.clone(), TaskId::next(), None);
+
+            let runners: Vec<_> = (0..2)
+                .map(|_| {
+                    let queue = queue.clone();
+                    let done = done.clone();
+                    thread::spawn(move || {
+                        let deadline = Instant::now() + Duration::from_secs(10);
+                        while !done.load(Ordering::SeqCst) {
+                            assert!(Instant::now() < deadline, "wakeup lost");
+                            match queue.pop() {
+                                Some(task) => {
+                                    if task.run().is_ready() {
+                                        done.store(true, Ordering::SeqCst);
+                                    }
+                                }
+                                None => thread::yield_now(),
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            let wakers: Vec<_> = (0..WAKERS)
+                .map(|_| {
+                    let events = events.clone();
+                    let waker = waker.clone();
+                    thread::spawn(move || {
+                        for _ in 0..EVENTS_PER_WAKER {
+                            events.fetch_add(1, Ordering::SeqCst);
+                            let current = waker.lock().unwrap().clone();
+                            if let Some(current) = current {
+                                current.wake();
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            for handle in wakers.into_iter().chain(runners) {
+                handle.join().unwrap();
+            }
+            // 每次调度恰好对应一次 poll
+            assert_eq!(queue.scheduled.load(Ordering::SeqCst), polls.load(Ordering::SeqCst));
+            assert!(queue.pop().is_none());
+        }
+    }
+}
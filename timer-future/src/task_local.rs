@@ -0,0 +1,183 @@
+//! 任务局部存储
+//!
+//! 用 [`task_local!`](crate::task_local!) 声明的变量只在 [`LocalKey::scope`] 包装的 Future 里可见。
+//! 每次 poll 这个 Future 时，把值换进线程局部变量，poll 结束后再换出来，
+//! 所以即使任务在不同线程上被 poll，或者同一线程交替 poll 多个任务，看到的都是自己的值。
+//!
+//! ```
+//! timer_future::task_local! {
+//!     static REQUEST_ID: u32;
+//! }
+//!
+//! let (executor, spawner) = timer_future::new_executor_and_spawner();
+//! let handle = spawner.spawn(REQUEST_ID.scope(7, async {
+//!     REQUEST_ID.with(|id| *id * 2)
+//! }));
+//! executor.run();
+//! assert_eq!(14, timer_future::block_on(handle).unwrap());
+//! ```
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+
+/// 声明任务局部变量，语法与 `thread_local!` 相同，但不需要初始值
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task_local::LocalKey<$t> = {
+            ::std::thread_local! {
+                static __KEY: ::std::cell::RefCell<::std::option::Option<$t>> =
+                    const { ::std::cell::RefCell::new(::std::option::Option::None) };
+            }
+            $crate::task_local::LocalKey { inner: __KEY }
+        };
+        $crate::task_local!($($rest)*);
+    };
+}
+
+/// 任务局部变量的 key，由 `task_local!` 创建
+pub struct LocalKey<T: 'static> {
+    #[doc(hidden)]
+    pub inner: thread::LocalKey<RefCell<Option<T>>>,
+}
+
+impl<T: 'static> LocalKey<T> {
+    /// 在 future 运行期间把变量设置为 value
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F> {
+        TaskLocalFuture { key: self, slot: Some(value), future }
+    }
+
+    /// 在同步函数 f 运行期间把变量设置为 value
+    pub fn sync_scope<R>(&'static self, value: T, f: impl FnOnce() -> R) -> R {
+        let mut slot = Some(value);
+        let _guard = self.enter(&mut slot);
+        f()
+    }
+
+    /// 访问当前的值，不在 scope 中时 panic
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        self.try_with(f).expect("task-local value not set in this scope")
+    }
+
+    /// 访问当前的值，不在 scope 中时返回错误
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        self.inner.with(|cell| {
+            let value = cell.borrow();
+            value.as_ref().map(f).ok_or(AccessError(()))
+        })
+    }
+
+    /// 把 slot 中的值换进线程局部变量，guard drop 时换回来（包括 poll 中 panic 的情况）
+    fn enter<'a>(&'static self, slot: &'a mut Option<T>) -> Guard<'a, T> {
+        self.inner.with(|cell| std::mem::swap(&mut *cell.borrow_mut(), slot));
+        Guard { key: self, slot }
+    }
+}
+
+impl<T: Clone + 'static> LocalKey<T> {
+    /// 返回当前值的克隆，不在 scope 中时 panic
+    pub fn get(&'static self) -> T {
+        self.with(T::clone)
+    }
+}
+
+impl<T: 'static> Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalKey").finish_non_exhaustive()
+    }
+}
+
+struct Guard<'a, T: 'static> {
+    key: &'static LocalKey<T>,
+    slot: &'a mut Option<T>,
+}
+
+impl<T: 'static> Drop for Guard<'_, T> {
+    fn drop(&mut self) {
+        self.key.inner.with(|cell| std::mem::swap(&mut *cell.borrow_mut(), self.slot));
+    }
+}
+
+/// `LocalKey::scope` 返回的 Future
+pub struct TaskLocalFuture<T: 'static, F> {
+    key: &'static LocalKey<T>,
+    /// 不在 poll 中时保存变量的值
+    slot: Option<T>,
+    future: F,
+}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: future 字段是结构性固定的，之后不会再移动它；slot 和 key 不需要固定
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let _guard = this.key.enter(&mut this.slot);
+        future.poll(cx)
+    }
+}
+
+/// 不在 scope 中访问任务局部变量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessError(());
+
+impl Display for AccessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "task-local value not set in this scope")
+    }
+}
+
+impl Error for AccessError {}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    use crate::{block_on, new_executor_and_spawner, TimerFuture};
+
+    crate::task_local! {
+        static NAME: &'static str;
+        static COUNTER: Cell<u32>;
+    }
+
+    #[test]
+    fn test_values_are_isolated_between_tasks() {
+        let (executor, spawner) = new_executor_and_spawner();
+        let handles: Vec<_> = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| {
+                spawner.spawn(NAME.scope(name, async {
+                    let before = NAME.get();
+                    // 等待期间其他任务会在同一线程上运行
+                    TimerFuture::new(Duration::from_millis(5)).await;
+                    (before, NAME.get())
+                }))
+            })
+            .collect();
+        executor.run();
+        for (handle, name) in handles.into_iter().zip(["a", "b", "c"]) {
+            assert_eq!((name, name), block_on(handle).unwrap());
+        }
+        assert!(NAME.try_with(|_| ()).is_err());
+    }
+
+    #[test]
+    fn test_nested_scopes_and_mutation() {
+        let result = block_on(COUNTER.scope(Cell::new(1), async {
+            COUNTER.with(|c| c.set(c.get() + 1));
+            let inner = COUNTER.scope(Cell::new(10), async { COUNTER.with(Cell::get) }).await;
+            (inner, COUNTER.with(Cell::get))
+        }));
+        assert_eq!((10, 2), result);
+        assert_eq!(5, COUNTER.sync_scope(Cell::new(5), || COUNTER.with(Cell::get)));
+    }
+}
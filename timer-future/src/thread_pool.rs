@@ -0,0 +1,346 @@
+//! 多线程工作窃取执行器
+//!
+//! [`ThreadPool`] 启动 N 个工作线程，每个线程有自己的本地运行队列，另外还有一个所有线程共享的全局注入队列：
+//!
+//! - 在工作线程上被唤醒或生成的任务放进该线程的本地队列，减少竞争并保持缓存局部性
+//! - 在其他线程上生成或唤醒的任务放进全局队列
+//! - 工作线程优先处理本地队列，其次从全局队列批量领取，最后从其他线程的本地队列窃取一半
+//! - 三处都没有任务时线程挂起，有新任务入队时再被唤醒
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle as ThreadHandle};
+
+use crate::task::{joinable, JoinHandle, Schedule, Task, TaskId};
+
+thread_local! {
+    /// 当前线程如果是某个线程池的工作线程，记录线程池和自己的编号
+    static WORKER: RefCell<Option<(Arc<Shared>, usize)>> = const { RefCell::new(None) };
+}
+
+/// 多线程工作窃取执行器，drop 时会停止并等待所有工作线程退出
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    workers: Vec<ThreadHandle<()>>,
+}
+
+/// 可以克隆并在任务内部使用的生成器
+#[derive(Clone)]
+pub struct PoolSpawner {
+    shared: Arc<Shared>,
+}
+
+/// 工作线程之间共享的状态
+struct Shared {
+    /// 全局注入队列
+    injector: Mutex<VecDeque<Arc<Task>>>,
+    /// 每个工作线程的本地队列，其他线程可以从中窃取
+    locals: Vec<Mutex<VecDeque<Arc<Task>>>>,
+    /// 所有队列中的任务总数，用来判断是否可以挂起
+    queued: AtomicUsize,
+    /// 挂起中的工作线程数
+    idle: AtomicUsize,
+    /// 挂起和唤醒用的锁和条件变量
+    sleep_lock: Mutex<()>,
+    sleep_condvar: Condvar,
+    shutdown: AtomicBool,
+}
+
+impl ThreadPool {
+    /// 创建一个有 workers 个工作线程的线程池
+    pub fn new(workers: usize) -> Self {
+        assert!(workers > 0, "thread pool needs at least one worker");
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            locals: (0..workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+            queued: AtomicUsize::new(0),
+            idle: AtomicUsize::new(0),
+            sleep_lock: Mutex::new(()),
+            sleep_condvar: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let workers = (0..workers)
+            .map(|index| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("pool-worker-{index}"))
+                    .spawn(move || shared.run_worker(index))
+                    .expect("无法启动工作线程")
+            })
+            .collect();
+        ThreadPool { shared, workers }
+    }
+
+    /// 生成一个任务
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.shared.spawn(future)
+    }
+
+    pub fn spawner(&self) -> PoolSpawner {
+        PoolSpawner { shared: self.shared.clone() }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.workers.len()
+    }
+}
+
+impl PoolSpawner {
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.shared.spawn(future)
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        {
+            let _guard = self.shared.sleep_lock.lock().unwrap();
+            self.shared.sleep_condvar.notify_all();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        // 队列里剩下的任务持有 Shared 的引用，需要手动清空来打破引用环；
+        // 任务被丢弃后，对应的 JoinHandle 会得到“已取消”。schedule 在同一把锁下检查 shutdown，
+        // 清空之后不会再有任务入队
+        let mut remaining: Vec<Arc<Task>> = self.shared.injector.lock().unwrap().drain(..).collect();
+        for local in &self.shared.locals {
+            remaining.extend(local.lock().unwrap().drain(..));
+        }
+        for task in remaining {
+            task.shutdown();
+        }
+    }
+}
+
+impl Shared {
+    fn spawn<F>(self: &Arc<Self>, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id = TaskId::next();
+        let (future, handle) = joinable(future, id);
+        Task::spawn(future, self.clone(), id, None);
+        handle
+    }
+
+    fn run_worker(self: Arc<Self>, index: usize) {
+        WORKER.with(|worker| *worker.borrow_mut() = Some((self.clone(), index)));
+        while !self.shutdown.load(Ordering::Acquire) {
+            match self.find_task(index) {
+                Some(task) => {
+                    self.queued.fetch_sub(1, Ordering::SeqCst);
+                    // panic 已经在任务内部被捕获并交给了 JoinHandle，工作线程不受影响
+                    let _ = task.run();
+                }
+                None => self.park(),
+            }
+        }
+        WORKER.with(|worker| worker.borrow_mut().take());
+    }
+
+    /// 依次从本地队列、全局队列和其他线程的本地队列中寻找任务
+    fn find_task(&self, index: usize) -> Option<Arc<Task>> {
+        if let Some(task) = self.locals[index].lock().unwrap().pop_front() {
+            return Some(task);
+        }
+
+        // 从全局队列领取一批，平分给所有线程，余下的放进自己的本地队列
+        {
+            let mut injector = self.injector.lock().unwrap();
+            if let Some(task) = injector.pop_front() {
+                let batch = injector.len() / self.locals.len();
+                if batch > 0 {
+                    self.locals[index].lock().unwrap().extend(injector.drain(..batch));
+                }
+                return Some(task);
+            }
+        }
+
+        // 从其他线程的本地队列末尾窃取一半
+        let count = self.locals.len();
+        for offset in 1..count {
+            let victim = (index + offset) % count;
+            let mut stolen = {
+                let mut queue = self.locals[victim].lock().unwrap();
+                let half = queue.len().div_ceil(2);
+                let at = queue.len() - half;
+                queue.split_off(at)
+            };
+            if let Some(task) = stolen.pop_front() {
+                if !stolen.is_empty() {
+                    self.locals[index].lock().unwrap().extend(stolen);
+                }
+                return Some(task);
+            }
+        }
+        None
+    }
+
+    /// 没有任务可做时挂起，直到有新任务入队或者线程池关闭
+    fn park(&self) {
+        let mut guard = self.sleep_lock.lock().unwrap();
+        self.idle.fetch_add(1, Ordering::SeqCst);
+        while self.queued.load(Ordering::SeqCst) == 0 && !self.shutdown.load(Ordering::SeqCst) {
+            guard = self.sleep_condvar.wait(guard).unwrap();
+        }
+        self.idle.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Schedule for Shared {
+    /// 把任务放入队列：在本线程池的工作线程上放入本地队列，否则放入全局队列
+    fn schedule(&self, task: Arc<Task>) {
+        let local = WORKER.with(|worker| match &*worker.borrow() {
+            Some((shared, index)) if std::ptr::eq(Arc::as_ptr(shared), self) => Some(*index),
+            _ => None,
+        });
+        let mut queue = match local {
+            Some(index) => self.locals[index].lock().unwrap(),
+            None => self.injector.lock().unwrap(),
+        };
+        // 持有队列的锁检查 shutdown：Drop 设置 shutdown 之后才在同一把锁下清空队列，
+        // 这里要么赶在清空之前入队，要么看到 shutdown 直接丢弃任务，任务不会留在队列里无人处理
+        if self.shutdown.load(Ordering::Acquire) {
+            drop(queue);
+            task.shutdown();
+            return;
+        }
+        // 先计数再入队，工作线程取出任务后减少计数时不会减到 0 以下
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        queue.push_back(task);
+        drop(queue);
+
+        // 有挂起的线程才需要唤醒；与 park 中先增加 idle 再检查 queued 的顺序配合，不会丢失唤醒
+        if self.idle.load(Ordering::SeqCst) > 0 {
+            let _guard = self.sleep_lock.lock().unwrap();
+            self.sleep_condvar.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use futures::future::join_all;
+
+    use crate::executor::block_on;
+    use crate::thread_pool::ThreadPool;
+    use crate::{yield_now, TimerFuture};
+
+    #[test]
+    fn test_all_tasks_complete() {
+        let pool = ThreadPool::new(4);
+        let counter = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..10_000u64)
+            .map(|i| {
+                let counter = counter.clone();
+                pool.spawn(async move {
+                    for _ in 0..3 {
+                        yield_now().await;
+                    }
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    i * 2
+                })
+            })
+            .collect();
+
+        let results = block_on(join_all(handles));
+        assert_eq!(10_000, counter.load(Ordering::Relaxed));
+        let sum: u64 = results.into_iter().map(|r| r.unwrap()).sum();
+        assert_eq!((0..10_000u64).map(|i| i * 2).sum::<u64>(), sum);
+    }
+
+    #[test]
+    fn test_work_is_spread_across_workers() {
+        let pool = ThreadPool::new(4);
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let handles: Vec<_> = (0..64)
+            .map(|_| {
+                let threads = threads.clone();
+                pool.spawn(async move {
+                    // 阻塞一小会，让其他工作线程有机会窃取
+                    thread::sleep(Duration::from_millis(2));
+                    threads.lock().unwrap().insert(thread::current().id());
+                })
+            })
+            .collect();
+        block_on(join_all(handles));
+        assert!(threads.lock().unwrap().len() > 1);
+    }
+
+    #[test]
+    fn test_spawn_from_task_and_timers() {
+        let pool = ThreadPool::new(2);
+        let spawner = pool.spawner();
+        let handle = pool.spawn(async move {
+            let children: Vec<_> = (0..100u64)
+                .map(|i| {
+                    spawner.spawn(async move {
+                        TimerFuture::new(Duration::from_millis(i % 10)).await;
+                        i
+                    })
+                })
+                .collect();
+            join_all(children).await.into_iter().map(|r| r.unwrap()).sum::<u64>()
+        });
+        assert_eq!(4950, block_on(handle).unwrap());
+    }
+
+    #[test]
+    fn test_drop_cancels_pending_tasks() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.spawn(futures::future::pending::<()>());
+        let timer = pool.spawn(TimerFuture::new(Duration::from_secs(60)));
+        drop(pool);
+        assert!(block_on(handle).unwrap_err().is_cancelled());
+        // 定时器仍持有任务的 waker，任务在定时器被 drop 时一并释放
+        drop(timer);
+    }
+
+    #[test]
+    fn test_spawn_racing_with_drop() {
+        for _ in 0..50 {
+            let pool = ThreadPool::new(2);
+            let spawner = pool.spawner();
+            let spawning = thread::spawn(move || {
+                (0..200).map(|_| spawner.spawn(futures::future::pending::<()>())).collect::<Vec<_>>()
+            });
+            drop(pool);
+            // 和关闭同时入队的任务要么在清空队列时被取消，要么在入队时被拒绝，不会留在队列里
+            for handle in spawning.join().unwrap() {
+                assert!(block_on(handle).unwrap_err().is_cancelled());
+            }
+        }
+    }
+
+    #[test]
+    fn test_workers_survive_panics() {
+        let pool = ThreadPool::new(2);
+        let panicking: Vec<_> = (0..4).map(|_| pool.spawn(async { panic!("worker task panicked") })).collect();
+        let handles: Vec<_> = (0..100).map(|i| pool.spawn(async move { i })).collect();
+        for handle in panicking {
+            assert!(block_on(handle).unwrap_err().is_panic());
+        }
+        let results: Vec<_> = block_on(join_all(handles)).into_iter().map(Result::unwrap).collect();
+        assert_eq!((0..100).collect::<Vec<_>>(), results);
+    }
+}
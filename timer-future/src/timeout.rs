@@ -0,0 +1,124 @@
+//! 超时控制
+//!
+//! [`timeout`] 给任意 Future 加上时间限制：内部 Future 先完成则返回它的结果，定时器先到期则返回 [`Elapsed`]。
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::TimerFuture;
+
+/// 超时错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl Error for Elapsed {}
+
+/// 带超时的 Future，由 [`timeout`] 和 [`timeout_at`] 创建
+pub struct Timeout<F> {
+    future: F,
+    timer: TimerFuture,
+}
+
+/// 如果 future 在 duration 内没有完成，则返回 `Err(Elapsed)`，并丢弃 future
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        timer: TimerFuture::new(duration),
+    }
+}
+
+/// 如果 future 在 deadline 之前没有完成，则返回 `Err(Elapsed)`
+pub fn timeout_at<F: Future>(deadline: Instant, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        timer: TimerFuture::sleep_until(deadline),
+    }
+}
+
+impl<F> Timeout<F> {
+    pub fn get_ref(&self) -> &F {
+        &self.future
+    }
+
+    pub fn into_inner(self) -> F {
+        self.future
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: future 字段是结构性固定（structural pinning）的，Timeout 被 Pin 住之后不会再移动它；
+        // timer 是 Unpin 的，可以直接取可变引用
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        // 先检查内部 Future，同时就绪时优先返回结果
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut this.timer).poll(cx).map(|_| Err(Elapsed(())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use futures::executor::block_on;
+    use futures::future::pending;
+    use futures::FutureExt;
+
+    use crate::clock::MockClock;
+    use crate::timeout::{timeout, timeout_at};
+    use crate::TimerFuture;
+
+    #[test]
+    fn test_timeout() {
+        let clock = MockClock::new();
+        let _guard = clock.enter();
+
+        let mut inner_done = Box::pin(timeout(Duration::from_secs(20), async {
+            TimerFuture::new(Duration::from_secs(10)).await;
+            5
+        }));
+        let mut elapsed = timeout(Duration::from_secs(5), pending::<()>());
+        assert_eq!(None, inner_done.as_mut().now_or_never());
+        assert_eq!(None, (&mut elapsed).now_or_never());
+
+        clock.advance(Duration::from_secs(5));
+        let err = (&mut elapsed).now_or_never().unwrap().unwrap_err();
+        assert_eq!("deadline has elapsed", err.to_string());
+        assert_eq!(None, inner_done.as_mut().now_or_never());
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(Some(Ok(5)), inner_done.now_or_never());
+    }
+
+    #[test]
+    fn test_timeout_real_clock() {
+        let start = Instant::now();
+        let result = block_on(timeout(Duration::from_millis(30), pending::<()>()));
+        assert!(result.is_err());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_timeout_at_past_deadline() {
+        // 截止时间已过，但内部 Future 已经就绪时仍然返回结果
+        let deadline = Instant::now();
+        assert_eq!(Ok(1), block_on(timeout_at(deadline, async { 1 })));
+        assert!(block_on(timeout_at(deadline, pending::<()>())).is_err());
+    }
+}
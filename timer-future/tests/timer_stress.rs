@@ -0,0 +1,42 @@
+//! 大量定时器的压力测试
+//!
+//! 单独放在一个测试二进制里，避免其他测试创建的线程影响线程数的统计。
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use futures::executor::block_on;
+use futures::future::join_all;
+
+use timer_future::TimerFuture;
+
+const TIMERS: u64 = 100_000;
+
+/// 读取当前进程的线程数
+fn thread_count() -> usize {
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Threads:"))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap()
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_many_timers_share_one_thread() {
+    // 先用一个定时器把驱动线程启动起来
+    block_on(TimerFuture::new(Duration::from_millis(1)));
+    let before = thread_count();
+
+    let start = Instant::now();
+    // 截止时间分散在 50ms ~ 550ms 之间
+    let timers: Vec<TimerFuture> = (0..TIMERS)
+        .map(|i| TimerFuture::new(Duration::from_millis(50 + i % 500)))
+        .collect();
+    assert_eq!(before, thread_count());
+
+    block_on(join_all(timers));
+    assert!(start.elapsed() >= Duration::from_millis(549));
+    assert_eq!(before, thread_count());
+}